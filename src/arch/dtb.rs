@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+
+use alloc::boxed::Box;
+
+use fdt::node::FdtNode;
+use fdt::Fdt;
+use lazy_static::lazy_static;
+
+use crate::arch::{IntId, BOOT_ARGS};
+use crate::mm::PhyAddr;
+use crate::pr_notice;
+
+//the device tree the loader passed in x0. Until init copies it to the heap it is read
+//where the loader put it, through the boot page table; the heap may cover that memory
+lazy_static! {
+    static ref DTB: Fdt<'static> = {
+        let boot = boot_dtb();
+        let blob = unsafe { core::slice::from_raw_parts(boot_address() as *const u8, boot.total_size()) };
+        let dtb = Fdt::new(Box::leak(Box::from(blob))).expect("device tree");
+        if let Some(platform) = dtb.find_node("/platform-bus") {
+            pr_notice!(
+                "Model: {}, Platform: {}\n",
+                dtb.root().model(),
+                platform.compatible().unwrap().first()
+            )
+        }
+        dtb
+    };
+}
+
+fn boot_address() -> usize {
+    BOOT_ARGS[0].into_vaddr().as_usize()
+}
+
+//before the heap is up, nodes are found in the loader's copy
+pub fn boot_dtb() -> Fdt<'static> {
+    unsafe { Fdt::from_ptr(boot_address() as *const u8) }.expect("device tree")
+}
+
+pub fn dtb() -> &'static Fdt<'static> {
+    &DTB
+}
+
+//right after the heap is initialized, before anything is allocated over the tree
+pub fn init() {
+    lazy_static::initialize(&DTB);
+}
+
+//a node by compatible string, by path for trees without a matching one
+pub fn find_device(compatible: &[&str], path: &str) -> Option<FdtNode<'static, 'static>> {
+    dtb().find_compatible(compatible).or_else(|| dtb().find_node(path))
+}
+
+//the index-th reg entry, physical address and size
+pub fn reg(node: &FdtNode, index: usize) -> Option<(PhyAddr, usize)> {
+    let reg = node.reg()?.nth(index)?;
+    Some((PhyAddr::new(reg.starting_address as usize), reg.size.unwrap_or(0)))
+}
+
+//a GIC interrupt specifier: type (0 SPI, 1 PPI), number and flags
+pub fn gic_irq(specifier: [u32; 3]) -> Option<IntId> {
+    match specifier {
+        [0, spi, _] => Some(IntId::spi(spi)),
+        [1, ppi, _] => Some(IntId::ppi(ppi)),
+        _ => None,
+    }
+}
+
+//the index-th entry of interrupts, 3 cells each for the GIC
+pub fn interrupt(node: &FdtNode, index: usize) -> Option<IntId> {
+    let value = node.property("interrupts")?.value;
+    let cells = value.get(index * 12..index * 12 + 12)?;
+    let cell = |n: usize| u32::from_be_bytes([cells[n * 4], cells[n * 4 + 1], cells[n * 4 + 2], cells[n * 4 + 3]]);
+    gic_irq([cell(0), cell(1), cell(2)])
+}
+
+//the end of the memory region holding pa
+pub fn memory_end(pa: PhyAddr) -> Option<PhyAddr> {
+    let pa = pa.as_usize();
+    boot_dtb().memory().regions().find_map(|region| {
+        let start = region.starting_address as usize;
+        let end = start + region.size?;
+        (start..end).contains(&pa).then_some(PhyAddr::new(end))
+    })
+}
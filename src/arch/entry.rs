@@ -46,8 +46,11 @@ other_core:
 #[naked]
 #[link_section = ".text._entry"]
 pub unsafe extern "C" fn _entry() {
+    //BOOT_ARGS is in .bss behind the embedded init image, further away than the
+    //1MiB adr reaches, adrp + add reach 4GiB
     asm!(r#"
-    adr    x8, {args}
+    adrp   x8, {args}
+    add    x8, x8, :lo12:{args}
     stp    x0, x1, [x8], #16
     stp    x2, x3, [x8]
     mrs    x8, CurrentEL
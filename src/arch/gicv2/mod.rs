@@ -1,6 +1,10 @@
 //! ARM Generic Interrupt Controller v2.
 #![allow(dead_code)]
 
+use alloc::vec;
+use alloc::vec::Vec;
+
+use arrayvec::ArrayVec;
 use lazy_static::lazy_static;
 use tock_registers::interfaces::{Readable, Writeable};
 
@@ -8,28 +12,44 @@ use reg::{GICC, GICD};
 pub use types::{IntId, SgiData, Trigger};
 
 use crate::common::sync::Mutex;
-use crate::config::{GICC_BASE, GICD_BASE};
-use crate::mm::VirtAddr;
+use crate::common::sync::rcu::{rcu_read_lock, RcuCell};
+use crate::arch::dtb;
+use crate::mm::ioremap;
 
 mod reg;
 mod types;
 
-const NUM_IRQ: usize = 128;
+//32 private ones and the 256 SPIs of the QEMU virt machine, GICv2m MSIs included
+const NUM_IRQ: usize = 288;
 
 pub type HandlerFn = fn(IntId) -> i32;
 
+//returned by a handler of a shared line whose device did not interrupt
+pub const IRQ_NONE: i32 = -1;
+//devices on one line, e.g. PCI INTx
+const MAX_SHARED: usize = 4;
+
 pub struct GICv2 {
     gicd_base: usize,
     gicc_base: usize,
-    handlers: [Option<HandlerFn>; NUM_IRQ],
 }
 
+type Handlers = ArrayVec<HandlerFn, MAX_SHARED>;
+type HandlerTable = Vec<Handlers>;
+
+//read by platform_irq without GIC_V2, replaced under it
+static HANDLERS: RcuCell<HandlerTable> = RcuCell::empty();
+
 lazy_static! {
+    //distributor and cpu interface are the first two reg entries of the intc node
     pub static ref GIC_V2: Mutex<GICv2> = {
-        let gic = GICv2::form_addr(
-            VirtAddr::from_phy(GICD_BASE).as_usize(),
-            VirtAddr::from_phy(GICC_BASE).as_usize(),
-        );
+        let node = dtb::find_device(&["arm,cortex-a15-gic", "arm,gic-400", "arm,cortex-a9-gic"], "/intc")
+            .expect("no GICv2 in the device tree");
+        let [gicd, gicc] = [0, 1].map(|index| {
+            let (pa, size) = dtb::reg(&node, index).expect("GICv2 reg");
+            ioremap(pa, size).expect("GICv2 mapping").as_usize()
+        });
+        let gic = GICv2::form_addr(gicd, gicc);
         gic.init();
         Mutex::new(gic)
     };
@@ -38,17 +58,36 @@ lazy_static! {
 unsafe impl Sync for GICv2 {}
 
 impl GICv2 {
-    const HANDLER_NONE: Option<HandlerFn> = None;
-
     pub const fn form_addr(gicd_base: usize, gicc_base: usize) -> GICv2 {
-        Self {
-            gicd_base,
-            gicc_base,
-            handlers: [Self::HANDLER_NONE; NUM_IRQ],
-        }
+        Self { gicd_base, gicc_base }
+    }
+    //copy, update and publish the handler table
+    fn update_handlers<R>(&mut self, irq: IntId, f: impl FnOnce(&mut Handlers) -> R) -> R {
+        let mut handlers = match HANDLERS.read(&rcu_read_lock()) {
+            None => vec![Handlers::new(); NUM_IRQ],
+            Some(handlers) => handlers.clone(),
+        };
+        let ret = f(&mut handlers[irq.0 as usize]);
+        HANDLERS.assign(handlers);
+        ret
     }
+    //the only handler of irq
     pub fn set_handler(&mut self, irq: IntId, handler: HandlerFn) {
-        self.handlers[irq.0 as usize].replace(handler);
+        self.update_handlers(irq, |handlers| {
+            handlers.clear();
+            handlers.push(handler);
+        })
+    }
+    //chain handler after the ones already on irq, false when the chain is full
+    pub fn add_handler(&mut self, irq: IntId, handler: HandlerFn) -> bool {
+        self.update_handlers(irq, |handlers| handlers.try_push(handler).is_ok())
+    }
+    //take handler off irq, the number of handlers left
+    pub fn remove_handler(&mut self, irq: IntId, handler: HandlerFn) -> usize {
+        self.update_handlers(irq, |handlers| {
+            handlers.retain(|other| *other as usize != handler as usize);
+            handlers.len()
+        })
     }
     fn gicc(&self) -> &'static mut GICC {
         unsafe { &mut *(self.gicc_base as *mut GICC) }
@@ -137,10 +176,12 @@ impl GICv2 {
         if sgi != 0 {
             return Some(IntId::sgi(((sgi >> 24) & 0xff) as u32));
         }
-        for i in 0..64 {
-            match self.probe_pending(IntId { 0: i }) {
-                true => return Some(IntId { 0: i }),
-                false => {}
+        //the lowest pending one that is enabled, 32 at a time
+        let gicd = self.gicd();
+        for word in 0..NUM_IRQ / 32 {
+            let pending = gicd.ispendr[word].get() & gicd.isenabler[word].get();
+            if pending != 0 {
+                return Some(IntId((word * 32) as u32 + pending.trailing_zeros()));
             }
         }
         None
@@ -186,10 +227,41 @@ pub fn setup_irq(irq: IntId, mode: Trigger, handler: HandlerFn) {
     }
 }
 
-pub fn fetch_handler(irq: IntId) -> Option<HandlerFn> {
-    match GIC_V2.lock() {
-        gic => gic.handlers[irq.0 as usize],
+//for a line shared with other devices, the first handler sets the trigger mode and
+//enables it. Every handler of the line runs on an interrupt
+pub fn request_shared_irq(irq: IntId, mode: Trigger, handler: HandlerFn) -> bool {
+    let mut gic = GIC_V2.lock();
+    let first = HANDLERS
+        .read(&rcu_read_lock())
+        .map_or(true, |handlers| handlers[irq.0 as usize].is_empty());
+    if !gic.add_handler(irq, handler) {
+        return false;
     }
+    if first {
+        gic.setup_irq(irq, mode);
+    }
+    true
+}
+
+//the line is disabled with its last handler
+pub fn free_irq(irq: IntId, handler: HandlerFn) {
+    let mut gic = GIC_V2.lock();
+    if gic.remove_handler(irq, handler) == 0 {
+        gic.disable(irq);
+    }
+}
+
+//run the handlers of irq, None when it has none. The result of the one that handled
+//it, IRQ_NONE when none of them did
+pub fn handle_irq(irq: IntId) -> Option<i32> {
+    let handlers = HANDLERS
+        .read(&rcu_read_lock())
+        .and_then(|handlers| handlers.get(irq.0 as usize).cloned())
+        .filter(|handlers| !handlers.is_empty())?;
+    Some(handlers.iter().fold(IRQ_NONE, |ret, handler| match handler(irq) {
+        IRQ_NONE => ret,
+        handled => handled,
+    }))
 }
 
 pub fn enable_irq(irq: IntId, is_enable: bool) {
@@ -62,6 +62,7 @@ pub fn init_mmu() {
     reg_write_p!(TTBR1_EL1, root_addr);
 
     // enable icache, dcache, sp check
+    // SPAN stays clear, PSTATE.PAN is set on every exception taken to EL1
     SCTLR_EL1::write(
         SCTLR_EL1::M
             | SCTLR_EL1::C
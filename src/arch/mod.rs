@@ -1,12 +1,18 @@
 #[allow(unused_imports)]
-pub use gicv2::{ack_irq, fetch_handler, fetch_irq, IntId, setup_irq, Trigger};
+pub use gicv2::{
+    ack_irq, enable_irq, fetch_irq, free_irq, handle_irq, request_shared_irq, setup_irq, HandlerFn, IntId,
+    Trigger, IRQ_NONE,
+};
 
 use crate::arch::timer::setup_timer;
+pub use crate::arch::timer::time_ms;
 use crate::mm::PhyAddr;
-use crate::reg_write_p;
+use crate::{pr_notice, pr_warn, reg_write_p};
 
 mod gicv2;
 
+pub mod dtb;
+
 pub mod entry;
 pub mod reg;
 pub mod macros;
@@ -23,5 +29,13 @@ pub static BOOT_ARGS: [PhyAddr; 4] = [PhyAddr::new(0); 4];
 pub fn init() {
     extern "C" { pub fn exception_base(); }
     reg_write_p!(VBAR_EL1, exception_base as usize);
+    //kernel never touches user memory outside of the user copy routines
+    match reg::pan_supported() {
+        true => {
+            reg::set_pan(true);
+            pr_notice!("PAN: enabled\n");
+        }
+        false => pr_warn!("PAN: not supported by cpu\n"),
+    }
     setup_timer();
 }
@@ -41,6 +41,26 @@ pub fn set_thread_pointer(tp: usize) {
     reg_write_p!(TPIDR_EL1, tp)
 }
 
+//TLS pointer of the user thread
+#[inline(always)]
+pub fn user_thread_pointer() -> usize {
+    reg_read_p!(TPIDR_EL0)
+}
+
+//FEAT_PAN, ID_AA64MMFR1_EL1.PAN
+#[inline(always)]
+pub fn pan_supported() -> bool {
+    (reg_read_p!(ID_AA64MMFR1_EL1) >> 20) & 0xf != 0
+}
+
+//PSTATE.PAN, the PAN register is S3_0_C4_C2_3
+#[inline(always)]
+pub fn set_pan(enable: bool) {
+    if pan_supported() {
+        reg_write_p!(S3_0_C4_C2_3, (enable as usize) << 22)
+    }
+}
+
 #[allow(dead_code)]
 #[inline(always)]
 pub fn nop() {
@@ -26,6 +26,11 @@ pub mod SCTLR_EL1 {
     pub const I: usize = 1 << 12;
     //Controls enabling of pointer authentication  of instruction addresses in the EL1&0 translation regime.
     pub const EN_DB: usize = 1 << 13;
+    //Write permission implies XN (Execute-never) for the EL1&0 translation regime.
+    pub const WXN: usize = 1 << 19;
+    //Set Privileged Access Never, on taking an exception to EL1.
+    //0: PSTATE.PAN is set to 1 on taking an exception to EL1.
+    pub const SPAN: usize = 1 << 23;
 
     def_reg_fn!(usize, SCTLR_EL1);
 }
\ No newline at end of file
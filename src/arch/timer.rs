@@ -1,9 +1,8 @@
 use lazy_static::lazy_static;
 
 use crate::{reg_read_p, reg_update_p, reg_write_p};
-use crate::arch::{IntId, setup_irq, Trigger};
+use crate::arch::{dtb, IntId, setup_irq, Trigger};
 use crate::common::sync::RwLock;
-use crate::config::TIMER_IRQ;
 
 lazy_static! {
     static ref TIMER: RwLock<Timer> = RwLock::new(Timer::new());
@@ -13,6 +12,7 @@ lazy_static! {
 pub struct Timer {
     clock_freq: u64,
     ms_ticks: u64,
+    irq: IntId,
 }
 
 impl Timer {
@@ -20,12 +20,17 @@ impl Timer {
         Self {
             clock_freq: 0,
             ms_ticks: 0,
+            irq: IntId::new_empty(),
         }
     }
     pub fn init(&mut self) {
         self.clock_freq = reg_read_p!(CNTFRQ_EL0) as u64;
         self.ms_ticks = self.clock_freq / 1000;
-        setup_irq(IntId::ppi(TIMER_IRQ), Trigger::Edge, timer_irq_handler);
+        //the second interrupt of the timer node is the non-secure physical timer
+        self.irq = dtb::find_device(&["arm,armv8-timer", "arm,armv7-timer"], "/timer")
+            .and_then(|node| dtb::interrupt(&node, 1))
+            .expect("no timer interrupt in the device tree");
+        setup_irq(self.irq, Trigger::Edge, timer_irq_handler);
         reg_update_p!(CNTP_CTL_EL0, 1);
     }
     #[allow(dead_code)]
@@ -43,9 +48,14 @@ fn timer_irq_handler(_irq: IntId) -> i32 {
     0
 }
 
+//milliseconds since boot, the tick that notices an expired timeout is 100ms
+pub fn time_ms() -> u64 {
+    TIMER.read().get_time_ms()
+}
+
 pub fn setup_timer() {
     match TIMER.write() {
         mut lock => lock.init(),
     };
-    timer_irq_handler(IntId::ppi(TIMER_IRQ));
+    timer_irq_handler(TIMER.read().irq);
 }
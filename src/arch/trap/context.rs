@@ -4,6 +4,8 @@ use core::fmt::{Display, Formatter};
 use crate::{pr_err, reg_read_a};
 use crate::common::symbol::find_symbol;
 
+const STACKTRACE_DEPTH: usize = 32;
+
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct Context {
@@ -44,6 +46,10 @@ impl Display for Context {
 }
 
 impl Context {
+    //EL0t, D A F masked and IRQ unmasked
+    const SPSR_USER: usize = (1 << 9) | (1 << 8) | (0 << 7) | (1 << 6) | 0b0000;
+    const SPSR_NZCV: usize = 0xF << 28;
+
     pub fn new_user(entry: usize, stack_top: usize) -> Self {
         Self {
             reg: [0; 29],
@@ -51,9 +57,18 @@ impl Context {
             lr: 0,
             usp: stack_top,
             elr: entry,
-            spsr: (1 << 9) | (1 << 8) | (0 << 7) | (1 << 6) | 0b0000,
+            spsr: Self::SPSR_USER,
         }
     }
+    //the exception was taken from EL0
+    pub fn is_user(&self) -> bool {
+        self.spsr & 0xF == 0
+    }
+    //a context read back from user memory keeps only its condition flags,
+    //it must not return to EL1 or with interrupts masked
+    pub fn sanitize_spsr(&mut self) {
+        self.spsr = (self.spsr & Self::SPSR_NZCV) | Self::SPSR_USER
+    }
     pub unsafe fn exec(&self, stack_top: usize) -> ! {
         asm!("
             mov     sp, x1
@@ -85,16 +100,33 @@ impl Context {
         options(noreturn),
         )
     }
-    pub fn stacktrace(&self) {
-        let (mut frame_pc, mut frame_fp);
-        if self.fp == 0 {
-            return;
-        } else {
-            frame_fp = self.fp;
-            frame_pc = self.elr;
+    //the caller's frames, for a trace outside of an exception
+    #[inline(always)]
+    #[allow(dead_code)]
+    pub fn current() -> Self {
+        let (fp, pc): (usize, usize);
+        unsafe { asm!("mov {}, x29", "adr {}, .", out(reg) fp, out(reg) pc) };
+        Self { reg: [0; 29], fp, lr: 0, usp: 0, elr: pc, spsr: 0 }
+    }
+    //return addresses along the frame pointer chain, elr first
+    pub fn backtrace(&self, frames: &mut [usize]) -> usize {
+        let (mut frame_pc, mut frame_fp) = (self.elr, self.fp);
+        let mut n = 0;
+        while frame_fp != 0 && n < frames.len() {
+            frames[n] = frame_pc;
+            n += 1;
+            /* Stack frame pointer should be 16 bytes aligned */
+            if frame_fp & 0xF != 0 {
+                break;
+            }
+            frame_pc = reg_read_a!(frame_fp + 8, usize);
+            frame_fp = reg_read_a!(frame_fp, usize);
         }
+        n
+    }
+    pub fn print_frames(frames: &[usize]) {
         pr_err!("stack trace:\n");
-        while frame_fp != 0 {
+        for frame_pc in frames.iter().copied() {
             pr_err!("\t#{:#018x}", frame_pc);
             match find_symbol(frame_pc) {
                 None => {}
@@ -104,12 +136,13 @@ impl Context {
                 },
             }
             pr_err!("\n");
-            /* Stack frame pointer should be 16 bytes aligned */
-            if frame_fp & 0xF != 0 {
-                break;
-            }
-            frame_pc = reg_read_a!(frame_fp + 8, usize);
-            frame_fp = reg_read_a!(frame_fp, usize);
+        }
+    }
+    pub fn stacktrace(&self) {
+        let mut frames = [0; STACKTRACE_DEPTH];
+        let n = self.backtrace(&mut frames);
+        if n > 0 {
+            Self::print_frames(&frames[..n]);
         }
     }
 }
@@ -1,4 +1,5 @@
 mod trap;
+pub use trap::in_interrupt;
 pub mod context;
 pub mod types;
 pub mod syscall;
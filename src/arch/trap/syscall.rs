@@ -1,29 +1,140 @@
-use alloc::string::String;
 use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
 
-use crate::{pr_err, print};
+use crate::pr_err;
 use crate::arch::psci::{psci_cpu_off, psci_cpu_rest};
-use crate::devices::gets;
+use crate::common::errno::{Errno, SysResult};
+use crate::arch::trap::context::Context;
 use crate::mm::{UserBuffer, UserPtr};
-use crate::task::scheduler;
+use crate::mm::{PTEFlags, shm, swap};
+use crate::net::socket;
+use crate::task::mem::{MapKind, UserSpace};
+use crate::task::{futex, pipe, scheduler, signal};
+use crate::task::futex::{FUTEX_CMD_MASK, FUTEX_PRIVATE_FLAG, FUTEX_WAIT, FUTEX_WAKE};
+use crate::task::task::{CLONE_CHILD_CLEARTID, CLONE_CHILD_SETTID, CLONE_FILES, CLONE_FS, CLONE_PARENT_SETTID};
+use crate::task::task::{CLONE_SETTLS, CLONE_SIGHAND, CLONE_SYSVSEM, CLONE_THREAD, CLONE_VM, CSIGNAL};
+use crate::task::types::TaskId;
+use crate::task::wait::CHILD_EXIT;
 
 const SYSCALL_SHUTDOWN: usize = 142;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+//exit ends the calling thread, exit_group the whole process
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_EXIT_GROUP: usize = 94;
+const SYSCALL_SET_TID_ADDRESS: usize = 96;
+const SYSCALL_FUTEX: usize = 98;
+const SYSCALL_SCHED_YIELD: usize = 124;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETPPID: usize = 173;
+const SYSCALL_GETTID: usize = 178;
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
+//swap partitions are named by their MBR index instead of a device path
+const SYSCALL_SWAPON: usize = 224;
+const SYSCALL_SWAPOFF: usize = 225;
+const SYSCALL_SHMGET: usize = 194;
+const SYSCALL_SHMCTL: usize = 195;
+const SYSCALL_SHMAT: usize = 196;
+const SYSCALL_SHMDT: usize = 197;
+const SYSCALL_MUNMAP: usize = 215;
+const SYSCALL_CLONE: usize = 220;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP3: usize = 24;
+const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_PIPE2: usize = 59;
+const SYSCALL_WAIT4: usize = 260;
+const SYSCALL_IOCTL: usize = 29;
+const SYSCALL_KILL: usize = 129;
+const SYSCALL_RT_SIGACTION: usize = 134;
+const SYSCALL_RT_SIGPROCMASK: usize = 135;
+const SYSCALL_RT_SIGRETURN: usize = 139;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_SOCKET: usize = 198;
+const SYSCALL_BIND: usize = 200;
+const SYSCALL_LISTEN: usize = 201;
+const SYSCALL_ACCEPT: usize = 202;
+const SYSCALL_CONNECT: usize = 203;
+const SYSCALL_SENDTO: usize = 206;
+const SYSCALL_RECVFROM: usize = 207;
+const SYSCALL_SETSOCKOPT: usize = 208;
+const SYSCALL_GETSOCKOPT: usize = 209;
+//of a socket, 142 above powers the machine off
+const SYSCALL_SOCKET_SHUTDOWN: usize = 210;
+
+const RLIMIT_STACK: usize = 3;
+
+const PROT_READ: usize = 1;
+const PROT_WRITE: usize = 2;
+const PROT_EXEC: usize = 4;
+const MAP_SHARED: usize = 0x01;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_ANONYMOUS: usize = 0x20;
+const SIGCHLD: usize = 17;
+const WNOHANG: usize = 1;
+const O_CLOEXEC: usize = 0o2000000;
+const TIOCGPGRP: usize = 0x540F;
+const TIOCSPGRP: usize = 0x5410;
+//sizeof(sigset_t) of the kernel abi
+const SIGSET_SIZE: usize = 8;
 
 #[no_mangle]
-pub fn syscall(syscall_id: usize, args: [usize; 6]) -> usize {
+pub fn syscall(syscall_id: usize, args: [usize; 6], context: &mut Context) -> usize {
     match syscall_id {
-        SYSCALL_WRITE => sys_write(args[0], UserPtr::<u8>::new(args[1], args[2])),
-        SYSCALL_READ => sys_read(args[0], &mut UserPtr::<u8>::new(args[1], args[2])),
+        SYSCALL_WRITE => Errno::ret(sys_write(args[0], UserPtr::<u8>::new(args[1], args[2]))),
+        SYSCALL_READ => Errno::ret(sys_read(args[0], &mut UserPtr::<u8>::new(args[1], args[2]))),
         SYSCALL_SHUTDOWN =>{
+            #[cfg(feature = "lockstat")]
+            print_lock_stats();
             match args[0] {
                 0 => psci_cpu_off(),
                 _ => psci_cpu_rest()
             }
         },
         SYSCALL_EXIT => scheduler::exit_current(args[0] as isize),
+        SYSCALL_EXIT_GROUP => sys_exit_group(args[0] as isize),
+        SYSCALL_SET_TID_ADDRESS => Errno::ret(sys_set_tid_address(args[0])),
+        SYSCALL_FUTEX => Errno::ret(sys_futex(args[0], args[1], args[2], args[3])),
+        SYSCALL_SCHED_YIELD => Errno::ret(sys_sched_yield()),
+        SYSCALL_GETPID => Errno::ret(sys_getpid()),
+        SYSCALL_GETPPID => Errno::ret(sys_getppid()),
+        SYSCALL_GETTID => Errno::ret(sys_gettid()),
+        SYSCALL_GETRLIMIT => Errno::ret(sys_getrlimit(args[0], &mut UserPtr::<usize>::new(args[1], 2))),
+        SYSCALL_SETRLIMIT => Errno::ret(sys_setrlimit(args[0], UserPtr::<usize>::new(args[1], 2))),
+        SYSCALL_SWAPON => Errno::ret(swap::swapon(args[0])),
+        SYSCALL_SWAPOFF => Errno::ret(swap::swapoff(args[0])),
+        SYSCALL_SHMGET => Errno::ret(shm::shmget(args[0], args[1], args[2])),
+        SYSCALL_SHMCTL => Errno::ret(shm::shmctl(args[0], args[1])),
+        SYSCALL_SHMAT => Errno::ret(sys_shmat(args[0], args[1], args[2])),
+        SYSCALL_SHMDT => Errno::ret(sys_shmdt(args[0])),
+        SYSCALL_MUNMAP => Errno::ret(sys_munmap(args[0], args[1])),
+        SYSCALL_CLONE => Errno::ret(sys_clone(args[0], args[1], args[2], args[3], args[4], context)),
+        SYSCALL_MMAP => Errno::ret(sys_mmap(args[0], args[1], args[2], args[3])),
+        SYSCALL_DUP => Errno::ret(sys_dup(args[0])),
+        SYSCALL_DUP3 => Errno::ret(sys_dup3(args[0], args[1], args[2])),
+        SYSCALL_CLOSE => Errno::ret(sys_close(args[0])),
+        SYSCALL_PIPE2 => Errno::ret(sys_pipe2(&mut UserPtr::<i32>::new(args[0], 2), args[1])),
+        SYSCALL_WAIT4 => Errno::ret(sys_wait4(args[0] as isize, args[1], args[2])),
+        SYSCALL_IOCTL => Errno::ret(sys_ioctl(args[0], args[1], args[2])),
+        SYSCALL_KILL => Errno::ret(signal::kill(args[0] as isize, args[1])),
+        SYSCALL_RT_SIGACTION => Errno::ret(sys_rt_sigaction(args[0], args[1], args[2], args[3])),
+        SYSCALL_RT_SIGPROCMASK => Errno::ret(sys_rt_sigprocmask(args[0], args[1], args[2], args[3])),
+        SYSCALL_RT_SIGRETURN => Errno::ret(signal::sigreturn(context)),
+        SYSCALL_SETPGID => Errno::ret(sys_setpgid(args[0], args[1])),
+        SYSCALL_GETPGID => Errno::ret(sys_getpgid(args[0])),
+        SYSCALL_SOCKET => Errno::ret(socket::socket(args[0], args[1], args[2])),
+        SYSCALL_BIND => Errno::ret(socket::bind(args[0], args[1], args[2])),
+        SYSCALL_LISTEN => Errno::ret(socket::listen(args[0], args[1])),
+        SYSCALL_ACCEPT => Errno::ret(socket::accept(args[0], args[1], args[2])),
+        SYSCALL_CONNECT => Errno::ret(socket::connect(args[0], args[1], args[2])),
+        SYSCALL_SENDTO => Errno::ret(socket::sendto(args[0], args[1], args[2], args[3], args[4], args[5])),
+        SYSCALL_RECVFROM => Errno::ret(socket::recvfrom(args[0], args[1], args[2], args[3], args[4], args[5])),
+        SYSCALL_SETSOCKOPT => Errno::ret(socket::setsockopt(args[0], args[1], args[2], args[3], args[4])),
+        SYSCALL_GETSOCKOPT => Errno::ret(socket::getsockopt(args[0], args[1], args[2], args[3], args[4])),
+        SYSCALL_SOCKET_SHUTDOWN => Errno::ret(socket::shutdown(args[0], args[1])),
         _ => {
             pr_err!("Unsupported syscall_id: {}\n", syscall_id);
             0
@@ -31,27 +142,377 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> usize {
     }
 }
 
-pub fn sys_write(fd: usize, ptr :UserPtr<u8>)-> usize{
-    let ret = ptr.len();
-    match fd {
-        1 => {
-            print!("{}",String::copy_from_user(ptr).unwrap());
+//the locks every cpu goes through
+#[cfg(feature = "lockstat")]
+fn print_lock_stats() {
+    use crate::mm::heap::ALLOCATOR;
+    let scheduler = scheduler::SCHEDULER.stats();
+    let allocator = ALLOCATOR.get().stats();
+    crate::pr_notice!("\nscheduler lock: {}\nallocator lock: {}\n", scheduler, allocator);
+}
+
+pub fn sys_write(fd: usize, ptr :UserPtr<u8>)-> SysResult{
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let file = unsafe { (*(*task).files.lock()).get(fd)? };
+    let buffer = Vec::<u8>::copy_from_user(ptr).ok_or(Errno::ENOMEM)?;
+    file.write(&buffer)
+}
+
+
+pub fn sys_read(fd: usize, ptr: &mut UserPtr<u8>)-> SysResult{
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let file = unsafe { (*(*task).files.lock()).get(fd)? };
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(ptr.len()).map_err(|_| Errno::ENOMEM)?;
+    buffer.resize(ptr.len(), 0);
+    let ret = file.read(&mut buffer)?;
+    buffer.truncate(ret);
+    buffer.copy_to_user(&mut UserPtr::new(ptr.as_ptr().addr(), ret));
+    Ok(ret)
+}
+
+//struct rlimit { rlim_cur, rlim_max }
+pub fn sys_getrlimit(resource: usize, ptr: &mut UserPtr<usize>) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    match resource {
+        RLIMIT_STACK => {
+            let limit = unsafe {
+                let mut vm = (*task).page.lock();
+                if !vm.access_ok(ptr.as_ptr().addr(), 2 * size_of::<usize>(), true) {
+                    return Err(Errno::EFAULT);
+                }
+                vm.stack_limit()
+            };
+            vec![limit, UserSpace::STACK_LIMIT_MAX].copy_to_user(ptr);
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+pub fn sys_setrlimit(resource: usize, ptr: UserPtr<usize>) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    if !unsafe { (*task).page.lock().access_ok(ptr.as_ptr().addr(), 2 * size_of::<usize>(), false) } {
+        return Err(Errno::EFAULT);
+    }
+    let limit = Vec::<usize>::copy_from_user(ptr).ok_or(Errno::ENOMEM)?;
+    match resource {
+        RLIMIT_STACK => match limit[0] <= limit[1] && unsafe { (*task).page.lock().set_stack_limit(limit[0]) } {
+            true => Ok(0),
+            false => Err(Errno::EINVAL),
+        },
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+//anonymous mappings only, the address hint is ignored
+pub fn sys_mmap(_addr: usize, len: usize, prot: usize, flags: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let shared = match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return Err(Errno::EINVAL),
+    };
+    if len == 0 || flags & MAP_ANONYMOUS == 0 || prot & (PROT_READ | PROT_WRITE | PROT_EXEC) == 0 {
+        return Err(Errno::EINVAL);
+    }
+    //W^X, as for ELF segments
+    if prot & PROT_WRITE != 0 && prot & PROT_EXEC != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut pte_flags = PTEFlags::R | PTEFlags::U;
+    if prot & PROT_WRITE != 0 {
+        pte_flags |= PTEFlags::W;
+    }
+    if prot & PROT_EXEC != 0 {
+        pte_flags |= PTEFlags::X;
+    }
+    unsafe { (*task).page.lock().mmap(len, pte_flags, shared) }
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    unsafe { (*task).page.lock().munmap(addr, len) }
+}
+
+//the segment is attached at an address of the kernel's choosing, addr must be 0
+pub fn sys_shmat(id: usize, addr: usize, flags: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    if addr != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let pte_flags = match flags & shm::SHM_RDONLY {
+        0 => PTEFlags::RW | PTEFlags::U,
+        _ => PTEFlags::R | PTEFlags::U,
+    };
+    let frames = shm::attach(id)?;
+    unsafe { (*task).page.lock().map_frames(&frames, pte_flags, MapKind::Shm(id)) }
+}
+
+pub fn sys_shmdt(addr: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    unsafe {
+        let mut vm = (*task).page.lock();
+        let len = vm.shm_len(addr).ok_or(Errno::EINVAL)?;
+        vm.munmap(addr, len)
+    }
+}
+
+//fork, or a thread sharing memory, descriptors and signal actions. Arguments in the
+//aarch64 order: flags, stack, parent_tid, tls, child_tid. Tids are u32 in user memory
+pub fn sys_clone(flags: usize, stack: usize, parent_tid: usize, tls: usize, child_tid: usize, context: &Context) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let supported = CSIGNAL | CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD | CLONE_SYSVSEM
+        | CLONE_SETTLS | CLONE_PARENT_SETTID | CLONE_CHILD_CLEARTID | CLONE_CHILD_SETTID;
+    let exit_signal = flags & CSIGNAL;
+    if flags & !supported != 0 || (exit_signal != 0 && exit_signal != SIGCHLD) {
+        return Err(Errno::EINVAL);
+    }
+    //as in Linux, threads share signal actions and those need a shared address space.
+    //the child tid can only be stored into memory the parent can see
+    if (flags & CLONE_THREAD != 0 && flags & CLONE_SIGHAND == 0)
+        || (flags & CLONE_SIGHAND != 0 && flags & CLONE_VM == 0)
+        || (flags & CLONE_CHILD_SETTID != 0 && flags & CLONE_VM == 0) {
+        return Err(Errno::EINVAL);
+    }
+    let settid = [(CLONE_PARENT_SETTID, parent_tid), (CLONE_CHILD_SETTID, child_tid)];
+    for (flag, addr) in settid {
+        if flags & flag != 0 && !unsafe { (*task).page.lock().access_ok(addr, 4, true) } {
+            return Err(Errno::EFAULT);
+        }
+    }
+    let mut child = unsafe { (*task).clone_task(context, flags, stack, tls)? };
+    let pid = child.pid().as_usize();
+    //copying the memory may have swapped a tid page out and faulting it back in can
+    //still fail, the child never ran and gives back what clone_task took
+    for (flag, addr) in settid {
+        if flags & flag != 0 {
+            if let Err(errno) = put_tid(addr, pid) {
+                child.release();
+                return Err(errno);
+            }
+        }
+    }
+    if flags & CLONE_CHILD_CLEARTID != 0 {
+        child.clear_child_tid = child_tid;
+    }
+    scheduler::add_task(child);
+    Ok(pid as usize)
+}
+
+//u32 tid into the memory of the current task
+fn put_tid(addr: usize, tid: u32) -> SysResult<()> {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    if !unsafe { (*task).page.lock().access_ok(addr, 4, true) } {
+        return Err(Errno::EFAULT);
+    }
+    vec![tid].copy_to_user(&mut UserPtr::<u32>::new(addr, 1));
+    Ok(())
+}
+
+//CLONE_CHILD_CLEARTID for the calling thread, returns its tid
+//futex(uaddr, op, val, timeout), uaddr2 and val3 of the requeue and bitset ops are unused
+pub fn sys_futex(uaddr: usize, op: usize, val: usize, timeout: usize) -> SysResult {
+    let private = op & FUTEX_PRIVATE_FLAG != 0;
+    match op & FUTEX_CMD_MASK {
+        FUTEX_WAIT => futex::wait(uaddr, val as u32, futex::timeout_ms(timeout)?, private),
+        FUTEX_WAKE => futex::wake(uaddr, val.min(i32::MAX as usize), private),
+        _ => Err(Errno::ENOSYS),
+    }
+}
+
+pub fn sys_set_tid_address(addr: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    unsafe {
+        (*task).clear_child_tid = addr;
+        Ok((*task).pid().as_usize() as usize)
+    }
+}
+
+//every other thread of the process gets SIGKILL
+pub fn sys_exit_group(code: isize) -> ! {
+    if let Some(task) = scheduler::current() {
+        signal::kill_other_threads(unsafe { &*task });
+    }
+    scheduler::exit_current(code)
+}
+
+pub fn sys_getpid() -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    Ok(unsafe { (*task).tgid.as_usize() } as usize)
+}
+
+pub fn sys_getppid() -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    Ok(unsafe { (*task).ppid().as_usize() } as usize)
+}
+
+pub fn sys_gettid() -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    Ok(unsafe { (*task).pid().as_usize() } as usize)
+}
+
+pub fn sys_sched_yield() -> SysResult {
+    scheduler::yield_current();
+    Ok(0)
+}
+
+pub fn sys_close(fd: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    unsafe { (*task).files.lock().close(fd) }
+}
+
+pub fn sys_dup(fd: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    unsafe { (*task).files.lock().dup(fd) }
+}
+
+//there is no exec, so O_CLOEXEC is the only flag and it changes nothing
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    if flags & !O_CLOEXEC != 0 {
+        return Err(Errno::EINVAL);
+    }
+    unsafe { (*task).files.lock().dup3(old_fd, new_fd) }
+}
+
+//int pipefd[2], read end first
+pub fn sys_pipe2(fds: &mut UserPtr<i32>, flags: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    if flags & !O_CLOEXEC != 0 {
+        return Err(Errno::EINVAL);
+    }
+    if !unsafe { (*task).page.lock().access_ok(fds.as_ptr().addr(), 2 * size_of::<i32>(), true) } {
+        return Err(Errno::EFAULT);
+    }
+    let (reader, writer) = pipe::pipe()?;
+    let mut files = unsafe { (*task).files.lock() };
+    let read_fd = files.alloc(reader)?;
+    let write_fd = match files.alloc(writer) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            files.close(read_fd)?;
+            return Err(errno);
+        }
+    };
+    vec![read_fd as i32, write_fd as i32].copy_to_user(fds);
+    Ok(0)
+}
+
+//pid -1 waits for any child, wstatus holds the exit code in bits 8..15
+//or the terminating signal in bits 0..7
+pub fn sys_wait4(pid: isize, wstatus: usize, options: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let parent = unsafe { (*task).tgid };
+    let pid = match pid {
+        -1 => None,
+        pid if pid > 0 => Some(TaskId::from(pid as u32)),
+        _ => return Err(Errno::EINVAL),
+    };
+    loop {
+        //checked before a child is reaped, its status would be lost. Again after each
+        //sleep, the page may have been swapped out meanwhile
+        if wstatus != 0 && !unsafe { (*task).page.lock().access_ok(wstatus, size_of::<i32>(), true) } {
+            return Err(Errno::EFAULT);
+        }
+        if let Some((child, status)) = scheduler::reap(parent, pid)? {
+            if wstatus != 0 {
+                vec![status].copy_to_user(&mut UserPtr::<i32>::new(wstatus, 1));
+            }
+            return Ok(child.as_usize() as usize);
+        }
+        if options & WNOHANG != 0 {
+            return Ok(0);
+        }
+        if signal::interrupted() {
+            return Err(Errno::EINTR);
+        }
+        //children only exit while this task is switched out, nothing to hold across
+        CHILD_EXIT.sleep_with(());
+    }
+}
+
+pub fn sys_rt_sigaction(signo: usize, act: usize, old_act: usize, size: usize) -> SysResult {
+    if size != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    signal::sigaction(signo, act, old_act)
+}
+
+pub fn sys_rt_sigprocmask(how: usize, set: usize, old_set: usize, size: usize) -> SysResult {
+    if size != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    signal::sigprocmask(how, set, old_set)
+}
+
+//pid 0 is the caller, pgid 0 makes pid a group leader. Only the caller and its children
+pub fn sys_setpgid(pid: usize, pgid: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let caller = unsafe { (*task).pid() };
+    let pid = match pid {
+        0 => caller,
+        pid => TaskId::from(pid as u32),
+    };
+    let pgid = match pgid {
+        0 => pid,
+        pgid if pgid <= u32::MAX as usize => TaskId::from(pgid as u32),
+        _ => return Err(Errno::EINVAL),
+    };
+    let mut found = false;
+    scheduler::for_each_task(|task| {
+        if task.pid() == pid && (pid == caller || task.ppid() == caller) {
+            task.pgid = pgid;
+            found = true;
         }
-        _ => {}
+    });
+    match found {
+        true => Ok(0),
+        false => Err(Errno::ESRCH),
     }
-    ret
 }
 
+pub fn sys_getpgid(pid: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    if pid == 0 {
+        return Ok(unsafe { (*task).pgid.as_usize() } as usize);
+    }
+    let mut pgid = None;
+    scheduler::for_each_task(|task| {
+        if task.pid() == TaskId::from(pid as u32) {
+            pgid = Some(task.pgid.as_usize() as usize);
+        }
+    });
+    pgid.ok_or(Errno::ESRCH)
+}
 
-pub fn sys_read(fd: usize, ptr: &mut UserPtr<u8>)-> usize{
-    let ret;
-    match fd {
-        0 => {
-            let mut buffer = vec![0; ptr.len()];
-            ret = gets(&mut buffer);
-            buffer.copy_to_user(ptr);
+//foreground process group of the console only
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let file = unsafe { (*(*task).files.lock()).get(fd)? };
+    if !file.is_tty() {
+        return Err(Errno::ENOTTY);
+    }
+    let arg_ok = |write| unsafe { (*task).page.lock().access_ok(arg, size_of::<i32>(), write) };
+    match cmd {
+        TIOCGPGRP => {
+            if !arg_ok(true) {
+                return Err(Errno::EFAULT);
+            }
+            vec![signal::foreground().as_usize() as i32].copy_to_user(&mut UserPtr::<i32>::new(arg, 1));
+            Ok(0)
+        }
+        TIOCSPGRP => {
+            if !arg_ok(false) {
+                return Err(Errno::EFAULT);
+            }
+            let pgid = Vec::<i32>::copy_from_user(UserPtr::<i32>::new(arg, 1)).ok_or(Errno::ENOMEM)?[0];
+            if pgid <= 0 {
+                return Err(Errno::EINVAL);
+            }
+            signal::set_foreground(TaskId::from(pgid as u32));
+            Ok(0)
         }
-        _ => unreachable!()
+        _ => Err(Errno::EINVAL),
     }
-    ret
 }
@@ -1,8 +1,14 @@
 use core::arch::global_asm;
+use core::mem::align_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::arch::reg::DAIF;
 use crate::arch::trap::syscall::syscall;
-use crate::arch::{ack_irq, fetch_handler, fetch_irq};
+use crate::arch::{ack_irq, fetch_irq, handle_irq};
+use crate::mm::{oom, USER_END};
+use crate::task::{scheduler, softirq};
+use crate::task::signal::{self, SIGBUS, SIGILL, SIGSEGV, SIGTRAP};
+use crate::task::task::KERNEL_STACK_SIZE;
 use crate::{get_bit, pr_err, println, reg_read_p};
 
 use super::context::Context;
@@ -10,6 +16,28 @@ use super::types::{SyncException, SyncExceptionType};
 
 global_asm!(include_str!("../macros.S"), include_str!("trap.S"));
 
+//exception stack used to report a kernel stack overflow, aligned like a kernel stack
+#[repr(C, align(0x8000))]
+struct OverflowStack([u8; KERNEL_STACK_SIZE]);
+
+//KSTACK_SHIFT in trap.S
+const _: () = assert!(KERNEL_STACK_SIZE == 1 << 14 && align_of::<OverflowStack>() == 2 * KERNEL_STACK_SIZE);
+
+#[no_mangle]
+static mut OVERFLOW_STACK: OverflowStack = OverflowStack([0; KERNEL_STACK_SIZE]);
+
+#[no_mangle]
+fn stack_overflow(context: &Context) {
+    pr_err!(
+        "kernel stack overflow: PC at {:#018x}, FAR {:#018x}\n",
+        context.elr,
+        reg_read_p!(far_el1)
+    );
+    pr_err!("{}\n", context);
+    context.stacktrace();
+    panic!()
+}
+
 #[no_mangle]
 fn invalid_exception(context: &Context) {
     pr_err!("{}\n", context);
@@ -23,6 +51,9 @@ fn sync_exception(context: &mut Context) {
     let ec = SyncException::new();
     let far = reg_read_p!(far_el1);
     match ec.ec {
+        SyncExceptionType::UnknownReason | SyncExceptionType::IllegalExecutionState if context.is_user() => {
+            return user_signal(context, SIGILL);
+        }
         SyncExceptionType::UnknownReason => {}
         SyncExceptionType::TrappedWFIorWFE => {
             pr_err!("{:?}\n", ec.ec);
@@ -40,22 +71,26 @@ fn sync_exception(context: &mut Context) {
                     context.reg[4],
                     context.reg[5],
                 ],
+                context,
             );
+            signal::do_signal(context);
             DAIF::All.enable();
             return;
         }
         SyncExceptionType::TrappedMSROrMRSAArch64 => {}
         SyncExceptionType::ExceptionPointerAuthentication => {}
         SyncExceptionType::InstructionAbortLowLevel => {
+            if user_fault(far, &ec) {
+                DAIF::All.enable();
+                return;
+            }
             pr_err!(
                 "Instruction Abort LowLevel: PC at {:#018x} iss: {:#x} {}\n",
                 context.elr,
                 ec.iss,
                 ec.fault_msg()
             );
-            pr_err!("{}\n", context);
-            DAIF::All.enable();
-            return;
+            return user_signal(context, SIGSEGV);
         }
         SyncExceptionType::InstructionAbortCurrentLevel => {
             pr_err!(
@@ -65,8 +100,15 @@ fn sync_exception(context: &mut Context) {
                 ec.fault_msg()
             );
         }
+        SyncExceptionType::PCAlignmentFault | SyncExceptionType::SPAlignmentFault if context.is_user() => {
+            return user_signal(context, SIGBUS);
+        }
         SyncExceptionType::PCAlignmentFault => {}
         SyncExceptionType::DataAbortLowLevel => {
+            if user_fault(far, &ec) {
+                DAIF::All.enable();
+                return;
+            }
             pr_err!(
                 "{}: {} access LowLevel from PC {:#018x}, FAR {:#018x}, iss {:#018x} {}\n",
                 ec,
@@ -79,11 +121,17 @@ fn sync_exception(context: &mut Context) {
                 ec.iss,
                 ec.fault_msg()
             );
-            pr_err!("{}\n", context);
-            DAIF::All.enable();
-            return;
+            return match ec.is_alignment_fault() {
+                true => user_signal(context, SIGBUS),
+                false => user_signal(context, SIGSEGV),
+            };
         }
         SyncExceptionType::DataAbortCurrentLevel => {
+            //user copy routines touching a stack page not faulted in yet
+            if user_fault(far, &ec) {
+                DAIF::All.enable();
+                return;
+            }
             pr_err!(
                 "{}: {} access from PC {:#018x}, FAR {:#018x}, iss {:#018x} {}\n",
                 ec,
@@ -105,6 +153,9 @@ fn sync_exception(context: &mut Context) {
         SyncExceptionType::SoftwareStepCurrentLevel => {}
         SyncExceptionType::WatchpointLowLevel => {}
         SyncExceptionType::WatchpointCurrentLevel => {}
+        SyncExceptionType::BRKInstructionAArch64 if context.is_user() => {
+            return user_signal(context, SIGTRAP);
+        }
         SyncExceptionType::BRKInstructionAArch64 => {}
     }
     pr_err!("{}\n", context);
@@ -112,21 +163,72 @@ fn sync_exception(context: &mut Context) {
     panic!()
 }
 
+//translation or access flag fault on a user address: swap the page back in,
+//grow the stack of the current task or mark the page young again. Out of memory
+//while the OOM killer waits for its victim the task gives way and faults again
+fn user_fault(far: usize, ec: &SyncException) -> bool {
+    if far > USER_END || !(ec.is_translation_fault() || ec.is_access_flag_fault()) {
+        return false;
+    }
+    let task = match scheduler::current() {
+        None => return false,
+        Some(task) => unsafe { &mut *task },
+    };
+    if task.page.lock().handle_fault(far, ec.is_access_flag_fault()) {
+        return true;
+    }
+    if oom::pending() && !signal::killed(task) {
+        scheduler::yield_current();
+        return true;
+    }
+    false
+}
+
+//a fault of user code is a signal to the task instead of a kernel panic
+fn user_signal(context: &mut Context, signo: usize) {
+    if let Some(task) = scheduler::current() {
+        signal::force(unsafe { &mut *task }, signo);
+    }
+    signal::do_signal(context);
+    DAIF::All.enable();
+}
+
+//platform_irq nesting, a softirq handler may be interrupted
+static IRQ_NESTING: AtomicUsize = AtomicUsize::new(0);
+
+pub fn in_irq() -> bool {
+    IRQ_NESTING.load(Ordering::Relaxed) > 0
+}
+
+//hard irq or softirq: the interrupted task may be anywhere, even inside the scheduler
+pub fn in_interrupt() -> bool {
+    in_irq() || softirq::in_softirq()
+}
+
 #[no_mangle]
-fn platform_irq() -> i32 {
+fn platform_irq(context: &mut Context) -> i32 {
     let mut ret = 0;
+    IRQ_NESTING.fetch_add(1, Ordering::Relaxed);
     match fetch_irq() {
         None => {}
         Some(irq) => {
-            match fetch_handler(irq) {
+            match handle_irq(irq) {
                 None => {
                     panic!("Unknown platform_irq: {:?}", irq.0)
                 }
-                Some(handler) => ret = handler(irq),
+                Some(handled) => ret = handled,
             }
             ack_irq(irq)
         }
     }
+    IRQ_NESTING.fetch_sub(1, Ordering::Relaxed);
+    if !in_irq() && softirq::has_pending() {
+        softirq::do_softirq();
+    }
+    //Ctrl-C or a kill while the task was running in user space
+    if context.is_user() {
+        signal::do_signal(context);
+    }
     ret
 }
 
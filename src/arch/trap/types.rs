@@ -160,6 +160,18 @@ impl SyncException {
             ec: SyncExceptionType::from(get_bits!(value, 26, 7) as u16),
         }
     }
+    //DFSC/IFSC: translation fault, level 0..3
+    pub fn is_translation_fault(&self) -> bool {
+        get_bits!(self.iss, 2, 4) == 0b0001
+    }
+    //DFSC/IFSC: access flag fault, level 0..3
+    pub fn is_access_flag_fault(&self) -> bool {
+        get_bits!(self.iss, 2, 4) == 0b0010
+    }
+    //DFSC: alignment fault
+    pub fn is_alignment_fault(&self) -> bool {
+        get_bits!(self.iss, 0, 6) == 0b100001
+    }
     pub fn fault_msg(&self) -> &str
     {
         for (id, msg) in FAULT_STATUS_MAP {
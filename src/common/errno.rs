@@ -0,0 +1,58 @@
+#![allow(dead_code)]
+#![allow(clippy::upper_case_acronyms)]
+
+//https://man7.org/linux/man-pages/man3/errno.3.html
+#[repr(isize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EFAULT = 14,
+    EBUSY = 16,
+    EEXIST = 17,
+    ENODEV = 19,
+    EINVAL = 22,
+    EMFILE = 24,
+    ENOTTY = 25,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EPIPE = 32,
+    ENOSYS = 38,
+    ENOTSOCK = 88,
+    EDESTADDRREQ = 89,
+    EMSGSIZE = 90,
+    ENOPROTOOPT = 92,
+    EPROTONOSUPPORT = 93,
+    ESOCKTNOSUPPORT = 94,
+    EOPNOTSUPP = 95,
+    EAFNOSUPPORT = 97,
+    EADDRINUSE = 98,
+    EADDRNOTAVAIL = 99,
+    ENETUNREACH = 101,
+    ECONNRESET = 104,
+    EISCONN = 106,
+    ENOTCONN = 107,
+    ETIMEDOUT = 110,
+    ECONNREFUSED = 111,
+    EALREADY = 114,
+}
+
+pub type SysResult<T = usize> = Result<T, Errno>;
+
+impl Errno {
+    //syscall return value, -errno on failure
+    pub fn ret(result: SysResult) -> usize {
+        match result {
+            Ok(value) => value,
+            Err(errno) => -(errno as isize) as usize,
+        }
+    }
+}
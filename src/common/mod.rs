@@ -1,6 +1,7 @@
 #[allow(unused_imports)]
 pub use mmio::MMIO;
 
+pub mod errno;
 pub mod print;
 pub mod symbol;
 pub mod sync;
@@ -0,0 +1,298 @@
+//lock dependency validator, built with the lockdep feature.
+//a lock class is the place its Mutex::new or RwLock::new was called, so every task's
+//files lock is one class. Each acquisition adds held class -> new class edges, a new
+//edge that closes a cycle is a possible deadlock even if it never hung. The first
+//report turns the validator off, the rest would mostly be follow-ups.
+//single core: the held stack belongs to the cpu. That holds as long as no spin lock
+//is held across a switch, which the scheduler checks before every one: a task that
+//sleeps releases its lock first (WaitQueue::sleep), swap I/O under a lock polls
+
+use core::cell::UnsafeCell;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::reg::DAIF;
+use crate::arch::trap::context::Context;
+use crate::arch::trap::in_interrupt;
+use crate::pr_err;
+
+pub type LockClass = &'static Location<'static>;
+
+const MAX_CLASSES: usize = 256;
+const MAX_EDGES: usize = 1024;
+const MAX_HELD: usize = 48;
+const TRACE_DEPTH: usize = 8;
+const WORDS: usize = MAX_CLASSES / 64;
+
+type Trace = [usize; TRACE_DEPTH];
+
+#[derive(Copy, Clone)]
+struct Usage {
+    trace: Trace,
+    exclusive: bool,
+}
+
+#[derive(Copy, Clone)]
+struct Held {
+    class: usize,
+    instance: usize,
+    exclusive: bool,
+    trace: Trace,
+}
+
+#[derive(Copy, Clone)]
+struct Edge {
+    from: usize,
+    to: usize,
+    trace: Trace,
+}
+
+struct Graph {
+    classes: [Option<LockClass>; MAX_CLASSES],
+    //after[a] has bit b: b was taken while holding a
+    after: [[u64; WORDS]; MAX_CLASSES],
+    edges: [Option<Edge>; MAX_EDGES],
+    //taken in platform_irq or a softirq / outside of them with irqs enabled
+    in_irq: [Option<Usage>; MAX_CLASSES],
+    irq_enabled: [Option<Usage>; MAX_CLASSES],
+    held: [Option<Held>; MAX_HELD],
+}
+
+struct State(UnsafeCell<Graph>);
+
+//only touched with irqs off and BUSY set
+unsafe impl Sync for State {}
+
+static STATE: State = State(UnsafeCell::new(Graph {
+    classes: [None; MAX_CLASSES],
+    after: [[0; WORDS]; MAX_CLASSES],
+    edges: [None; MAX_EDGES],
+    in_irq: [None; MAX_CLASSES],
+    irq_enabled: [None; MAX_CLASSES],
+    held: [None; MAX_HELD],
+}));
+static ENABLED: AtomicBool = AtomicBool::new(true);
+//set while the validator runs, the console locks of a report are not tracked
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+//f gets whether irqs were enabled at the lock operation
+fn with_graph(f: impl FnOnce(&mut Graph, bool)) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let irq_enabled = !DAIF::Irq.is_disabled();
+    DAIF::Irq.disable();
+    if !BUSY.swap(true, Ordering::Acquire) {
+        f(unsafe { &mut *STATE.0.get() }, irq_enabled);
+        BUSY.store(false, Ordering::Release);
+    }
+    if irq_enabled {
+        DAIF::Irq.enable();
+    }
+}
+
+#[inline(always)]
+fn trace() -> Trace {
+    let mut trace = [0; TRACE_DEPTH];
+    Context::current().backtrace(&mut trace);
+    trace
+}
+
+fn print_trace(trace: &Trace) {
+    let n = trace.iter().position(|pc| *pc == 0).unwrap_or(TRACE_DEPTH);
+    Context::print_frames(&trace[..n]);
+}
+
+fn turn_off() {
+    ENABLED.store(false, Ordering::Relaxed);
+    pr_err!("turning off the locking correctness validator\n");
+}
+
+impl Graph {
+    fn class_index(&mut self, class: LockClass) -> Option<usize> {
+        for (index, slot) in self.classes.iter_mut().enumerate() {
+            match slot {
+                Some(known) if *known == class => return Some(index),
+                Some(_) => {}
+                None => {
+                    *slot = Some(class);
+                    return Some(index);
+                }
+            }
+        }
+        None
+    }
+    fn name(&self, class: usize) -> LockClass {
+        self.classes[class].unwrap()
+    }
+    fn has_edge(&self, from: usize, to: usize) -> bool {
+        self.after[from][to / 64] & (1 << (to % 64)) != 0
+    }
+    fn add_edge(&mut self, from: usize, to: usize, trace: Trace) -> bool {
+        match self.edges.iter_mut().find(|edge| edge.is_none()) {
+            None => false,
+            Some(slot) => {
+                *slot = Some(Edge { from, to, trace });
+                self.after[from][to / 64] |= 1 << (to % 64);
+                true
+            }
+        }
+    }
+    fn edge_trace(&self, from: usize, to: usize) -> Option<&Trace> {
+        self.edges.iter().flatten().find(|edge| edge.from == from && edge.to == to).map(|edge| &edge.trace)
+    }
+    //breadth first from -> to, parent[c] is the class c was reached from
+    fn path(&self, from: usize, to: usize, parent: &mut [usize; MAX_CLASSES]) -> bool {
+        let mut queue = [0usize; MAX_CLASSES];
+        let (mut head, mut tail) = (0, 1);
+        parent.fill(usize::MAX);
+        queue[0] = from;
+        parent[from] = from;
+        while head < tail {
+            let class = queue[head];
+            head += 1;
+            if class == to {
+                return true;
+            }
+            for (next, reached_from) in parent.iter_mut().enumerate() {
+                if self.has_edge(class, next) && *reached_from == usize::MAX {
+                    *reached_from = class;
+                    queue[tail] = next;
+                    tail += 1;
+                }
+            }
+        }
+        false
+    }
+    fn report_cycle(&self, held: &Held, class: usize, trace: &Trace) {
+        pr_err!("\nlockdep: possible circular locking dependency\n");
+        pr_err!("taking {} while holding {}\n", self.name(class), self.name(held.class));
+        print_trace(trace);
+        pr_err!("{} was taken at\n", self.name(held.class));
+        print_trace(&held.trace);
+        pr_err!("the existing dependency chain:\n");
+        let mut parent = [0; MAX_CLASSES];
+        self.path(class, held.class, &mut parent);
+        let mut to = held.class;
+        while to != class {
+            let from = parent[to];
+            pr_err!("{} -> {}\n", self.name(from), self.name(to));
+            if let Some(trace) = self.edge_trace(from, to) {
+                print_trace(trace);
+            }
+            to = from;
+        }
+    }
+    fn check_irq(&mut self, class: usize, exclusive: bool, irq_enabled: bool, trace: &Trace) -> bool {
+        let usage = Usage { trace: *trace, exclusive };
+        let (mine, other) = match in_interrupt() {
+            true => (&mut self.in_irq[class], self.irq_enabled[class]),
+            false if irq_enabled => (&mut self.irq_enabled[class], self.in_irq[class]),
+            false => return true,
+        };
+        if mine.map_or(true, |old| exclusive && !old.exclusive) {
+            *mine = Some(usage);
+        }
+        //readers on both sides do not wait for each other
+        match other {
+            Some(other) if other.exclusive || exclusive => {
+                pr_err!("\nlockdep: inconsistent irq usage of {}\n", self.name(class));
+                pr_err!("taken {}\n", if in_interrupt() { "in interrupt context" } else { "with irqs enabled" });
+                print_trace(trace);
+                pr_err!("also taken {}\n", if in_interrupt() { "with irqs enabled" } else { "in interrupt context" });
+                print_trace(&other.trace);
+                false
+            }
+            _ => true,
+        }
+    }
+    fn acquire(&mut self, class: LockClass, instance: usize, exclusive: bool, try_lock: bool, irq_enabled: bool, trace: Trace) {
+        let class = match self.class_index(class) {
+            Some(class) => class,
+            None => {
+                pr_err!("lockdep: more than {} lock classes\n", MAX_CLASSES);
+                return turn_off();
+            }
+        };
+        if !try_lock {
+            let held = self.held.iter().flatten().copied().find(|held| held.instance == instance);
+            match held {
+                //a second reader of the same lock does not spin
+                Some(held) if held.exclusive || exclusive => {
+                    pr_err!("\nlockdep: recursive locking of {}\n", self.name(class));
+                    print_trace(&trace);
+                    pr_err!("already taken at\n");
+                    print_trace(&held.trace);
+                    return turn_off();
+                }
+                _ => {}
+            }
+            for index in 0..MAX_HELD {
+                let held = match self.held[index] {
+                    //two locks of one class, e.g. the files of two tasks, are not ordered
+                    Some(held) if held.class != class => held,
+                    _ => continue,
+                };
+                if self.has_edge(held.class, class) {
+                    continue;
+                }
+                let mut parent = [0; MAX_CLASSES];
+                if self.path(class, held.class, &mut parent) {
+                    self.report_cycle(&held, class, &trace);
+                    return turn_off();
+                }
+                if !self.add_edge(held.class, class, trace) {
+                    pr_err!("lockdep: more than {} dependencies\n", MAX_EDGES);
+                    return turn_off();
+                }
+            }
+        }
+        if !self.check_irq(class, exclusive, irq_enabled, &trace) {
+            return turn_off();
+        }
+        match self.held.iter_mut().find(|held| held.is_none()) {
+            Some(slot) => *slot = Some(Held { class, instance, exclusive, trace }),
+            None => {
+                pr_err!("lockdep: more than {} locks held\n", MAX_HELD);
+                turn_off()
+            }
+        }
+    }
+    fn release(&mut self, instance: usize) {
+        //the latest one, a force unlocked lock may be released twice
+        if let Some(slot) = self.held.iter_mut().rev().find(|held| held.is_some_and(|held| held.instance == instance)) {
+            *slot = None;
+        }
+    }
+}
+
+//before spinning on the lock, so a deadlock is reported instead of hanging
+#[inline(always)]
+pub fn acquire(class: LockClass, instance: usize, exclusive: bool, try_lock: bool) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let trace = trace();
+    with_graph(|graph, irq_enabled| graph.acquire(class, instance, exclusive, try_lock, irq_enabled, trace));
+}
+
+pub fn release(instance: usize) {
+    with_graph(|graph, _| graph.release(instance));
+}
+
+//before a task switch, the next task taking a lock still held would spin forever
+pub fn check_switch() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let trace = trace();
+    with_graph(|graph, _| {
+        if let Some(held) = graph.held.iter().flatten().next() {
+            pr_err!("\nlockdep: {} held across a context switch\n", graph.name(held.class));
+            print_trace(&trace);
+            pr_err!("{} was taken at\n", graph.name(held.class));
+            print_trace(&held.trace);
+            turn_off();
+        }
+    });
+}
@@ -0,0 +1,52 @@
+//per Mutex contention statistics, built with the lockstat feature.
+//wait times are in CNTPCT ticks, only read when the lock was contended
+
+use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::reg_read_p;
+
+pub struct LockStats {
+    acquired: AtomicUsize,
+    contended: AtomicUsize,
+    wait_ticks: AtomicU64,
+    max_wait_ticks: AtomicU64,
+}
+
+impl LockStats {
+    pub const fn new() -> Self {
+        Self {
+            acquired: AtomicUsize::new(0),
+            contended: AtomicUsize::new(0),
+            wait_ticks: AtomicU64::new(0),
+            max_wait_ticks: AtomicU64::new(0),
+        }
+    }
+    pub fn acquired(&self) {
+        self.acquired.fetch_add(1, Ordering::Relaxed);
+    }
+    //the lock was taken, returns the start of the wait
+    pub fn contended(&self) -> u64 {
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        reg_read_p!(CNTPCT_EL0) as u64
+    }
+    pub fn waited(&self, start: u64) {
+        let ticks = (reg_read_p!(CNTPCT_EL0) as u64).saturating_sub(start);
+        self.wait_ticks.fetch_add(ticks, Ordering::Relaxed);
+        self.max_wait_ticks.fetch_max(ticks, Ordering::Relaxed);
+    }
+}
+
+impl Display for LockStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let us = |ticks: u64| ticks * 1_000_000 / reg_read_p!(CNTFRQ_EL0) as u64;
+        write!(
+            f,
+            "acquired {} contended {} wait {}us max {}us",
+            self.acquired.load(Ordering::Relaxed),
+            self.contended.load(Ordering::Relaxed),
+            us(self.wait_ticks.load(Ordering::Relaxed)),
+            us(self.max_wait_ticks.load(Ordering::Relaxed))
+        )
+    }
+}
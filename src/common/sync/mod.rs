@@ -1,8 +1,13 @@
 #[allow(unused_imports)]
-pub use mutex::Mutex;
+pub use mutex::{Mutex, MutexGuard};
 #[allow(unused_imports)]
 pub use rwlock::RwLock;
 
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+#[cfg(feature = "lockstat")]
+pub mod lockstat;
 mod mutex;
+pub mod rcu;
 mod rwlock;
 pub type MutexNoIrq<T> = Mutex<T>;
@@ -1,25 +1,38 @@
 #![allow(dead_code)]
 
+use core::arch::asm;
 use core::cell::UnsafeCell;
 use core::default::Default;
-use core::hint::spin_loop;
 use core::marker::Sync;
 use core::ops::{Deref, DerefMut, Drop};
 use core::option::Option::{self, None, Some};
-use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+use core::sync::atomic::{AtomicU16, Ordering};
 
 use crate::arch::reg::DAIF;
+#[cfg(feature = "lockdep")]
+use crate::common::sync::lockdep::{self, LockClass};
+#[cfg(feature = "lockstat")]
+use crate::common::sync::lockstat::LockStats;
 
+//ticket lock: a locker takes the next ticket and waits in wfe until owner reaches it,
+//so waiters get the lock in arrival order. An unlock signals them with sev
 pub struct Mutex<T: ?Sized> {
     no_irq: bool,
-    lock: AtomicBool,
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
+    #[cfg(feature = "lockstat")]
+    stats: LockStats,
+    next: AtomicU16,
+    owner: AtomicU16,
     data: UnsafeCell<T>,
 }
 
 #[derive(Debug)]
 pub struct MutexGuard<'a, T: ?Sized + 'a> {
     irq_enabled_before: bool,
-    lock: &'a AtomicBool,
+    owner: &'a AtomicU16,
     data: &'a mut T,
     no_irq: bool,
 }
@@ -37,19 +50,42 @@ unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
 
 unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
 
+#[inline(always)]
+fn wfe() {
+    unsafe { asm!("wfe", options(nomem, nostack)) }
+}
+
+//the owner store has to be visible before the waiters wake up
+#[inline(always)]
+fn sev() {
+    unsafe { asm!("dsb ishst", "sev", options(nostack)) }
+}
+
 impl<T> Mutex<T> {
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(data: T) -> Mutex<T> {
         Mutex {
-            lock: AtomicBool::new(false),
+            next: AtomicU16::new(0),
+            owner: AtomicU16::new(0),
             data: UnsafeCell::new(data),
             no_irq: false,
+            #[cfg(feature = "lockdep")]
+            class: Location::caller(),
+            #[cfg(feature = "lockstat")]
+            stats: LockStats::new(),
         }
     }
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new_no_irq(data: T) -> Mutex<T> {
         Mutex {
-            lock: AtomicBool::new(false),
+            next: AtomicU16::new(0),
+            owner: AtomicU16::new(0),
             data: UnsafeCell::new(data),
             no_irq: true,
+            #[cfg(feature = "lockdep")]
+            class: Location::caller(),
+            #[cfg(feature = "lockstat")]
+            stats: LockStats::new(),
         }
     }
 
@@ -62,7 +98,7 @@ impl<T> Mutex<T> {
 
 impl<T: ?Sized> Mutex<T> {
     pub fn is_locked(&self) -> bool {
-        self.lock.load(Ordering::Relaxed)
+        self.next.load(Ordering::Relaxed) != self.owner.load(Ordering::Relaxed)
     }
 
     pub fn lock(&self) -> MutexGuard<T> {
@@ -71,26 +107,37 @@ impl<T: ?Sized> Mutex<T> {
             irq_state = !DAIF::Irq.is_disabled();
             DAIF::Irq.disable();
         }
-        while self
-            .lock
-            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
-        {
-            while self.is_locked() {
-                spin_loop();
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class, self.owner.as_ptr().addr(), true, false);
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        if self.owner.load(Ordering::Acquire) != ticket {
+            #[cfg(feature = "lockstat")]
+            let start = self.stats.contended();
+            while self.owner.load(Ordering::Acquire) != ticket {
+                wfe();
             }
+            #[cfg(feature = "lockstat")]
+            self.stats.waited(start);
         }
+        #[cfg(feature = "lockstat")]
+        self.stats.acquired();
         MutexGuard {
             irq_enabled_before: irq_state,
-            lock: &self.lock,
+            owner: &self.owner,
             data: unsafe { &mut *self.data.get() },
             no_irq: self.no_irq,
         }
     }
 
+    //release whoever holds the lock
     #[allow(unused)]
     pub unsafe fn force_unlock(&self) {
-        self.lock.store(false, Ordering::Release);
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.owner.as_ptr().addr());
+        if self.is_locked() {
+            self.owner.fetch_add(1, Ordering::Release);
+            sev();
+        }
     }
 
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
@@ -99,14 +146,20 @@ impl<T: ?Sized> Mutex<T> {
             irq_state = !DAIF::Irq.is_disabled();
             DAIF::Irq.disable();
         }
+        //only free when nobody holds or waits for a ticket
+        let owner = self.owner.load(Ordering::Acquire);
         if self
-            .lock
-            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .next
+            .compare_exchange(owner, owner.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            #[cfg(feature = "lockdep")]
+            lockdep::acquire(self.class, self.owner.as_ptr().addr(), true, true);
+            #[cfg(feature = "lockstat")]
+            self.stats.acquired();
             Some(MutexGuard {
                 irq_enabled_before: irq_state,
-                lock: &self.lock,
+                owner: &self.owner,
                 data: unsafe { &mut *self.data.get() },
                 no_irq: self.no_irq,
             })
@@ -117,6 +170,11 @@ impl<T: ?Sized> Mutex<T> {
             None
         }
     }
+
+    #[cfg(feature = "lockstat")]
+    pub fn stats(&self) -> &LockStats {
+        &self.stats
+    }
 }
 
 
@@ -141,7 +199,12 @@ impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
 
 impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.store(false, Ordering::Release);
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.owner.as_ptr().addr());
+        //only the holder writes owner
+        let ticket = self.owner.load(Ordering::Relaxed);
+        self.owner.store(ticket.wrapping_add(1), Ordering::Release);
+        sev();
         if self.irq_enabled_before && self.no_irq {
             DAIF::Irq.enable();
         }
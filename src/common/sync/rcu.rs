@@ -0,0 +1,145 @@
+#![allow(dead_code)]
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::arch::reg::current_core;
+use crate::common::sync::Mutex;
+use crate::config::NR_CPUS;
+use crate::pr_err;
+use crate::task::scheduler;
+use crate::task::softirq::{self, SoftIrq};
+
+//read-copy-update: readers take no lock, a writer publishes a new copy and frees the
+//old one after a grace period. Every cpu passing through the scheduler is a quiescent
+//state, read-side sections must not sleep or yield. A grace period has elapsed once
+//every cpu has switched since it started
+
+type Snapshot = [usize; NR_CPUS];
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+//scheduling points per cpu
+static SWITCHES: [AtomicUsize; NR_CPUS] = [ZERO; NR_CPUS];
+//read-side nesting per cpu, only checked at a switch
+static NESTING: [AtomicUsize; NR_CPUS] = [ZERO; NR_CPUS];
+//in call order, so the snapshots are too
+static CALLBACKS: Mutex<VecDeque<(Snapshot, Callback)>> = Mutex::new_no_irq(VecDeque::new());
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+fn snapshot() -> Snapshot {
+    let mut snapshot = [0; NR_CPUS];
+    for (cpu, switches) in SWITCHES.iter().enumerate() {
+        snapshot[cpu] = switches.load(Ordering::Acquire);
+    }
+    snapshot
+}
+
+fn elapsed(snapshot: &Snapshot) -> bool {
+    SWITCHES
+        .iter()
+        .zip(snapshot)
+        .all(|(switches, old)| switches.load(Ordering::Acquire) != *old)
+}
+
+pub struct RcuReadGuard {
+    //stays on the cpu it was taken on
+    _not_send: PhantomData<*const ()>,
+}
+
+pub fn rcu_read_lock() -> RcuReadGuard {
+    NESTING[current_core()].fetch_add(1, Ordering::Relaxed);
+    RcuReadGuard { _not_send: PhantomData }
+}
+
+impl Drop for RcuReadGuard {
+    fn drop(&mut self) {
+        NESTING[current_core()].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+//called by the scheduler before picking the next task
+pub fn note_context_switch() {
+    let cpu = current_core();
+    if NESTING[cpu].load(Ordering::Relaxed) != 0 {
+        pr_err!("rcu: context switch in a read-side critical section\n");
+    }
+    SWITCHES[cpu].fetch_add(1, Ordering::Release);
+    if PENDING.load(Ordering::Relaxed) != 0 {
+        softirq::raise_softirq(SoftIrq::Rcu);
+    }
+}
+
+//wait for every reader that may still see the old copy, from task context outside
+//of a read-side section. The caller's own yield is its cpu's quiescent state
+pub fn synchronize_rcu() {
+    let snapshot = snapshot();
+    while !elapsed(&snapshot) {
+        scheduler::yield_current();
+    }
+}
+
+//run f after a grace period, in softirq context. May be called by readers
+pub fn call_rcu(f: impl FnOnce() + Send + 'static) {
+    CALLBACKS.lock().push_back((snapshot(), Box::new(f)));
+    PENDING.fetch_add(1, Ordering::Relaxed);
+}
+
+fn rcu_action() {
+    loop {
+        let callback = match CALLBACKS.lock() {
+            mut callbacks if callbacks.front().is_some_and(|(snapshot, _)| elapsed(snapshot)) => {
+                callbacks.pop_front().unwrap().1
+            }
+            _ => return,
+        };
+        PENDING.fetch_sub(1, Ordering::Relaxed);
+        callback();
+    }
+}
+
+pub fn init() {
+    softirq::open_softirq(SoftIrq::Rcu, rcu_action);
+}
+
+//a pointer published with rcu. Writers serialize among themselves, e.g. under the
+//lock of the object it belongs to; a replaced copy is freed with call_rcu
+pub struct RcuCell<T: Send + 'static> {
+    ptr: AtomicPtr<T>,
+}
+
+unsafe impl<T: Send + Sync + 'static> Sync for RcuCell<T> {}
+
+impl<T: Send + 'static> RcuCell<T> {
+    pub const fn empty() -> Self {
+        Self {
+            ptr: AtomicPtr::new(core::ptr::null_mut()),
+        }
+    }
+    pub fn new(data: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(data))),
+        }
+    }
+    pub fn read<'a>(&'a self, _guard: &'a RcuReadGuard) -> Option<&'a T> {
+        unsafe { self.ptr.load(Ordering::Acquire).as_ref() }
+    }
+    pub fn assign(&self, data: T) {
+        let old = self.ptr.swap(Box::into_raw(Box::new(data)), Ordering::AcqRel) as usize;
+        if old != 0 {
+            call_rcu(move || drop(unsafe { Box::from_raw(old as *mut T) }));
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        let old = *self.ptr.get_mut() as usize;
+        if old != 0 {
+            call_rcu(move || drop(unsafe { Box::from_raw(old as *mut T) }));
+        }
+    }
+}
@@ -8,8 +8,15 @@ use core::{
 };
 //https://github.com/mvdnes/spin-rs/blob/master/src/rwlock.rs
 use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+
+#[cfg(feature = "lockdep")]
+use crate::common::sync::lockdep::{self, LockClass};
 
 pub struct RwLock<T: ?Sized> {
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
     lock: AtomicUsize,
     data: UnsafeCell<T>,
 }
@@ -44,10 +51,13 @@ unsafe impl<T: ?Sized + Sync> Sync for ReadGuard<'_, T> {}
 
 impl<T> RwLock<T> {
     #[inline]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(data: T) -> Self {
         RwLock {
             lock: AtomicUsize::new(0),
             data: UnsafeCell::new(data),
+            #[cfg(feature = "lockdep")]
+            class: Location::caller(),
         }
     }
 
@@ -69,8 +79,10 @@ impl<T> RwLock<T> {
 impl<T: ?Sized> RwLock<T> {
     #[inline]
     pub fn read(&self) -> ReadGuard<T> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class, self.lock.as_ptr().addr(), false, false);
         loop {
-            match self.try_read() {
+            match self.try_read_internal() {
                 Some(guard) => return guard,
                 _ => {}
             }
@@ -79,6 +91,8 @@ impl<T: ?Sized> RwLock<T> {
 
     #[inline]
     pub fn write(&self) -> WriteGuard<T> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class, self.lock.as_ptr().addr(), true, false);
         loop {
             match self.try_write_internal(false) {
                 Some(guard) => return guard,
@@ -104,6 +118,16 @@ impl<T: ?Sized> RwLock<T> {
 
     #[inline]
     pub fn try_read(&self) -> Option<ReadGuard<T>> {
+        let guard = self.try_read_internal();
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(self.class, self.lock.as_ptr().addr(), false, true);
+        }
+        guard
+    }
+
+    #[inline(always)]
+    fn try_read_internal(&self) -> Option<ReadGuard<T>> {
         let value = self.acquire_reader();
 
         // We check the UPGRADED bit here so that new readers are prevented when an UPGRADED lock is held.
@@ -153,6 +177,8 @@ impl<T: ?Sized> RwLock<T> {
     /// RAII. The underlying atomic operation uses `Ordering::Release`.
     #[inline]
     pub unsafe fn force_read_decrement(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock.as_ptr().addr());
         debug_assert!(self.lock.load(Ordering::Relaxed) & !WRITER > 0);
         self.lock.fetch_sub(READER, Ordering::Release);
     }
@@ -167,6 +193,8 @@ impl<T: ?Sized> RwLock<T> {
     /// underlying atomic operation uses `Ordering::Release`.
     #[inline]
     pub unsafe fn force_write_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock.as_ptr().addr());
         debug_assert_eq!(self.lock.load(Ordering::Relaxed) & !(WRITER | UPGRADED), 0);
         self.lock.fetch_and(!(WRITER | UPGRADED), Ordering::Release);
     }
@@ -195,7 +223,12 @@ impl<T: ?Sized> RwLock<T> {
 
     #[inline]
     pub fn try_write(&self) -> Option<WriteGuard<T>> {
-        self.try_write_internal(true)
+        let guard = self.try_write_internal(true);
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(self.class, self.lock.as_ptr().addr(), true, true);
+        }
+        guard
     }
 
     /// Attempt to lock this rwlock with exclusive write access.
@@ -204,7 +237,12 @@ impl<T: ?Sized> RwLock<T> {
     /// would otherwise succeed, which can result in more efficient code on some platforms.
     #[inline]
     pub fn try_write_weak(&self) -> Option<WriteGuard<T>> {
-        self.try_write_internal(false)
+        let guard = self.try_write_internal(false);
+        #[cfg(feature = "lockdep")]
+        if guard.is_some() {
+            lockdep::acquire(self.class, self.lock.as_ptr().addr(), true, true);
+        }
+        guard
     }
 
     pub fn get_mut(&mut self) -> &mut T {
@@ -298,6 +336,8 @@ impl<'rwlock, T: ?Sized> DerefMut for WriteGuard<'rwlock, T> {
 impl<'rwlock, T: ?Sized> Drop for ReadGuard<'rwlock, T> {
     fn drop(&mut self) {
         debug_assert!(self.lock.load(Ordering::Relaxed) & !(WRITER | UPGRADED) > 0);
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock.as_ptr().addr());
         self.lock.fetch_sub(READER, Ordering::Release);
     }
 }
@@ -306,6 +346,8 @@ impl<'rwlock, T: ?Sized> Drop for WriteGuard<'rwlock, T> {
     fn drop(&mut self) {
         debug_assert_eq!(self.inner.lock.load(Ordering::Relaxed) & WRITER, WRITER);
 
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.inner.lock.as_ptr().addr());
         // Writer is responsible for clearing both WRITER and UPGRADED bits.
         // The UPGRADED bit may be set if an upgradeable lock attempts an upgrade while this lock is held.
         self.inner
@@ -17,6 +17,8 @@ pub mod lds {
         pub static __stack_end: u8;
         pub static __ro_start: u8;
         pub static __ro_end: u8;
+        pub static __drivers_start: u8;
+        pub static __drivers_end: u8;
         pub static __data_start: u8;
         pub static __data_end: u8;
         pub static __bss_start: u8;
@@ -27,14 +29,5 @@ pub mod lds {
     }
 }
 
-pub const UART_ADDRESS: usize = 0x9000000;
-pub const MEM_SIZE: usize = 0x8000000;
-pub const PL011_IRQ: u32 = 0x1;
-pub const TIMER_IRQ: u32 = 0xe;
-pub const GICD_BASE: usize = 0x8000000;
-pub const GICC_BASE: usize = 0x8010000;
-pub const GICD_SIZE: usize = 0x10000;
-pub const GICC_SIZE: usize = 0x10000;
-pub const PCIE_CONFIG_SPACE_START: usize = 0x4010000000;
-pub const PCIE_MEM_64_START: usize = 0x8000000000;
+pub const NR_CPUS: usize = 1;
 
@@ -1,19 +1,47 @@
 use core::fmt;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use arrayvec::ArrayVec;
+use fdt::node::FdtNode;
+use fdt::Fdt;
 
+use crate::arch::dtb::{self, boot_dtb};
 use crate::arch::{IntId, setup_irq, Trigger};
+use crate::common::errno::{Errno, SysResult};
+use crate::devices::driver::{device_name, Device, DeviceClass, DeviceId, Driver, ProbeInfo};
 use crate::devices::uart::{Pl011Uart, Read};
-use crate::mm::VirtAddr;
+use crate::mm::{ioremap, VirtAddr};
+use crate::{pr_err, register_driver};
 
-use super::super::config::{PL011_IRQ, UART_ADDRESS};
-
-static mut UART: Pl011Uart = Pl011Uart::new(VirtAddr::from_phy(UART_ADDRESS).as_usize());
+//nothing is printed until early_console found the uart
+static mut UART: Pl011Uart = Pl011Uart::new(0);
 static mut UART_RX_BUFFER: ArrayVec<u8, 64> = ArrayVec::new_const();
+//Ctrl-C was typed, it is not buffered but turned into SIGINT
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+const ETX: u8 = 0x03;
 
 pub fn puts(args: fmt::Arguments) {
-    unsafe { UART.write_fmt(args).unwrap() }
+    unsafe {
+        if UART.base_addr() != 0 {
+            UART.write_fmt(args).unwrap()
+        }
+    }
+}
+
+fn uart_node<'b>(fdt: &'b Fdt<'static>) -> Option<FdtNode<'b, 'static>> {
+    fdt.find_compatible(&["arm,pl011"]).or_else(|| fdt.find_node("/pl011"))
+}
+
+//first thing at boot, before the heap: the uart of the loader's device tree is used
+//through the boot page table until the kernel page table has it
+pub fn early_console() {
+    let fdt = boot_dtb();
+    if let Some((pa, size)) = uart_node(&fdt).and_then(|node| dtb::reg(&node, 0)) {
+        if let Ok(va) = ioremap(pa, size) {
+            unsafe { UART = Pl011Uart::new(va.as_usize()) }
+        }
+    }
 }
 
 #[macro_export]
@@ -48,6 +76,10 @@ pub fn gets(buffer: &mut [u8]) -> usize {
     }
 }
 
+pub fn take_interrupt() -> bool {
+    INTERRUPT.swap(false, Ordering::AcqRel)
+}
+
 fn pl011uart_irq_handler(_irq: IntId) -> i32 {
     unsafe {
         match UART.is_rx_interrupt() {
@@ -58,6 +90,7 @@ fn pl011uart_irq_handler(_irq: IntId) -> i32 {
                 while !UART.rx_is_empty() {
                     match UART.read_char() {
                         None => {}
+                        Some(ETX) => INTERRUPT.store(true, Ordering::Release),
                         Some(c) => UART_RX_BUFFER.push(c),
                     }
                 }
@@ -68,9 +101,27 @@ fn pl011uart_irq_handler(_irq: IntId) -> i32 {
     0
 }
 
-pub fn setup_console() {
-    unsafe {
-        UART.init(0, 0)
+static PL011_DRIVER: Driver = Driver {
+    name: "pl011",
+    ids: &[DeviceId::Compatible("arm,pl011")],
+    probe: pl011_probe,
+};
+register_driver!(PL011_DRIVER);
+
+//only the uart early_console took, it gets its rx interrupt
+fn pl011_probe(info: &ProbeInfo) -> SysResult<Device> {
+    let node = match info {
+        ProbeInfo::Fdt(node) => node,
+        _ => return Err(Errno::ENODEV),
     };
-    setup_irq(IntId::spi(PL011_IRQ), Trigger::Level, pl011uart_irq_handler);
+    let (pa, _) = dtb::reg(node, 0).ok_or(Errno::ENODEV)?;
+    if unsafe { UART.base_addr() } != VirtAddr::from_phy(pa.as_usize()).as_usize() {
+        return Err(Errno::ENODEV);
+    }
+    unsafe { UART.init(0, 0) };
+    match dtb::interrupt(node, 0) {
+        None => pr_err!("console: the uart has no interrupt, no input\n"),
+        Some(irq) => setup_irq(irq, Trigger::Level, pl011uart_irq_handler),
+    }
+    Ok(Device::new(device_name("ttyAMA"), DeviceClass::Serial))
 }
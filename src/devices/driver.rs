@@ -0,0 +1,213 @@
+#![allow(dead_code)]
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use fdt::node::FdtNode;
+
+use crate::arch::dtb::dtb;
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::devices::pci::bus::{PCIBus, PciDevice};
+use crate::task::sync::Completion;
+use crate::{lds_address, pr_err, pr_notice};
+
+//drivers register themselves with register_driver!, the probe walks the device tree and
+//then every PCI bus a driver found there. A device without a driver is only logged
+
+//what a driver binds to
+pub enum DeviceId {
+    Compatible(&'static str),
+    Pci { vendor: u16, device: u16 },
+    PciClass { class: u8, sub_class: u8 },
+}
+
+//the device a probe is called for
+pub enum ProbeInfo<'a> {
+    Fdt(&'a FdtNode<'static, 'static>),
+    Pci(&'static PCIBus, &'static PciDevice),
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    pub ids: &'static [DeviceId],
+    pub probe: fn(&ProbeInfo) -> SysResult<Device>,
+}
+
+//in the .drivers section, between __drivers_start and __drivers_end
+#[macro_export]
+macro_rules! register_driver {
+    ($driver: ident) => {
+        ::paste::paste! {
+            #[used]
+            #[link_section = ".drivers"]
+            static [<__DRIVER_ $driver>]: &'static $crate::devices::driver::Driver = &$driver;
+        }
+    };
+}
+
+//read_block and write_block may sleep until the request is done, never call them under
+//a spin lock (Mutex) or SCHEDULER. The atomic ones poll the device instead, for swap
+//I/O under SWAP or the page table lock of a faulting task
+pub trait BlockDevice: Send + Sync {
+    fn block_size(&self) -> usize;
+    //in bytes
+    fn capacity(&self) -> u64;
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> bool;
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> bool;
+    fn read_block_atomic(&self, block_id: usize, buf: &mut [u8]) -> bool;
+    fn write_block_atomic(&self, block_id: usize, buf: &[u8]) -> bool;
+}
+
+pub trait NetDevice: Send + Sync {
+    fn mac(&self) -> [u8; 6];
+    //the largest IP packet in a frame
+    fn mtu(&self) -> usize;
+    //an ethernet frame without the frame check sequence. csum is where a TCP or UDP
+    //checksum starts and where it goes, the pseudo header sum is already in place.
+    //false when the frame was dropped
+    fn transmit(&self, frame: &[u8], csum: Option<(usize, usize)>) -> bool;
+    //the next received frame and whether the device checked its checksums
+    fn receive(&self) -> Option<(Vec<u8>, bool)>;
+    //completed when frames arrive, None when the device is polled
+    fn rx_event(&self) -> Option<&'static Completion>;
+}
+
+pub enum DeviceClass {
+    Block(&'static dyn BlockDevice),
+    Net(&'static dyn NetDevice),
+    Serial,
+    Bus,
+    Interrupt,
+    Other,
+}
+
+impl DeviceClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceClass::Block(_) => "block",
+            DeviceClass::Net(_) => "net",
+            DeviceClass::Serial => "serial",
+            DeviceClass::Bus => "bus",
+            DeviceClass::Interrupt => "interrupt",
+            DeviceClass::Other => "other",
+        }
+    }
+}
+
+pub struct Device {
+    pub name: String,
+    pub class: DeviceClass,
+    pub driver: &'static str,
+}
+
+impl Device {
+    //driver is filled in by the probe
+    pub fn new(name: String, class: DeviceClass) -> Self {
+        Self { name, class, driver: "" }
+    }
+}
+
+static DEVICES: Mutex<Vec<Device>> = Mutex::new(Vec::new());
+//host bridges probed so far, their devices are probed after the device tree
+static PCI_BUSES: Mutex<Vec<&'static PCIBus>> = Mutex::new(Vec::new());
+
+fn drivers() -> &'static [&'static Driver] {
+    let start = lds_address!(drivers_start);
+    let end = lds_address!(drivers_end);
+    unsafe { core::slice::from_raw_parts(start as *const &'static Driver, (end - start) / 8) }
+}
+
+//prefix and the number of devices named like it so far, e.g. vd0
+pub fn device_name(prefix: &str) -> String {
+    let n = DEVICES.lock().iter().filter(|dev| dev.name.starts_with(prefix)).count();
+    format!("{}{}", prefix, n)
+}
+
+pub fn add_pci_bus(bus: &'static PCIBus) {
+    PCI_BUSES.lock().push(bus);
+}
+
+pub fn pci_buses() -> Vec<&'static PCIBus> {
+    PCI_BUSES.lock().clone()
+}
+
+fn matches(id: &DeviceId, info: &ProbeInfo) -> bool {
+    match (id, info) {
+        (DeviceId::Compatible(compatible), ProbeInfo::Fdt(node)) => node
+            .compatible()
+            .is_some_and(|node_compatible| node_compatible.all().any(|name| name == *compatible)),
+        (DeviceId::Pci { vendor, device }, ProbeInfo::Pci(_, dev)) => {
+            dev.vendor_id == *vendor && dev.device_id == *device
+        }
+        (DeviceId::PciClass { class, sub_class }, ProbeInfo::Pci(_, dev)) => {
+            dev.class_code == *class && dev.sub_class == *sub_class
+        }
+        _ => false,
+    }
+}
+
+//the first driver that takes the device, false when none does
+fn probe_device(info: &ProbeInfo) -> bool {
+    for driver in drivers() {
+        if !driver.ids.iter().any(|id| matches(id, info)) {
+            continue;
+        }
+        match (driver.probe)(info) {
+            Ok(mut device) => {
+                device.driver = driver.name;
+                pr_notice!("{}: {} device {}\n", driver.name, device.class.as_str(), device.name);
+                DEVICES.lock().push(device);
+                return true;
+            }
+            //not a device the driver can handle after all, e.g. an empty slot
+            Err(Errno::ENODEV) => {}
+            Err(errno) => pr_err!("{}: probe failed: {:?}\n", driver.name, errno),
+        }
+    }
+    false
+}
+
+pub fn probe_all() {
+    for node in dtb().all_nodes() {
+        //bound to nothing, nothing to say about it
+        if node.compatible().is_none() || matches!(node.property("status").and_then(|s| s.as_str()), Some("disabled")) {
+            continue;
+        }
+        probe_device(&ProbeInfo::Fdt(&node));
+    }
+    for bus in pci_buses() {
+        for dev in bus.devices() {
+            if !dev.is_bridge() && !probe_device(&ProbeInfo::Pci(bus, dev)) {
+                pr_notice!("PCI: no driver for {}\n", dev);
+            }
+        }
+    }
+}
+
+pub fn for_each_device(mut f: impl FnMut(&Device)) {
+    for device in DEVICES.lock().iter() {
+        f(device)
+    }
+}
+
+//the first block device, or the one named name
+pub fn block_device(name: Option<&str>) -> Option<&'static dyn BlockDevice> {
+    DEVICES.lock().iter().find_map(|device| match device.class {
+        DeviceClass::Block(block) if name.map_or(true, |name| device.name == name) => Some(block),
+        _ => None,
+    })
+}
+
+//every network device and its name
+pub fn net_devices() -> Vec<(String, &'static dyn NetDevice)> {
+    DEVICES
+        .lock()
+        .iter()
+        .filter_map(|device| match device.class {
+            DeviceClass::Net(net) => Some((device.name.clone(), net)),
+            _ => None,
+        })
+        .collect()
+}
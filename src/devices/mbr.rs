@@ -0,0 +1,36 @@
+use super::{block_size, read_block};
+
+//https://wiki.osdev.org/MBR_(x86)
+const PARTITION_TABLE_OFFSET: usize = 446;
+const PARTITION_ENTRY_SIZE: usize = 16;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+pub const PARTITION_NUM: usize = 4;
+pub const PARTITION_LINUX_SWAP: u8 = 0x82;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Partition {
+    pub part_type: u8,
+    pub start_lba: usize,
+    pub sectors: usize,
+}
+
+//primary partition 1..=4 of the block device
+pub fn partition(index: usize) -> Option<Partition> {
+    if !(1..=PARTITION_NUM).contains(&index) || block_size() != 512 {
+        return None;
+    }
+    let mut sector = [0u8; 512];
+    if !read_block(0, &mut sector) || !sector.ends_with(&BOOT_SIGNATURE) {
+        return None;
+    }
+    let entry = &sector[PARTITION_TABLE_OFFSET + (index - 1) * PARTITION_ENTRY_SIZE..][..PARTITION_ENTRY_SIZE];
+    let part = Partition {
+        part_type: entry[4],
+        start_lba: u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize,
+        sectors: u32::from_le_bytes(entry[12..16].try_into().unwrap()) as usize,
+    };
+    match part.part_type {
+        0 => None,
+        _ => Some(part),
+    }
+}
@@ -1,73 +1,54 @@
-use alloc::boxed::Box;
-
-use fdt::Fdt;
-use lazy_static::lazy_static;
-
 #[allow(unused_imports)]
-pub use console::{gets, puts};
+pub use console::{early_console, gets, puts, take_interrupt};
 
-use crate::arch::BOOT_ARGS;
-use crate::common::sync::Mutex;
-use crate::devices::{virtio::blk::VirtIOBlk, virtio::VirtioBlkTrans};
-use crate::devices::pci::bus::PCIBus;
+use crate::devices::driver::block_device;
 use crate::pr_notice;
 
 mod console;
+pub mod driver;
+pub mod mbr;
 pub mod pci;
 mod uart;
 mod virtio;
 #[macro_use]
 mod macros;
 
-lazy_static! {
-    static ref DTB: Fdt<'static> = {
-        let dtb =
-            unsafe { Fdt::from_ptr(BOOT_ARGS[0].into_vaddr().as_usize() as *const u8) }.unwrap();
-        if let Some(platform) = dtb.find_node("/platform-bus") {
-            pr_notice!(
-                "Model: {}, Platform: {}\n",
-                dtb.root().model(),
-                platform.compatible().unwrap().first()
-            )
-        }
-        dtb
+fn blk_info() {
+    let blk = match block_device(None) {
+        None => return pr_notice!("No disk\n"),
+        Some(blk) => blk,
     };
+    pr_notice!("Disk size: {}MB ", blk.capacity() / 1024 / 1024);
+    let mut buffer = [0u8; 512];
+    if blk.block_size() == buffer.len() && blk.read_block(0, &mut buffer) && buffer.ends_with(&[0x55, 0xAA]) {
+        pr_notice!("DOS/MBR boot sector");
+    }
+    pr_notice!("\n");
 }
-lazy_static! {
-    static ref PCI_BUS: Mutex<PCIBus> =
-        Mutex::new(PCIBus::from_fdt(&DTB.find_node("/pcie").unwrap()));
+
+//the first block device, 0 and false without one
+pub fn block_size() -> usize {
+    block_device(None).map_or(0, |blk| blk.block_size())
 }
-lazy_static! {
-    static ref VIRT_BLK: Mutex<&'static mut VirtIOBlk> = {
-        let trans = Box::leak(Box::new(
-            VirtioBlkTrans::from_pci(PCI_BUS.lock().get_mut())
-                .unwrap_or_else(|| panic!("virt blk pci not found!")),
-        ));
-        let virt_blk = Box::leak(Box::new(
-            VirtIOBlk::new(trans).unwrap_or_else(|e| panic!("virt blk init failed: {:?}!", e)),
-        ));
-        Mutex::new(virt_blk)
-    };
+
+pub fn read_block(block_id: usize, buf: &mut [u8]) -> bool {
+    block_device(None).is_some_and(|blk| blk.read_block(block_id, buf))
 }
 
-fn blk_info() {
-    match VIRT_BLK.lock() {
-        mut blk => {
-            pr_notice!(
-                "Disk size: {}MB ",
-                blk.capacity * blk.blk_size / 1024 / 1024
-            );
-            let mut buffer = [0u8; 512];
-            blk.get_mut().read_block(0, &mut buffer).unwrap();
-            if buffer.ends_with(&[0x55, 0xAA]) {
-                pr_notice!("DOS/MBR boot sector");
-            }
-            pr_notice!("\n");
-        }
-    }
+#[allow(dead_code)]
+pub fn write_block(block_id: usize, buf: &[u8]) -> bool {
+    block_device(None).is_some_and(|blk| blk.write_block(block_id, buf))
+}
+
+pub fn read_block_atomic(block_id: usize, buf: &mut [u8]) -> bool {
+    block_device(None).is_some_and(|blk| blk.read_block_atomic(block_id, buf))
+}
+
+pub fn write_block_atomic(block_id: usize, buf: &[u8]) -> bool {
+    block_device(None).is_some_and(|blk| blk.write_block_atomic(block_id, buf))
 }
 
 pub fn init() {
-    console::setup_console();
+    driver::probe_all();
     blk_info();
 }
@@ -44,7 +44,7 @@ impl Debug for Bar {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum BarType {
     Memory = 0,
     IO = 1,
@@ -63,6 +63,7 @@ impl TryFromValue<u32> for BarType {
 }
 
 impl Bar {
+    //addr is the address programmed before, the low half of a 64 bit one
     pub fn from_addr(addr: usize) -> Self {
         let reg = unsafe { &*(addr as *mut BarReg) };
         let old = reg.0.get();
@@ -72,9 +73,11 @@ impl Bar {
             BarType::Memory => reg.0.get() & 0xFFFFFFF0,
             BarType::IO => reg.0.get() & 0xFFFFFFFC,
         };
-        let mem_size = match mem {
-            0 => 0,
-            _ => (!(mem << 4)) + 1,
+        //16 bit I/O decoders leave the high half zero
+        let mem_size = match (bar_type, mem) {
+            (_, 0) => 0,
+            (BarType::IO, mem) if mem >> 16 == 0 => (!(mem | 0xFFFF0000)).wrapping_add(1),
+            (_, mem) => (!mem).wrapping_add(1),
         };
 
         let bar = Self {
@@ -91,11 +94,17 @@ impl Bar {
                 _ => unreachable!(),
             },
             mem_size: mem_size as usize,
-            addr: 0,
+            addr: match bar_type {
+                BarType::Memory => old & 0xFFFFFFF0,
+                BarType::IO => old & 0xFFFFFFFC,
+            } as usize,
         };
         reg.0.set(old);
         bar
     }
+    pub fn raw(&self) -> u32 {
+        self.reg.0.get()
+    }
     pub fn setup(&mut self, address: u32, cpu_address: usize) {
         self.reg.0.set(address);
         self.addr = cpu_address;
@@ -1,13 +1,25 @@
-use core::fmt::{Debug, Formatter};
+#![allow(dead_code)]
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Display, Formatter};
 
 use arrayvec::{ArrayString, ArrayVec};
 use fdt::node::FdtNode;
 
-use crate::devices::macros::fdt_get;
-use crate::devices::pci::pci::Header0;
-use crate::mm::PhyAddr;
+use crate::arch::dtb::gic_irq;
+use crate::arch::IntId;
+use crate::common::errno::{Errno, SysResult};
+use crate::common::MMIO;
+use crate::devices::driver::{add_pci_bus, device_name, Device, DeviceClass, DeviceId, Driver, ProbeInfo};
+use crate::devices::macros::{fdt_get, pci_addr};
+use crate::devices::pci::bar::{Bar, BarType};
+use crate::devices::pci::ids::{dev_type, find};
+use crate::devices::pci::pci::{Head, Header0};
+use crate::mm::{ioremap, PhyAddr, PAGE_SIZE};
+use crate::{pr_err, pr_notice, register_driver};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(u8)]
 pub enum PciMemType {
     Config = 0b00,
@@ -41,57 +53,183 @@ impl From<PciMemType> for u8 {
 
 pub type PciAddr = usize;
 
+//a window of the host bridge, bars get space in it
 #[derive(Copy, Clone)]
 pub struct PciMem {
     pub pci_addr: PciAddr,
     pub phy_addr: PhyAddr,
     pub size: usize,
     pub pci_type: PciMemType,
+    pub prefetch: bool,
     used_size: usize,
 }
 
 impl Debug for PciMem {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.write_fmt(format_args!(
-            "PciMem: {{ {:#x}, {:#x}, {:#x} {:?} }}",
-            self.pci_addr, self.phy_addr, self.size, self.pci_type
+            "PciMem: {{ {:#x}, {:#x}, {:#x} {:?}{} }}",
+            self.pci_addr,
+            self.phy_addr,
+            self.size,
+            self.pci_type,
+            if self.prefetch { " prefetchable" } else { "" }
         ))
     }
 }
 
 impl PciMem {
+    //move the next allocation up to align, a power of two
+    pub fn align(&mut self, align: usize) -> PciAddr {
+        let next = self.pci_addr + self.used_size;
+        self.used_size = ((next + align - 1) & !(align - 1)) - self.pci_addr;
+        self.pci_addr + self.used_size
+    }
+    //size aligned space, size is a power of two. The cpu address is the same offset
+    //into the window on the cpu side
     pub fn alloc(&mut self, size: usize) -> Option<(PciAddr, PhyAddr)> {
-        let ret = (
-            PciAddr::from(self.pci_addr + self.used_size),
-            PhyAddr::new(self.pci_addr + self.used_size),
-        );
-        self.used_size += size;
-        if self.used_size >= self.size {
+        let used_size = self.used_size;
+        let start = self.align(size);
+        if start - self.pci_addr + size > self.size {
+            self.used_size = used_size;
             return None;
         }
-        Some(ret)
+        self.used_size += size;
+        Some((start, PhyAddr::new(self.phy_addr.as_usize() + start - self.pci_addr)))
     }
 }
 
+//an interrupt-map entry: the INTx pin of a device on the root bus and the GIC
+//interrupt it is wired to
 #[derive(Clone, Copy, Debug, Default)]
 pub struct InterruptMap {
-    device: u32,
-    specifier: u32,
+    //phys.hi of the child unit address: bus, device and function
+    address: u32,
+    pin: u32,
     parent: u32,
-    parent_address: u32,
+    //type (0 SPI, 1 PPI), number and flags
     parent_specifier: [u32; 3],
 }
 
+impl InterruptMap {
+    fn irq(&self) -> Option<IntId> {
+        gic_irq(self.parent_specifier)
+    }
+}
+
+//an assigned base address register
+#[derive(Debug, Copy, Clone)]
+pub struct PciBar {
+    pub bar_type: BarType,
+    pub bits: u8,
+    pub prefetch: bool,
+    pub pci_addr: PciAddr,
+    pub phy_addr: PhyAddr,
+    pub size: usize,
+}
+
+impl Display for PciBar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.bar_type {
+            BarType::IO => write!(f, "I/O ports at {:#x}", self.pci_addr)?,
+            BarType::Memory => write!(
+                f,
+                "Memory at {:#x} ({}-bit, {})",
+                self.phy_addr.as_usize(),
+                self.bits,
+                if self.prefetch { "prefetchable" } else { "non-prefetchable" }
+            )?,
+        }
+        match self.size {
+            size if size >= 1 << 20 => write!(f, " [size={}M]", size >> 20),
+            size if size >= 1 << 10 => write!(f, " [size={}K]", size >> 10),
+            size => write!(f, " [size={}]", size),
+        }
+    }
+}
+
+//a function found by PCIBus::enumerate
+#[derive(Debug, Copy, Clone)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub func: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class_code: u8,
+    pub sub_class: u8,
+    pub prog_if: u8,
+    //without the multi-function bit
+    pub header_type: u8,
+    //the bus behind a bridge
+    pub secondary: u8,
+    //INTx pin 1-4 for INTA-INTD, 0 for none
+    pub pin: u8,
+    //where the pin is routed to
+    pub irq: Option<IntId>,
+    //indexed like the registers, the high half of a 64 bit one is None
+    pub bars: [Option<PciBar>; 6],
+}
+
+impl PciDevice {
+    pub fn is_bridge(&self) -> bool {
+        self.header_type == HEADER_BRIDGE
+    }
+}
+
+impl Display for PciDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let (v, d) = find(self.vendor_id, self.device_id);
+        write!(
+            f,
+            "{:02x}:{:02x}.{} {} {:#04x}:{:#04x} [ {} {} {:#06x}:{:#06x} ]",
+            self.bus,
+            self.device,
+            self.func,
+            dev_type(self.class_code, self.sub_class),
+            self.class_code,
+            self.sub_class,
+            v,
+            d,
+            self.vendor_id,
+            self.device_id
+        )
+    }
+}
+
+const HEADER_BRIDGE: u8 = 1;
+const HEADER_MULTI_FUNCTION: u8 = 0x80;
+//bridge windows are 1M aligned, I/O ones 4K
+const WINDOW_ALIGN: usize = 0x10_0000;
+const IO_WINDOW_ALIGN: usize = 0x1000;
+
+//the bits of a bar that stick after writing all ones
+fn probe(reg: &MMIO) -> u32 {
+    let old: u32 = reg.read();
+    reg.write(u32::MAX);
+    let mask = reg.read();
+    reg.write(old);
+    mask
+}
+
+fn map_bar(phy_addr: PhyAddr, size: usize) {
+    if let Err(errno) = ioremap(phy_addr, size) {
+        pr_err!("PCI: mapping a bar at {:#x} failed: {:?}\n", phy_addr.as_usize(), errno);
+    }
+}
+
 #[derive(Clone)]
 pub struct PCIBus {
     pub reg: usize,
     pub reg_size: usize,
-    pub mem: [PciMem; 3],
+    pub mem: ArrayVec<PciMem, 8>,
     pub name: ArrayString<64>,
     pub compatible: ArrayString<64>,
     pub device_type: ArrayString<64>,
     pub bus_range: [u32; 2],
     pub interrupt_map: ArrayVec<InterruptMap, 16>,
+    //phys.hi and pin bits that select an interrupt-map entry
+    pub interrupt_map_mask: [u32; 2],
+    pub devices: Vec<PciDevice>,
 }
 
 impl Debug for PCIBus {
@@ -107,18 +245,14 @@ impl PCIBus {
         Self {
             reg: 0,
             reg_size: 0,
-            mem: [PciMem {
-                pci_addr: 0,
-                phy_addr: PhyAddr::new(0),
-                size: 0,
-                pci_type: PciMemType::Config,
-                used_size: 0,
-            }; 3],
+            mem: ArrayVec::new(),
             name: ArrayString::new_const(),
             compatible: ArrayString::new_const(),
             device_type: ArrayString::new_const(),
             bus_range: [0; 2],
             interrupt_map: ArrayVec::new(),
+            interrupt_map_mask: [u32::MAX; 2],
+            devices: Vec::new(),
         }
     }
     pub fn from_fdt(node: &FdtNode) -> PCIBus {
@@ -132,20 +266,22 @@ impl PCIBus {
         pci.reg = fdt_get!(data_slice, usize);
 
         pci.reg_size = fdt_get!(data_slice, usize);
+        //3 cells of pci address, 2 of cpu address and 2 of size each
         data_slice = node.property("ranges").unwrap().value;
-        for i in 0..3 {
+        while data_slice.len() >= 28 && !pci.mem.is_full() {
             let pci_type = fdt_get!(data_slice, u32);
             let pci_addr = fdt_get!(data_slice, usize);
             let phy_addr = fdt_get!(data_slice, usize);
             let size = fdt_get!(data_slice, usize);
 
-            pci.mem[i] = PciMem {
+            pci.mem.push(PciMem {
                 pci_type: PciMemType::from(pci_type),
+                prefetch: pci_type & (1 << 30) != 0,
                 pci_addr,
                 phy_addr: PhyAddr::new(phy_addr),
                 size,
                 used_size: 0,
-            };
+            });
         }
         data_slice = node.property("bus-range").unwrap().value;
         pci.bus_range[0] = fdt_get!(data_slice,u32);
@@ -153,50 +289,344 @@ impl PCIBus {
         pci.fetch_interrupt(&node);
         pci
     }
+    //3 cells of child address, 1 of pin, the parent phandle, 2 cells of parent
+    //address and 3 of GIC interrupt per entry
     pub fn fetch_interrupt(&mut self, node: &FdtNode) {
-        match node.property("interrupt-map") {
-            None => {}
-            Some(interrupt_map) => {
-                let mut map = InterruptMap {
-                    device: 0,
-                    specifier: 0,
-                    parent: 0,
-                    parent_address: 0,
-                    parent_specifier: [0, 0, 0],
+        if let Some(mask) = node.property("interrupt-map-mask") {
+            let mut value = mask.value;
+            if value.len() >= 16 {
+                self.interrupt_map_mask[0] = fdt_get!(value, u32);
+                let _ = fdt_get!(value, u64);
+                self.interrupt_map_mask[1] = fdt_get!(value, u32);
+            }
+        }
+        let mut interrupt_value = match node.property("interrupt-map") {
+            None => return,
+            Some(interrupt_map) => interrupt_map.value,
+        };
+        while interrupt_value.len() >= 40 && !self.interrupt_map.is_full() {
+            let address = fdt_get!(interrupt_value, u32);
+            let _ = fdt_get!(interrupt_value, u64);
+            let pin = fdt_get!(interrupt_value, u32);
+            let parent = fdt_get!(interrupt_value, u32);
+            let _ = fdt_get!(interrupt_value, u64);
+            self.interrupt_map.push(InterruptMap {
+                address,
+                pin,
+                parent,
+                parent_specifier: [
+                    fdt_get!(interrupt_value, u32),
+                    fdt_get!(interrupt_value, u32),
+                    fdt_get!(interrupt_value, u32),
+                ],
+            });
+        }
+    }
+
+    //the GIC interrupt of the INTx pin of dev. Behind a bridge the pin is rotated by
+    //the device number, hop by hop up to the root bus where interrupt-map has it
+    pub fn intx_irq(&self, dev: &PciDevice) -> Option<IntId> {
+        if !(1..=4).contains(&dev.pin) {
+            return None;
+        }
+        let (mut bus, mut device, mut func, mut pin) = (dev.bus, dev.device, dev.func, dev.pin as u32);
+        while bus as u32 != self.bus_range[0] {
+            let bridge = self
+                .devices
+                .iter()
+                .find(|bridge| bridge.is_bridge() && bridge.secondary == bus)?;
+            pin = (pin - 1 + device as u32) % 4 + 1;
+            (bus, device, func) = (bridge.bus, bridge.device, bridge.func);
+        }
+        let address = (bus as u32) << 16 | (device as u32) << 11 | (func as u32) << 8;
+        let [address_mask, pin_mask] = self.interrupt_map_mask;
+        self.interrupt_map
+            .iter()
+            .find(|entry| {
+                entry.address & address_mask == address & address_mask && entry.pin & pin_mask == pin & pin_mask
+            })
+            .and_then(|entry| entry.irq())
+    }
+
+    fn window(&mut self, pci_type: PciMemType) -> Option<&mut PciMem> {
+        self.mem.iter_mut().find(|mem| mem.pci_type == pci_type && mem.size != 0)
+    }
+    //ECAM has 1M of config space per bus
+    fn map_config(&self, bus: u8) {
+        let start = self.reg + ((bus as usize) << 20);
+        if let Err(errno) = ioremap(PhyAddr::new(start), 1 << 20) {
+            pr_err!("PCI: mapping the config space of bus {} failed: {:?}\n", bus, errno);
+        }
+    }
+
+    //space for a bar in the first window of the types that has room, a prefetchable
+    //window only takes prefetchable bars
+    fn alloc(&mut self, types: &[PciMemType], size: usize, prefetch: bool) -> Option<(PciAddr, PhyAddr)> {
+        types.iter().find_map(|pci_type| {
+            self.mem
+                .iter_mut()
+                .filter(|mem| mem.pci_type == *pci_type && (prefetch || !mem.prefetch))
+                .find_map(|mem| mem.alloc(size))
+        })
+    }
+
+    //walk every bus reachable from the first one of bus_range: all functions of
+    //multi-function devices, buses behind bridges numbered depth first. Bars are
+    //assigned on the way, so the bridge windows can cover what is behind them
+    pub fn enumerate(&mut self) {
+        self.devices.clear();
+        //port accesses go through a memory window, map it whole
+        for io in self.mem.iter().filter(|mem| mem.pci_type == PciMemType::IOSpace) {
+            if let Err(errno) = ioremap(io.phy_addr, io.size) {
+                pr_err!("PCI: mapping the I/O window failed: {:?}\n", errno);
+            }
+        }
+        let first = self.bus_range[0] as u8;
+        let mut last_bus = first;
+        self.scan_bus(first, &mut last_bus);
+        //bridges are all known now
+        for index in 0..self.devices.len() {
+            let dev = self.devices[index];
+            self.devices[index].irq = self.intx_irq(&dev);
+            if let Some(irq) = self.devices[index].irq {
+                //informational only, 0xff for a line that does not fit
+                let line = u8::try_from(irq.0).unwrap_or(u8::MAX);
+                MMIO::new(pci_addr!(self.reg, dev.bus, dev.device, dev.func)).offset(0x3c).write::<u8>(line);
+            }
+        }
+    }
+    fn scan_bus(&mut self, bus: u8, last_bus: &mut u8) {
+        self.map_config(bus);
+        for device in 0..32 {
+            for func in 0..8 {
+                let addr = pci_addr!(self.reg, bus, device, func);
+                let head = Head::read_header(addr);
+                if head.vendor_id == u16::MAX {
+                    //functions need not be contiguous, but function 0 is always there
+                    match func {
+                        0 => break,
+                        _ => continue,
+                    }
+                }
+                let mut dev = PciDevice {
+                    bus,
+                    device,
+                    func,
+                    vendor_id: head.vendor_id,
+                    device_id: head.device_id,
+                    class_code: head.class_code,
+                    sub_class: head.sub_class,
+                    prog_if: head.prog_if,
+                    header_type: head.header_type & !HEADER_MULTI_FUNCTION,
+                    secondary: 0,
+                    pin: MMIO::new(addr).offset(0x3d).read(),
+                    irq: None,
+                    bars: [None; 6],
                 };
-                let mut interrupt_value = interrupt_map.value;
-                while !interrupt_value.is_empty() {
-                    let value = fdt_get!(interrupt_value, u64);
-                    map.device = (value >> (32 + 11)) as u32;
-                    let value = fdt_get!(interrupt_value, u64);
-                    map.specifier = value as u32;
-
-                    let value = fdt_get!(interrupt_value, u32);
-                    map.parent = value;
-                    let value = fdt_get!(interrupt_value, u64);
-                    map.parent_address = value as u32;
-                    map.parent_specifier = [
-                        fdt_get!(interrupt_value, u32),
-                        fdt_get!(interrupt_value, u32),
-                        fdt_get!(interrupt_value, u32),
-                    ];
-                    self.interrupt_map.push(map);
+                match dev.header_type {
+                    HEADER_BRIDGE => {
+                        self.assign_bars(addr, &mut dev.bars[..2]);
+                        dev.secondary = self.scan_bridge(addr, bus, last_bus);
+                    }
+                    0 => self.assign_bars(addr, &mut dev.bars),
+                    other => pr_err!("PCI: {} unknown header type {}\n", dev, other),
+                }
+                self.devices.push(dev);
+                if func == 0 && head.header_type & HEADER_MULTI_FUNCTION == 0 {
+                    break;
                 }
             }
         }
     }
+    //number the bus behind the bridge, scan it and open the windows over the bars
+    //assigned there, returns the secondary bus
+    fn scan_bridge(&mut self, addr: usize, bus: u8, last_bus: &mut u8) -> u8 {
+        let config = MMIO::new(addr);
+        if *last_bus as u32 >= self.bus_range[1] {
+            pr_err!("PCI: no bus number left for a bridge on bus {}\n", bus);
+            return 0;
+        }
+        *last_bus += 1;
+        let secondary = *last_bus;
+        config.offset(0x18).write::<u8>(bus);
+        config.offset(0x19).write::<u8>(secondary);
+        //everything below until the scan knows better
+        config.offset(0x1a).write::<u8>(self.bus_range[1] as u8);
+        //only the first window of each type is forwarded
+        let io = self.window(PciMemType::IOSpace).map(|mem| mem.align(IO_WINDOW_ALIGN));
+        let mem = self.window(PciMemType::MemorySpace32).map(|mem| mem.align(WINDOW_ALIGN));
+        let pref = self.window(PciMemType::MemorySpace64).map(|mem| mem.align(WINDOW_ALIGN));
 
-    pub fn find_device(&self, vendor_id: u16, device_id: u16) -> Option<Header0> {
-        for device in 0u8..255 {
-            match Header0::new(self.reg, 0, device, 0) {
-                None => return None,
-                Some(pci) => {
-                    if pci.header.vendor_id == vendor_id && pci.header.device_id == device_id {
-                        return Some(pci);
+        self.scan_bus(secondary, last_bus);
+        config.offset(0x1a).write::<u8>(*last_bus);
+
+        let io_end = self.window(PciMemType::IOSpace).map(|mem| mem.align(IO_WINDOW_ALIGN));
+        let mem_end = self.window(PciMemType::MemorySpace32).map(|mem| mem.align(WINDOW_ALIGN));
+        let pref_end = self.window(PciMemType::MemorySpace64).map(|mem| mem.align(WINDOW_ALIGN));
+        //base above limit closes a window
+        let (base, limit) = match (io, io_end) {
+            (Some(start), Some(end)) if end > start => (start, end - 1),
+            _ => (0xf000, 0),
+        };
+        config.offset(0x1c).write::<u8>((base >> 8) as u8 & 0xf0);
+        config.offset(0x1d).write::<u8>((limit >> 8) as u8 & 0xf0);
+        config.offset(0x30).write::<u16>((base >> 16) as u16);
+        config.offset(0x32).write::<u16>((limit >> 16) as u16);
+        let (base, limit) = match (mem, mem_end) {
+            (Some(start), Some(end)) if end > start => (start, end - 1),
+            _ => (0xfff0_0000, 0),
+        };
+        config.offset(0x20).write::<u16>((base >> 16) as u16 & 0xfff0);
+        config.offset(0x22).write::<u16>((limit >> 16) as u16 & 0xfff0);
+        let (base, limit) = match (pref, pref_end) {
+            (Some(start), Some(end)) if end > start => (start, end - 1),
+            _ => (0xfff0_0000, 0),
+        };
+        config.offset(0x24).write::<u16>((base >> 16) as u16 & 0xfff0);
+        config.offset(0x26).write::<u16>((limit >> 16) as u16 & 0xfff0);
+        config.offset(0x28).write::<u32>((base >> 32) as u32);
+        config.offset(0x2c).write::<u32>((limit >> 32) as u32);
+        //forward I/O, memory and bus master cycles
+        let command: u16 = config.offset(0x4).read();
+        config.offset(0x4).write::<u16>(command | 0x7);
+        secondary
+    }
+    //size the bars and give them space in a matching window, decoding is off
+    //meanwhile. Prefetchable 64 bit ones go above 4G, the 64 bit window is the
+    //prefetchable one of the bridges so the others stay below. Memory is mapped for
+    //the kernel. I/O or memory decode is turned on when every bar of that kind got space
+    fn assign_bars(&mut self, addr: usize, bars: &mut [Option<PciBar>]) {
+        let config = MMIO::new(addr);
+        let command: u16 = config.offset(0x4).read();
+        config.offset(0x4).write::<u16>(command & !0x3);
+        let (mut decode, mut unassigned) = (0u16, 0u16);
+        let mut id = 0;
+        while id < bars.len() {
+            let mut bar = Bar::from_addr(addr + 0x10 + id * 4);
+            let bits = match bar.bar_type {
+                BarType::Memory if bar.mem_bits == 64 && id + 1 < bars.len() => 64,
+                _ => 32,
+            };
+            let size = match bits {
+                64 => {
+                    let low = probe(&config.offset(0x10 + id * 4)) & !0xf;
+                    let high = probe(&config.offset(0x14 + id * 4));
+                    match ((high as usize) << 32) | low as usize {
+                        0 => 0,
+                        mask => (!mask).wrapping_add(1),
                     }
                 }
+                _ => bar.mem_size,
+            };
+            id += bits / 32;
+            if size == 0 {
+                continue;
+            }
+            let types: &[PciMemType] = match bar.bar_type {
+                BarType::IO => &[PciMemType::IOSpace],
+                BarType::Memory if bits == 64 && bar.prefetch => {
+                    &[PciMemType::MemorySpace64, PciMemType::MemorySpace32]
+                }
+                BarType::Memory => &[PciMemType::MemorySpace32],
+            };
+            let enable = match bar.bar_type {
+                BarType::IO => 0x1,
+                BarType::Memory => 0x2,
+            };
+            //memory bars get whole pages of their own
+            let space = match bar.bar_type {
+                BarType::IO => size,
+                BarType::Memory => size.max(PAGE_SIZE),
+            };
+            let (pci_addr, phy_addr) = match self.alloc(types, space, bar.prefetch) {
+                None => {
+                    pr_err!("PCI: no space for a {:#x} bar\n", size);
+                    unassigned |= enable;
+                    continue;
+                }
+                Some(address) => address,
+            };
+            decode |= enable;
+            let reg = id - bits / 32;
+            bar.setup(pci_addr as u32, phy_addr.as_usize());
+            if bits == 64 {
+                config.offset(0x14 + reg * 4).write::<u32>((pci_addr >> 32) as u32);
+            }
+            if matches!(bar.bar_type, BarType::Memory) {
+                map_bar(phy_addr, space);
+            }
+            bars[reg] = Some(PciBar {
+                bar_type: bar.bar_type,
+                bits: bits as u8,
+                prefetch: bar.prefetch,
+                pci_addr,
+                phy_addr,
+                size,
+            });
+        }
+        config.offset(0x4).write::<u16>(command & !0x3 | decode & !unassigned);
+    }
+
+    //lspci -v like dump of the enumerated devices
+    pub fn lspci(&self) {
+        for dev in self.devices.iter() {
+            pr_notice!("{}\n", dev);
+            if dev.is_bridge() {
+                pr_notice!("\tBus: primary={:02x}, secondary={:02x}\n", dev.bus, dev.secondary);
+            }
+            if let Some(irq) = dev.irq {
+                pr_notice!("\tInterrupt: pin {} routed to IRQ {}\n", (b'A' + dev.pin - 1) as char, irq.0);
+            }
+            for (id, bar) in dev.bars.iter().enumerate() {
+                if let Some(bar) = bar {
+                    pr_notice!("\tRegion {}: {}\n", id, bar);
+                }
             }
         }
-        return None;
     }
+
+    pub fn devices(&self) -> &[PciDevice] {
+        &self.devices
+    }
+    pub fn find_class(&self, class_code: u8, sub_class: u8) -> impl Iterator<Item = &PciDevice> {
+        self.devices
+            .iter()
+            .filter(move |dev| dev.class_code == class_code && dev.sub_class == sub_class)
+    }
+    //the first enumerated function with these ids, bar addresses are cpu addresses
+    pub fn find_device(&self, vendor_id: u16, device_id: u16) -> Option<Header0> {
+        let dev = self
+            .devices
+            .iter()
+            .find(|dev| dev.vendor_id == vendor_id && dev.device_id == device_id)?;
+        self.header(dev)
+    }
+    //the config header of an enumerated function, bar addresses are cpu addresses
+    pub fn header(&self, dev: &PciDevice) -> Option<Header0> {
+        let mut header = Header0::new(self.reg, dev.bus, dev.device, dev.func)?;
+        for (reg, bar) in header.base_address_reg.iter_mut().zip(dev.bars.iter()) {
+            reg.addr = bar.map_or(0, |bar| bar.phy_addr.as_usize());
+        }
+        Some(header)
+    }
+}
+
+static PCI_HOST_DRIVER: Driver = Driver {
+    name: "pci-host-ecam",
+    ids: &[DeviceId::Compatible("pci-host-ecam-generic")],
+    probe: pci_host_probe,
+};
+register_driver!(PCI_HOST_DRIVER);
+
+//enumerate the host bridge, it stays for good and its functions are probed next
+fn pci_host_probe(info: &ProbeInfo) -> SysResult<Device> {
+    let node = match info {
+        ProbeInfo::Fdt(node) => node,
+        _ => return Err(Errno::ENODEV),
+    };
+    let mut bus = PCIBus::from_fdt(node);
+    bus.enumerate();
+    bus.lspci();
+    add_pci_bus(Box::leak(Box::new(bus)));
+    Ok(Device::new(device_name("pci"), DeviceClass::Bus))
 }
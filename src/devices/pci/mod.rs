@@ -1,5 +1,6 @@
 mod bar;
 pub mod ids;
+pub mod msi;
 pub mod pci;
 pub mod bus;
 
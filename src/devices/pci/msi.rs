@@ -0,0 +1,164 @@
+#![allow(dead_code)]
+
+use fdt::node::FdtNode;
+use tock_registers::interfaces::{Readable, Writeable};
+
+use crate::arch::{enable_irq, setup_irq, HandlerFn, IntId, Trigger};
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::common::MMIO;
+use crate::devices::driver::{device_name, Device, DeviceClass, DeviceId, Driver, ProbeInfo};
+use crate::devices::pci::pci::{CapMSIX, Header0, MsixTable};
+use crate::mm::{ioremap, PhyAddr, PAGE_SIZE};
+use crate::{pr_err, pr_notice, register_driver};
+
+//GICv2 has no ITS. A GICv2m frame turns a write of a SPI number to its MSI_SETSPI_NS
+//register into that edge triggered SPI, every MSI-X vector is one of its SPIs
+const MSI_TYPER: usize = 0x8;
+const MSI_SETSPI_NS: usize = 0x40;
+const MAX_SPIS: usize = 1024;
+
+struct V2m {
+    phy_base: usize,
+    spi_base: u32,
+    spi_count: u32,
+    used: [u64; MAX_SPIS / 64],
+}
+
+static V2M: Mutex<Option<V2m>> = Mutex::new(None);
+
+static V2M_DRIVER: Driver = Driver {
+    name: "gicv2m",
+    ids: &[DeviceId::Compatible("arm,gic-v2m-frame")],
+    probe: v2m_probe,
+};
+register_driver!(V2M_DRIVER);
+
+fn v2m_probe(info: &ProbeInfo) -> SysResult<Device> {
+    match info {
+        ProbeInfo::Fdt(node) => init(node)?,
+        _ => return Err(Errno::ENODEV),
+    }
+    Ok(Device::new(device_name("v2m"), DeviceClass::Interrupt))
+}
+
+//the arm,gic-v2m-frame node, its SPI range from the properties or MSI_TYPER
+pub fn init(node: &FdtNode) -> SysResult<()> {
+    let reg = match node.reg().and_then(|mut reg| reg.next()) {
+        None => {
+            pr_err!("MSI: {} has no reg\n", node.name);
+            return Err(Errno::ENODEV);
+        }
+        Some(reg) => reg,
+    };
+    let phy_base = reg.starting_address as usize;
+    let va = ioremap(PhyAddr::new(phy_base), PAGE_SIZE)?;
+    let typer = MMIO::new(va.as_usize()).offset(MSI_TYPER).read::<u32>();
+    let property = |name| node.property(name).and_then(|value| value.as_usize()).map(|value| value as u32);
+    let spi_base = property("arm,msi-base-spi").unwrap_or((typer >> 16) & 0x3ff);
+    let spi_count = property("arm,msi-num-spis").unwrap_or(typer & 0x3ff).min(MAX_SPIS as u32);
+    pr_notice!("MSI: GICv2m frame at {:#x}, SPIs {}..{}\n", phy_base, spi_base, spi_base + spi_count);
+    V2M.lock().replace(V2m {
+        phy_base,
+        spi_base,
+        spi_count,
+        used: [0; MAX_SPIS / 64],
+    });
+    Ok(())
+}
+
+pub fn available() -> bool {
+    V2M.lock().is_some()
+}
+
+//a free SPI of the frame and the doorbell a device writes its number to
+pub fn alloc_vector() -> Option<(IntId, usize)> {
+    let mut v2m = V2M.lock();
+    let v2m = v2m.as_mut()?;
+    let index = (0..v2m.spi_count as usize).find(|index| v2m.used[index / 64] & (1 << (index % 64)) == 0)?;
+    v2m.used[index / 64] |= 1 << (index % 64);
+    Some((IntId(v2m.spi_base + index as u32), v2m.phy_base + MSI_SETSPI_NS))
+}
+
+pub fn free_vector(irq: IntId) {
+    if let Some(v2m) = V2M.lock().as_mut() {
+        if let Some(index) = irq.0.checked_sub(v2m.spi_base).filter(|index| *index < v2m.spi_count) {
+            v2m.used[index as usize / 64] &= !(1 << (index % 64));
+        }
+    }
+}
+
+//the MSI-X table of a function. Entries start out masked, an entry gets a vector
+//with setup_vector
+#[derive(Copy, Clone)]
+pub struct Msix {
+    cap: CapMSIX,
+    table: usize,
+    size: usize,
+}
+
+unsafe impl Send for Msix {}
+
+impl Msix {
+    //the bar holding the table has to be assigned, the table is mapped here
+    pub fn from_header(header: &Header0) -> Option<Self> {
+        let offset = header.find_cap(CapMSIX::ID)?;
+        let cap = CapMSIX::from_addr(header.base_addr, offset);
+        let bar = header.base_address_reg.get(cap.bar as usize)?;
+        if bar.addr == 0 {
+            pr_err!("MSI: the MSI-X table bar {} is not assigned\n", cap.bar);
+            return None;
+        }
+        let size = cap.table_size();
+        let table = PhyAddr::new(bar.addr + cap.table_offset as usize);
+        let table = match ioremap(table, size * core::mem::size_of::<MsixTable>()) {
+            Ok(table) => table,
+            Err(errno) => {
+                pr_err!("MSI: mapping the MSI-X table failed: {:?}\n", errno);
+                return None;
+            }
+        };
+        Some(Self { cap, table: table.as_usize(), size })
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    fn entry(&self, entry: usize) -> &'static MsixTable {
+        assert!(entry < self.size);
+        unsafe { &*((self.table + entry * core::mem::size_of::<MsixTable>()) as *const MsixTable) }
+    }
+    pub fn enable(&self) {
+        self.cap.set_enable(true)
+    }
+    pub fn disable(&self) {
+        self.cap.set_enable(false)
+    }
+    pub fn mask(&self, entry: usize) {
+        let control = self.entry(entry).vector_control.get();
+        self.entry(entry).vector_control.set(control | 1);
+    }
+    pub fn unmask(&self, entry: usize) {
+        let control = self.entry(entry).vector_control.get();
+        self.entry(entry).vector_control.set(control & !1);
+    }
+    //route entry to a new SPI with handler and unmask it
+    pub fn setup_vector(&self, entry: usize, handler: HandlerFn) -> Option<IntId> {
+        if entry >= self.size {
+            return None;
+        }
+        let (irq, doorbell) = alloc_vector()?;
+        setup_irq(irq, Trigger::Edge, handler);
+        let table = self.entry(entry);
+        self.mask(entry);
+        table.message_address_low.set(doorbell as u32);
+        table.message_address_high.set((doorbell >> 32) as u32);
+        table.message_data.set(irq.0);
+        self.unmask(entry);
+        Some(irq)
+    }
+    pub fn free_vector(&self, entry: usize, irq: IntId) {
+        self.mask(entry);
+        enable_irq(irq, false);
+        free_vector(irq);
+    }
+}
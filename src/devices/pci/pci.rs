@@ -1,13 +1,13 @@
 use core::fmt::{Debug, Display, Formatter};
 
 use bitflags::bitflags;
+use tock_registers::interfaces::{ReadWriteable, Readable};
 use tock_registers::register_bitfields;
 use tock_registers::registers::ReadWrite;
 
 use crate::common::MMIO;
 use crate::devices::macros::pci_addr;
 use crate::devices::pci::bar::BarType;
-use crate::mm::PhyAddr;
 
 ///https://wiki.osdev.org/PCI
 use super::{
@@ -23,7 +23,7 @@ pub struct Head {
     command: *mut u16,
     pub status: *mut u16,
     revision_id: u8,
-    prog_if: u8,
+    pub prog_if: u8,
     pub sub_class: u8,
     pub class_code: u8,
     cache_line_size: u8,
@@ -120,16 +120,24 @@ impl Header0 {
                 base.offset(0x34).read::<u8>() as usize,
             )),
         };
+        let mut base_address_reg = [
+            Bar::from_addr(base_addr + 0x10),
+            Bar::from_addr(base_addr + 0x14),
+            Bar::from_addr(base_addr + 0x18),
+            Bar::from_addr(base_addr + 0x1c),
+            Bar::from_addr(base_addr + 0x20),
+            Bar::from_addr(base_addr + 0x24),
+        ];
+        //assigned by PCIBus::enumerate, the high half is in the next bar
+        for id in 0..5 {
+            if matches!(base_address_reg[id].bar_type, BarType::Memory) && base_address_reg[id].mem_bits == 64 {
+                let high = base_address_reg[id + 1].raw() as usize;
+                base_address_reg[id].addr |= high << 32;
+            }
+        }
         Some(Self {
             header,
-            base_address_reg: [
-                Bar::from_addr(base_addr + 0x10),
-                Bar::from_addr(base_addr + 0x14),
-                Bar::from_addr(base_addr + 0x18),
-                Bar::from_addr(base_addr + 0x1c),
-                Bar::from_addr(base_addr + 0x20),
-                Bar::from_addr(base_addr + 0x24),
-            ],
+            base_address_reg,
             card_bus_cis_pointer: base_addr + 0x28,
             subsystem_vendor_id: base.offset(0x2a).read(),
             subsystem_id: base.offset(0x2c).read(),
@@ -155,40 +163,6 @@ impl Header0 {
             | Command::PCI_COMMAND_BUS_MASTER_EN;
         unsafe { self.header.command.write_volatile(cmd.bits()) }
     }
-    pub fn setup_bar(&mut self, id: usize, address: usize, cpu_address: PhyAddr) {
-        // Disable IO and MEM decoding around BAR detection, as we fiddle with
-        let cmd = Command::from_bits(unsafe { self.header.command.read_volatile() })
-            .unwrap()
-            .bits();
-        unsafe {
-            self.header.command.write_volatile(
-                (Command::from_bits(cmd).unwrap()
-                    & !(Command::PCI_COMMAND_IO_EN | Command::PCI_COMMAND_MEM_EN))
-                    .bits(),
-            )
-        };
-        match self.base_address_reg[id].bar_type {
-            BarType::Memory => match self.base_address_reg[id].mem_bits {
-                32 => self.base_address_reg[id].setup(address as u32, cpu_address.as_usize()),
-                64 => {
-                    self.base_address_reg[id].setup(address as u32, cpu_address.as_usize());
-                    self.base_address_reg[id + 1]
-                        .setup(address.overflowing_shr(32).0 as u32, cpu_address.as_usize());
-                }
-                _ => unreachable!(),
-            },
-            BarType::IO => match self.base_address_reg[id].mem_bits {
-                32 => self.base_address_reg[id].setup(address as u32, cpu_address.as_usize()),
-                64 => {
-                    self.base_address_reg[id].setup(address as u32, cpu_address.as_usize());
-                    self.base_address_reg[id + 1]
-                        .setup(address.overflowing_shr(32).0 as u32, cpu_address.as_usize());
-                }
-                _ => unreachable!(),
-            },
-        }
-        unsafe { self.header.command.write_volatile(cmd) };
-    }
 }
 
 register_bitfields![u16,
@@ -217,21 +191,27 @@ impl CapHeader {
         }
     }
 }
+//MSI-X capability, the table and the pending bits are in memory bars
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct CapMSIX {
-    header: CapHeader,
-    message_control: *mut ReadWrite<u16, MC::Register>,
-    bar: u8,
-    table_offset: u32,
-    pending_bit_offset: u32,
+    pub header: CapHeader,
+    pub message_control: *mut ReadWrite<u16, MC::Register>,
+    //bar indicator in the low 3 bits of both offsets
+    pub bar: u8,
+    pub table_offset: u32,
+    pub pending_bar: u8,
+    pub pending_bit_offset: u32,
 }
 
 impl CapMSIX {
-    #[allow(dead_code)]
+    pub const ID: u8 = 0x11;
+
     pub fn from_addr(addr: usize, offset: usize) -> Self {
         let address = addr + offset;
         let base = MMIO::new(address);
+        let table = base.offset(0x4).read::<u32>();
+        let pending = base.offset(0x8).read::<u32>();
         Self {
             header: CapHeader {
                 id: base.read::<u8>(),
@@ -239,18 +219,44 @@ impl CapMSIX {
                 base_addr: addr,
             },
             message_control: (address + 0x2) as *mut ReadWrite<u16, MC::Register>,
-            bar: base.offset(0x4).read(),
-            table_offset: base.offset(0x4).read::<u32>() & !0b11,
-            pending_bit_offset: base.offset(0x8).read(),
+            bar: (table & 0b111) as u8,
+            table_offset: table & !0b111,
+            pending_bar: (pending & 0b111) as u8,
+            pending_bit_offset: pending & !0b111,
         }
     }
+    pub fn table_size(&self) -> usize {
+        unsafe { (*self.message_control).read(MC::Table_Size) as usize + 1 }
+    }
+    pub fn set_enable(&self, enable: bool) {
+        let value = match enable {
+            true => MC::Enable::SET + MC::Function_Mask::CLEAR,
+            false => MC::Enable::CLEAR,
+        };
+        unsafe { (*self.message_control).modify(value) }
+    }
 }
 
-#[allow(dead_code)]
+//an entry of the MSI-X table, bit 0 of vector_control masks it
 #[repr(C)]
 pub struct MsixTable {
-    message_address_low: ReadWrite<u32>,
-    message_address_high: ReadWrite<u32>,
-    message_data: ReadWrite<u32>,
-    vector_control: ReadWrite<u32>,
+    pub message_address_low: ReadWrite<u32>,
+    pub message_address_high: ReadWrite<u32>,
+    pub message_data: ReadWrite<u32>,
+    pub vector_control: ReadWrite<u32>,
+}
+
+impl Header0 {
+    //walk the capability list
+    pub fn find_cap(&self, id: u8) -> Option<usize> {
+        let mut offset = self.capabilities_pointer as usize & !0b11;
+        while offset != 0 {
+            let cap = CapHeader::from_addr(self.base_addr, offset);
+            if cap.id == id {
+                return Some(offset);
+            }
+            offset = cap.next_pointer as usize & !0b11;
+        }
+        None
+    }
 }
@@ -195,6 +195,9 @@ impl Pl011Uart {
             base_addr: addr,
         }
     }
+    pub const fn base_addr(&self) -> usize {
+        self.base_addr
+    }
     pub fn init(&self, baud_rate: u32, uart_clk: u32) {
         self.reg().receive_status.set(0);
         self.reg()
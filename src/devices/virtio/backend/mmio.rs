@@ -0,0 +1,239 @@
+use alloc::boxed::Box;
+use core::fmt::{Display, Formatter};
+
+use fdt::node::FdtNode;
+
+use crate::arch::dtb;
+use crate::arch::{request_shared_irq, IntId, Trigger};
+use crate::devices::virtio::transport::{DeviceStatus, Transport};
+use crate::devices::virtio::{register_intx_event, unregister_intx_event, virtio_intx_irq, InterruptStatus};
+use crate::mm::{ioremap, PAGE_SIZE};
+use crate::pr_err;
+use crate::task::sync::Completion;
+
+//4.2 Virtio Over MMIO, offsets of the 32 bit registers
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+//legacy only
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+//legacy only
+const QUEUE_ALIGN: usize = 0x03c;
+const QUEUE_PFN: usize = 0x040;
+//modern only
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG_GENERATION: usize = 0x0fc;
+const CONFIG: usize = 0x100;
+
+const MAGIC: u32 = 0x7472_6976;
+const LEGACY: u32 = 1;
+const MODERN: u32 = 2;
+
+#[derive(Copy, Clone)]
+pub struct VirtioMmioTransport {
+    //the registers, mapped
+    base: usize,
+    //the device config runs up to the end of the region
+    size: usize,
+    version: u32,
+    device_type: u32,
+    irq: Option<IntId>,
+    event: Option<&'static Completion>,
+}
+
+impl VirtioMmioTransport {
+    //a virtio,mmio node, None for an empty slot
+    pub fn from_fdt(node: &FdtNode) -> Option<Self> {
+        let (pa, size) = dtb::reg(node, 0)?;
+        let base = match ioremap(pa, size) {
+            Ok(va) => va.as_usize(),
+            Err(errno) => {
+                pr_err!("virtio-mmio: cannot map {:?}: {:?}\n", pa, errno);
+                return None;
+            }
+        };
+        let read = |offset: usize| unsafe { ((base + offset) as *const u32).read_volatile() };
+        if read(MAGIC_VALUE) != MAGIC {
+            pr_err!("virtio-mmio: bad magic at {:?}\n", pa);
+            return None;
+        }
+        let version = read(VERSION);
+        if version != LEGACY && version != MODERN {
+            pr_err!("virtio-mmio: unknown version {} at {:?}\n", version, pa);
+            return None;
+        }
+        //QEMU has slots without a device behind them
+        let device_type = match read(DEVICE_ID) {
+            0 => return None,
+            ty => ty,
+        };
+        let mut transport = Self {
+            base,
+            size,
+            version,
+            device_type,
+            irq: None,
+            event: None,
+        };
+        if version == LEGACY {
+            transport.write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        }
+        if let Some(line) = dtb::interrupt(node, 0) {
+            let event: &'static Completion = Box::leak(Box::new(Completion::new()));
+            register_intx_event(line, InterruptStatus::Mmio(base), event);
+            if request_shared_irq(line, Trigger::Edge, virtio_intx_irq) {
+                transport.irq = Some(line);
+                transport.event = Some(event);
+            } else {
+                unregister_intx_event(line, event);
+                pr_err!("virtio-mmio: irq {} has too many handlers\n", line.0);
+            }
+        }
+        Some(transport)
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    fn write_u64(&self, low: usize, high: usize, value: u64) {
+        self.write(low, value as u32);
+        self.write(high, (value >> 32) as u32);
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.version == LEGACY
+    }
+
+    //when no driver took the device
+    pub fn release_irq(&self) {
+        if let (Some(line), Some(event)) = (self.irq, self.event) {
+            unregister_intx_event(line, event);
+        }
+    }
+}
+
+impl Transport for VirtioMmioTransport {
+    fn device_type(&self) -> u32 {
+        self.device_type
+    }
+
+    fn read_device_features(&mut self) -> u64 {
+        self.write(DEVICE_FEATURES_SEL, 0);
+        let low = self.read(DEVICE_FEATURES) as u64;
+        self.write(DEVICE_FEATURES_SEL, 1);
+        low | (self.read(DEVICE_FEATURES) as u64) << 32
+    }
+
+    fn write_driver_features(&mut self, driver_features: u64) {
+        self.write(DRIVER_FEATURES_SEL, 0);
+        self.write(DRIVER_FEATURES, driver_features as u32);
+        self.write(DRIVER_FEATURES_SEL, 1);
+        self.write(DRIVER_FEATURES, (driver_features >> 32) as u32);
+    }
+
+    //the register has no count, the queues that exist have a maximum size
+    fn num_queues(&mut self) -> u32 {
+        (0..u16::MAX as u32).find(|&queue| self.max_queue_size(queue) == 0).unwrap_or(0)
+    }
+
+    fn max_queue_size(&mut self, queue: u32) -> u32 {
+        self.write(QUEUE_SEL, queue);
+        self.read(QUEUE_NUM_MAX)
+    }
+
+    fn notify(&mut self, queue: u32) {
+        self.write(QUEUE_NOTIFY, queue);
+    }
+
+    fn status(&self) -> DeviceStatus {
+        DeviceStatus::from_bits_truncate(self.read(STATUS) as u8)
+    }
+
+    fn set_status(&mut self, status: DeviceStatus) {
+        self.write(STATUS, status.bits() as u32);
+    }
+
+    //writing 0 resets, the device reads 0 back once it is done
+    fn reset(&mut self) {
+        self.write(STATUS, 0);
+        while self.read(STATUS) != 0 {
+            core::hint::spin_loop();
+        }
+        if self.is_legacy() {
+            self.write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        }
+    }
+
+    //a legacy device takes one page number, the rings follow the descriptors with
+    //the used ring on the next page, as VirtQueue lays them out
+    fn queue_set(&mut self, queue: u32, size: u32, desc: usize, driver: usize, device: usize) {
+        self.write(QUEUE_SEL, queue);
+        self.write(QUEUE_NUM, size);
+        if self.is_legacy() {
+            self.write(QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write(QUEUE_PFN, (desc / PAGE_SIZE) as u32);
+        } else {
+            self.write_u64(QUEUE_DESC_LOW, QUEUE_DESC_HIGH, desc as u64);
+            self.write_u64(QUEUE_DRIVER_LOW, QUEUE_DRIVER_HIGH, driver as u64);
+            self.write_u64(QUEUE_DEVICE_LOW, QUEUE_DEVICE_HIGH, device as u64);
+            self.write(QUEUE_READY, 1);
+        }
+    }
+
+    fn queue_used(&mut self, queue: u32) -> bool {
+        self.write(QUEUE_SEL, queue);
+        match self.is_legacy() {
+            true => self.read(QUEUE_PFN) != 0,
+            false => self.read(QUEUE_READY) != 0,
+        }
+    }
+
+    //all queues share the one interrupt
+    fn queue_event(&self, _queue: u32) -> Option<&'static Completion> {
+        self.event
+    }
+
+    //legacy devices have no generation, their config is read as it is
+    fn config_generation(&self) -> u32 {
+        match self.is_legacy() {
+            true => 0,
+            false => self.read(CONFIG_GENERATION),
+        }
+    }
+
+    fn read_config(&self, offset: usize, buf: &mut [u8]) -> bool {
+        if CONFIG + offset + buf.len() > self.size {
+            return false;
+        }
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { ((self.base + CONFIG + offset + i) as *const u8).read_volatile() };
+        }
+        true
+    }
+}
+
+impl Display for VirtioMmioTransport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "virtio-mmio v{} type {}", self.version, self.device_type)
+    }
+}
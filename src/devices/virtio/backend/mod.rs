@@ -1,5 +1,7 @@
 #[allow(unused_imports)]
-pub use pci::VirtioBlkTrans;
+pub use mmio::VirtioMmioTransport;
+#[allow(unused_imports)]
+pub use pci::VirtioPciTransport;
 
+mod mmio;
 mod pci;
-
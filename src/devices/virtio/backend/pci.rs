@@ -1,13 +1,20 @@
+use alloc::boxed::Box;
 use core::fmt::{Display, Formatter};
 use core::mem::size_of;
 
 use tock_registers::interfaces::{Readable, Writeable};
 use tock_registers::registers::{ReadOnly, ReadWrite};
 
-use crate::{pr_notice, reg_read_a};
-use crate::devices::pci::bus::PCIBus;
-use crate::devices::virtio::blk::{DeviceStatus, Transport};
+use crate::{pr_err, pr_notice, reg_read_a};
+use crate::arch::{request_shared_irq, IntId, Trigger};
+use crate::devices::pci::bus::{PCIBus, PciDevice};
+use crate::devices::pci::msi::{self, Msix};
+use crate::devices::virtio::{
+    register_intx_event, register_queue_event, unregister_queue_event, virtio_intx_irq, virtio_irq, InterruptStatus,
+};
+use crate::devices::virtio::transport::{DeviceStatus, Transport};
 use crate::mm::VirtAddr;
+use crate::task::sync::Completion;
 
 //http://docs.oasis-open.org/virtio/virtio/v1.3/virtio-v1.3.html
 #[repr(u8)]
@@ -75,23 +82,6 @@ struct VirtioPciIsr {
     isr: u8,
 }
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub struct BlkConfig {
-    pub capacity_low: u32,
-    pub capacity_high: u32,
-    pub size_max: u32,
-    pub seg_max: u32,
-    pub cylinders: u16,
-    pub heads: u8,
-    pub sectors: u8,
-    pub blk_size: u32,
-    pub physical_block_exp: u8,
-    pub alignment_offset: u8,
-    pub min_io_size: u16,
-    pub opt_io_size: u32,
-}
-
 #[repr(C)]
 pub struct VirtioPciCommonCfg {
     pub device_feature_select: ReadWrite<u32>,
@@ -159,37 +149,26 @@ impl VirtioCap {
 
 #[allow(dead_code)]
 #[derive(Copy, Clone)]
-pub struct VirtioBlkTrans {
-    pub config: Option<BlkConfig>,
+pub struct VirtioPciTransport {
+    device_type: u32,
+    //address and length of the device specific config
+    device_cfg: Option<(usize, usize)>,
     common_cfg: Option<*mut VirtioPciCommonCfg>,
     isr_cfg: Option<*mut u8>,
     notify_reg: Option<*mut u16>,
     notify_cfg: Option<*mut VirtioPciNotifyCap>,
-    irq: u32,
+    //the INTx line when the queues have no MSI-X vectors
+    irq: Option<IntId>,
+    intx_event: Option<&'static Completion>,
+    msix: Option<Msix>,
+    //the vector of a queue and the event its interrupt completes
+    queue_events: [Option<(IntId, &'static Completion)>; Self::MAX_QUEUES],
 }
 
 
-impl Transport for VirtioBlkTrans {
-    fn capacity(&self) -> u64 {
-        match self.config() {
-            None => 0,
-            Some(config) => (config.capacity_high as u64) << 32 | config.capacity_low as u64
-        }
-    }
-
-    fn blk_size(&self) -> u64 {
-        match self.config() {
-            None => 0,
-            Some(config) => config.blk_size as u64
-        }
-    }
-
-    fn status(&self) -> DeviceStatus {
-        self.status()
-    }
-
-    fn reset(&mut self) {
-        self.reset()
+impl Transport for VirtioPciTransport {
+    fn device_type(&self) -> u32 {
+        self.device_type
     }
 
     fn read_device_features(&mut self) -> u64 {
@@ -200,19 +179,30 @@ impl Transport for VirtioBlkTrans {
         self.write_driver_features(driver_features)
     }
 
-    fn max_queue_size(&self) -> u32 {
-        self.max_queue_size()
+    fn num_queues(&mut self) -> u32 {
+        self.common_reg().num_queues.get() as u32
+    }
+
+    fn max_queue_size(&mut self, queue: u32) -> u32 {
+        self.max_queue_size(queue)
     }
 
     fn notify(&mut self, queue: u32) {
-        unsafe { self.isr_reg().write_volatile(1); }
         self.notify(queue)
     }
 
+    fn status(&self) -> DeviceStatus {
+        self.status()
+    }
+
     fn set_status(&mut self, status: DeviceStatus) {
         self.set_status(status)
     }
 
+    fn reset(&mut self) {
+        self.reset()
+    }
+
     fn queue_set(&mut self, queue: u32, size: u32, desc: usize, driver: usize, device: usize) {
         self.queue_set(queue, size, desc, driver, device)
     }
@@ -221,40 +211,61 @@ impl Transport for VirtioBlkTrans {
         self.queue_used(queue)
     }
 
-    fn init(&mut self) {
-        self.init()
+    fn queue_event(&self, queue: u32) -> Option<&'static Completion> {
+        match self.queue_events.get(queue as usize).copied().flatten() {
+            Some((_, event)) => Some(event),
+            None => self.intx_event,
+        }
     }
 
-    fn finish_init(&mut self) {
-        self.finish_init()
+    fn config_generation(&self) -> u32 {
+        self.common_reg().config_generation.get() as u32
+    }
+
+    fn read_config(&self, offset: usize, buf: &mut [u8]) -> bool {
+        match self.device_cfg {
+            Some((base, len)) if offset + buf.len() <= len => {
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    *byte = unsafe { ((base + offset + i) as *const u8).read_volatile() };
+                }
+                true
+            }
+            _ => false,
+        }
     }
 }
 
-impl Display for VirtioBlkTrans {
+impl Display for VirtioPciTransport {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
-        write!(f, "{:?}", self.config)
+        write!(f, "virtio-pci type {}", self.device_type)
     }
 }
 
-unsafe impl Sync for VirtioBlkTrans {}
+unsafe impl Sync for VirtioPciTransport {}
 
-unsafe impl Send for VirtioBlkTrans {}
+unsafe impl Send for VirtioPciTransport {}
 
 #[allow(dead_code)]
-impl VirtioBlkTrans {
-    const BLK_VENDOR: u16 = 0x1af4;
-    const BLK_DEVICE: u16 = 0x1001;
-
-    pub fn from_pci(pci_bus: &mut PCIBus) -> Option<Self> {
+impl VirtioPciTransport {
+    pub const VENDOR: u16 = 0x1af4;
+    //modern devices are 0x1040 + device type
+    const DEVICE_MODERN: u16 = 0x1040;
+    const MAX_QUEUES: usize = 8;
+    //no MSI-X vector for a queue or the config change
+    const NO_VECTOR: u16 = 0xffff;
+
+    pub fn from_pci(pci_bus: &PCIBus, dev: &PciDevice) -> Option<Self> {
         let mut common_cfg = None;
         let mut notify_cfg = None;
         let mut isr_reg = None;
         let mut notify_reg = None;
         let mut device_cfg = None;
-        let irq = 1;
-        match pci_bus.find_device(Self::BLK_VENDOR, Self::BLK_DEVICE) {
+        let mut irq = None;
+        let mut intx_event = None;
+        let mut msix = None;
+        match pci_bus.header(dev) {
             None => return None,
-            Some(mut pci) => {
+            Some(pci) => {
                 pr_notice!("PCI: {:02}.{:02}.{:02} {}\n", pci.bus, pci.device, pci.func, pci);
                 pci.enable();
                 unsafe { pci.header.status.write_volatile(1 << 3) }
@@ -268,14 +279,12 @@ impl VirtioBlkTrans {
                         Some(vc) => {
                             cap = vc;
                             let base_addr = match pci.base_address_reg[cap.bar as usize].addr {
+                                //assigned when the bus was enumerated
                                 0 => {
-                                    let address = pci_bus.mem[2]
-                                        .alloc(pci.base_address_reg[cap.bar as usize].mem_size)
-                                        .unwrap();
-                                    pci.setup_bar(cap.bar as usize, address.0, address.1);
-                                    address.1.as_usize()
+                                    pr_err!("virtio: bar {} has no space\n", cap.bar);
+                                    return None;
                                 }
-                                _ => pci.base_address_reg[cap.bar as usize].addr
+                                addr => addr
                             };
                             match cap.cfg_type {
                                 CapType::VirtioPciCapCommonCfg => {
@@ -289,17 +298,45 @@ impl VirtioBlkTrans {
                                     isr_reg = Some(VirtAddr::from_phy(base_addr + cap.offset as usize).as_mut_ptr())
                                 }
                                 CapType::VirtioPciCapDeviceCfg => {
-                                    device_cfg = Some(unsafe { *(VirtAddr::from_phy(base_addr + cap.offset as usize).as_mut_ptr() as *mut BlkConfig) })
+                                    device_cfg = Some((VirtAddr::from_phy(base_addr + cap.offset as usize).as_usize(), cap.length as usize))
                                 }
                                 _ => {}
                             }
                         }
                     }
                 }
+                if msi::available() {
+                    msix = Msix::from_header(&pci);
+                    if let Some(msix) = msix {
+                        msix.enable();
+                    }
+                }
+                //the shared INTx line otherwise, without either requests are polled
+                if let (None, Some(line), Some(isr)) = (msix, dev.irq, isr_reg) {
+                    let event: &'static Completion = Box::leak(Box::new(Completion::new()));
+                    register_intx_event(line, InterruptStatus::Pci(isr as usize), event);
+                    if request_shared_irq(line, Trigger::Level, virtio_intx_irq) {
+                        irq = Some(line);
+                        intx_event = Some(event);
+                    } else {
+                        pr_err!("virtio: irq {} has too many handlers\n", line.0);
+                    }
+                }
             }
         }
 
-        Some(Self { config: device_cfg, common_cfg, isr_cfg: isr_reg, notify_reg, notify_cfg, irq })
+        Some(Self {
+            device_type: Self::device_type_of(dev.device_id)?,
+            device_cfg,
+            common_cfg,
+            isr_cfg: isr_reg,
+            notify_reg,
+            notify_cfg,
+            irq,
+            intx_event,
+            msix,
+            queue_events: [None; Self::MAX_QUEUES],
+        })
     }
     fn common_reg(&self) -> &'static mut VirtioPciCommonCfg {
         match self.common_cfg {
@@ -328,23 +365,31 @@ impl VirtioBlkTrans {
             }
         }
     }
+    //transitional devices have ids of their own, 4.1.2 PCI Device Discovery
+    fn device_type_of(device_id: u16) -> Option<u32> {
+        match device_id {
+            0x1000 => Some(1),
+            0x1001 => Some(2),
+            0x1002 => Some(5),
+            0x1003 => Some(3),
+            0x1004 => Some(8),
+            0x1005 => Some(4),
+            0x1009 => Some(9),
+            id if (Self::DEVICE_MODERN..=0x107f).contains(&id) => Some((id - Self::DEVICE_MODERN) as u32),
+            _ => None,
+        }
+    }
     pub fn status(&self) -> DeviceStatus {
         DeviceStatus::from_bits_truncate(self.common_reg().device_status.get())
     }
+    //writing 0 resets, the device reads 0 back once it is done
     #[inline]
     pub fn reset(&mut self) {
         let virtio = self.common_reg();
         virtio.device_status.set(DeviceStatus::empty().bits());
-        virtio.device_status.set(
-            (DeviceStatus::from_bits_truncate(virtio.device_status.get()) | DeviceStatus::ACKNOWLEDGE)
-                .bits(),
-        );
-        virtio.device_status.set(
-            (DeviceStatus::from_bits_truncate(virtio.device_status.get()) | DeviceStatus::DRIVER).bits(),
-        );
-    }
-    pub const fn config(&self) -> Option<BlkConfig> {
-        self.config
+        while virtio.device_status.get() != 0 {
+            core::hint::spin_loop();
+        }
     }
 
     fn read_device_features(&mut self) -> u64 {
@@ -358,13 +403,14 @@ impl VirtioBlkTrans {
 
     fn write_driver_features(&mut self, driver_features: u64) {
         let reg = self.common_reg();
-        reg.device_feature_select.set(0);
+        reg.driver_feature_select.set(0);
         reg.driver_feature.set(driver_features as u32);
-        reg.device_feature_select.set(1);
+        reg.driver_feature_select.set(1);
         reg.driver_feature.set((driver_features >> 32) as u32);
     }
 
-    pub(crate) fn max_queue_size(&self) -> u32 {
+    pub(crate) fn max_queue_size(&mut self, queue: u32) -> u32 {
+        self.common_reg().queue_select.set(queue as u16);
         self.common_reg().queue_size.get() as u32
     }
 
@@ -394,24 +440,42 @@ impl VirtioBlkTrans {
         self.common_reg().queue_desc.set(desc as u64);
         self.common_reg().queue_driver.set(driver as u64);
         self.common_reg().queue_device.set(device as u64);
+        self.setup_queue_vector(queue);
         self.common_reg().queue_enable.set(1);
     }
 
+    //table entry 0 is left for config changes, queue n gets entry n + 1
+    fn setup_queue_vector(&mut self, queue: u32) {
+        let (msix, entry) = match self.msix {
+            Some(msix) if (queue as usize) < Self::MAX_QUEUES => (msix, queue as usize + 1),
+            _ => return,
+        };
+        let old = self.queue_events[queue as usize].take();
+        if let Some((irq, _)) = old {
+            unregister_queue_event(irq);
+            msix.free_vector(entry, irq);
+        }
+        let irq = match msix.setup_vector(entry, virtio_irq) {
+            None => return pr_err!("virtio: no MSI-X vector for queue {}\n", queue),
+            Some(irq) => irq,
+        };
+        self.common_reg().queue_msix_vector.set(entry as u16);
+        //the device answers NO_VECTOR when it could not take it
+        if self.common_reg().queue_msix_vector.get() == Self::NO_VECTOR {
+            pr_err!("virtio: queue {} refused MSI-X vector {}\n", queue, entry);
+            return msix.free_vector(entry, irq);
+        }
+        let event = match old {
+            Some((_, event)) => event,
+            None => Box::leak(Box::new(Completion::new())),
+        };
+        register_queue_event(irq, event);
+        self.queue_events[queue as usize] = Some((irq, event));
+    }
+
     pub(crate) fn queue_used(&mut self, queue: u32) -> bool {
         self.common_reg().queue_select.set(queue as u16);
         self.common_reg().queue_enable.get() != 0
     }
 
-
-    pub fn init(&mut self) {
-        self.set_status(DeviceStatus::ACKNOWLEDGE);
-        self.set_status(DeviceStatus::DRIVER);
-
-        let features = self.read_device_features();
-        self.write_driver_features(features);
-        self.set_status(DeviceStatus::FEATURES_OK);
-    }
-    pub fn finish_init(&mut self) {
-        self.set_status(DeviceStatus::DRIVER_OK);
-    }
 }
@@ -1,19 +1,52 @@
-use core::cell::RefCell;
 use core::hint::spin_loop;
 use core::mem::size_of;
 
-use bitflags::bitflags;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use super::{Error, Result};
 use super::queue::VirtQueue;
+use super::transport::{config_u32, config_u64, DeviceStatus, Transport, VIRTIO_F_VERSION_1};
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::devices::driver::{device_name, BlockDevice, Device, DeviceClass};
+use crate::pr_err;
+use crate::task::scheduler;
+use crate::task::sync::{Completion, SleepMutex};
+
+//the queue is shared by the requests in flight. Its lock is held to add a request and to
+//take used ones off, never while waiting for the device
+struct Queue {
+    transport: &'static mut dyn Transport,
+    queue: VirtQueue,
+    //requests the device used that their owner did not see yet
+    done: Vec<u16>,
+}
+
+unsafe impl Send for Queue {}
 
 pub struct VirtIOBlk {
-    pub transport: &'static mut dyn Transport,
-    pub queue: RefCell<VirtQueue>,
+    queue: Mutex<Queue>,
+    event: Option<&'static Completion>,
+    //in 512 byte sectors
     pub capacity: u64,
     pub blk_size: u64,
+    pub features: u64,
 }
 
+//5.2.3 Feature bits
+pub const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+pub const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+pub const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+pub const VIRTIO_BLK_F_WRITE_ZEROES: u64 = 1 << 14;
+const SUPPORTED_FEATURES: u64 =
+    VIRTIO_F_VERSION_1 | VIRTIO_BLK_F_RO | VIRTIO_BLK_F_BLK_SIZE | VIRTIO_BLK_F_FLUSH | VIRTIO_BLK_F_WRITE_ZEROES;
+
+//offsets into struct virtio_blk_config
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_BLK_SIZE: usize = 20;
+const SECTOR_SIZE: u64 = 512;
+
 ///# Safety
 unsafe trait AsBuf: Sized {
     fn as_buf(&self) -> &[u8] {
@@ -23,48 +56,45 @@ unsafe trait AsBuf: Sized {
         unsafe { core::slice::from_raw_parts_mut(self as *mut _ as _, size_of::<Self>()) }
     }
 }
-bitflags! {
-    #[derive(Default, Debug)]
-    pub struct DeviceStatus: u8 {
-        const ACKNOWLEDGE = 1;
-        const DRIVER = 2;
-        const FAILED = 128;
-        const FEATURES_OK = 8;
-        const DRIVER_OK = 4;
-        const DEVICE_NEEDS_RESET = 64;
-    }
+//tasks take turns on the disk and sleep until their request is done, the queue
+//interrupt wakes the one whose turn it is. The atomic calls skip the turn and poll
+struct VirtioBlkDevice {
+    blk: &'static VirtIOBlk,
+    turn: SleepMutex<()>,
 }
-pub trait Transport {
-    fn capacity(&self) -> u64;
-    fn blk_size(&self) -> u64;
-    fn status(&self) -> DeviceStatus;
-    fn reset(&mut self);
-    fn read_device_features(&mut self) -> u64;
-
-    fn write_driver_features(&mut self, driver_features: u64);
-
-    fn max_queue_size(&self) -> u32;
-
-    fn notify(&mut self, queue: u32);
-
-    fn set_status(&mut self, status: DeviceStatus);
-
-    fn queue_set(
-        &mut self,
-        queue: u32,
-        size: u32,
-        desc: usize,
-        driver: usize,
-        device: usize,
-    );
 
-    fn queue_used(&mut self, queue: u32) -> bool;
-
-
-    fn init(&mut self);
-    fn finish_init(&mut self);
+impl BlockDevice for VirtioBlkDevice {
+    fn block_size(&self) -> usize {
+        self.blk.blk_size as usize
+    }
+    fn capacity(&self) -> u64 {
+        self.blk.capacity * SECTOR_SIZE
+    }
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> bool {
+        let _turn = self.turn.lock();
+        self.blk.read_block(block_id, buf, false).is_ok()
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> bool {
+        let _turn = self.turn.lock();
+        self.blk.write_block(block_id, buf, false).is_ok()
+    }
+    fn read_block_atomic(&self, block_id: usize, buf: &mut [u8]) -> bool {
+        self.blk.read_block(block_id, buf, true).is_ok()
+    }
+    fn write_block_atomic(&self, block_id: usize, buf: &[u8]) -> bool {
+        self.blk.write_block(block_id, buf, true).is_ok()
+    }
 }
 
+pub fn probe(transport: &'static mut dyn Transport) -> SysResult<Device> {
+    let blk = VirtIOBlk::new(transport).map_err(|e| {
+        pr_err!("virtio-blk: init failed: {:?}\n", e);
+        Errno::EIO
+    })?;
+    let device: &'static VirtioBlkDevice =
+        Box::leak(Box::new(VirtioBlkDevice { blk: Box::leak(Box::new(blk)), turn: SleepMutex::new(()) }));
+    Ok(Device::new(device_name("vd"), DeviceClass::Block(device)))
+}
 
 unsafe impl Sync for VirtIOBlk {}
 
@@ -72,87 +102,94 @@ unsafe impl Send for VirtIOBlk {}
 
 impl VirtIOBlk {
     pub fn new<'a>(transport: &'static mut dyn Transport) -> Result<VirtIOBlk> {
-        transport.init();
-        let queue = RefCell::new(VirtQueue::new(transport, 0, 4)?);
+        let features = transport.begin_init(SUPPORTED_FEATURES)?;
+        let capacity = config_u64(transport, CONFIG_CAPACITY).ok_or(Error::NotReady)?;
+        let blk_size = match features & VIRTIO_BLK_F_BLK_SIZE {
+            0 => SECTOR_SIZE,
+            _ => config_u32(transport, CONFIG_BLK_SIZE).ok_or(Error::NotReady)? as u64,
+        };
+        //room for a sleeping request and a polled one, three descriptors each
+        let queue = match VirtQueue::new(transport, 0, 8) {
+            Ok(queue) => queue,
+            Err(e) => {
+                transport.set_status(DeviceStatus::FAILED);
+                return Err(e);
+            }
+        };
         transport.finish_init();
-        let capacity = transport.capacity();
-        let blk_size = transport.blk_size();
+        let event = transport.queue_event(0);
         Ok(VirtIOBlk {
-            transport,
-            queue,
+            queue: Mutex::new(Queue { transport, queue, done: Vec::new() }),
+            event,
             capacity,
             blk_size,
+            features,
         })
     }
 
+    //the first sector of block_id, requests are always in 512 byte sectors
+    fn sector(&self, block_id: usize) -> u64 {
+        block_id as u64 * self.blk_size / SECTOR_SIZE
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.features & VIRTIO_BLK_F_RO != 0
+    }
+
 
-    pub fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> Result {
+    pub fn read_block(&self, block_id: usize, buf: &mut [u8], poll: bool) -> Result {
         assert_eq!(buf.len(), self.blk_size as usize);
         let req = BlkReq {
             type_: ReqType::In,
             reserved: 0,
-            sector: block_id as u64,
+            sector: self.sector(block_id),
         };
         let mut resp = BlkResp::default();
-        self.queue.borrow_mut()
-            .add(&[req.as_buf()], &[buf, resp.as_buf_mut()])?;
-        self.transport.notify(0);
-        while !self.queue.borrow_mut().can_pop() {
-            spin_loop();
-        }
-        self.queue.borrow_mut().pop_used()?;
+        self.request(&[req.as_buf()], &[buf, resp.as_buf_mut()], poll)?;
         match resp.status {
             RespStatus::Ok => Ok(()),
             RespStatus::IoErr => Err(Error::IoError),
             _ => Err(Error::InvalidParam),
         }
     }
-    pub fn write_block(&mut self, block_id: usize, buf: &[u8]) -> Result {
+    pub fn write_block(&self, block_id: usize, buf: &[u8], poll: bool) -> Result {
         assert_eq!(buf.len(), self.blk_size as usize);
+        if self.read_only() {
+            return Err(Error::IoError);
+        }
         let req = BlkReq {
             type_: ReqType::Out,
             reserved: 0,
-            sector: block_id as u64,
+            sector: self.sector(block_id),
         };
         let mut resp = BlkResp::default();
-        self.queue
-            .borrow_mut()
-            .add(&[req.as_buf(), buf], &[resp.as_buf_mut()])?;
-        self.transport.notify(0);
-        while !self.queue.borrow_mut().can_pop() {
-            spin_loop();
-        }
-        self.queue.borrow_mut().pop_used()?;
+        self.request(&[req.as_buf(), buf], &[resp.as_buf_mut()], poll)?;
         match resp.status {
             RespStatus::Ok => Ok(()),
             RespStatus::IoErr => Err(Error::IoError),
             _ => Err(Error::InvalidParam),
         }
     }
-    pub fn clear_block(&mut self, block_id: usize, num_sectors: u32) -> Result {
+    pub fn clear_block(&self, block_id: usize, num_sectors: u32) -> Result {
         let req = BlkReq {
             type_: ReqType::WriteZeroes,
             reserved: 0,
-            sector: block_id as u64,
+            sector: self.sector(block_id),
         };
         let mut resp = BlkResp::default();
-        self.queue.borrow_mut().add(
+        self.request(
             &[
                 req.as_buf(),
                 DiscardWriteZeroes {
-                    sector: block_id,
+                    sector: self.sector(block_id) as usize,
                     num_sectors,
                     unmap: 0,
                 }
                     .as_buf(),
             ],
             &[resp.as_buf_mut()],
+            false,
         )?;
-        self.transport.notify(0);
-        while !self.queue.borrow_mut().can_pop() {
-            spin_loop();
-        }
-        self.queue.borrow_mut().pop_used()?;
         match resp.status {
             RespStatus::Ok => Ok(()),
             RespStatus::IoErr => Err(Error::IoError),
@@ -160,22 +197,51 @@ impl VirtIOBlk {
         }
     }
 
+    //add a request and wait until the device used it. The used ring is in the order the
+    //device finished, a request of somebody else is left in done for its owner, who
+    //frees its descriptors. poll spins instead of sleeping on the queue interrupt, as
+    //before the scheduler runs or without an interrupt
+    fn request(&self, inputs: &[&[u8]], outputs: &[&mut [u8]], poll: bool) -> Result {
+        let token = {
+            let mut queue = self.queue.lock();
+            let token = queue.queue.add(inputs, outputs)?;
+            queue.transport.notify(0);
+            token
+        };
+        loop {
+            let mut queue = self.queue.lock();
+            while let Ok((used, _)) = queue.queue.take_used() {
+                queue.done.push(used);
+            }
+            if let Some(index) = queue.done.iter().position(|used| *used == token) {
+                queue.done.swap_remove(index);
+                queue.queue.recycle(token);
+                return Ok(());
+            }
+            drop(queue);
+            match self.event {
+                Some(event) if !poll && scheduler::is_running() => event.wait(),
+                _ => spin_loop(),
+            }
+        }
+    }
+
     pub fn virt_queue_size(&self) -> u16 {
-        self.queue.borrow().size()
+        self.queue.lock().queue.size()
     }
 
-    pub fn status(&mut self) -> DeviceStatus {
-        self.transport.status()
+    pub fn status(&self) -> DeviceStatus {
+        self.queue.lock().transport.status()
     }
 
-    pub fn flush(&mut self, block_id: u64) -> Result {
+    pub fn flush(&self, block_id: u64) -> Result {
         let req = BlkReq {
             type_: ReqType::Flush,
             reserved: 0,
             sector: block_id as u64,
         };
         let mut resp = BlkResp::default();
-        self.queue.borrow_mut().add(
+        self.request(
             &[
                 req.as_buf(),
                 DiscardWriteZeroes {
@@ -186,20 +252,19 @@ impl VirtIOBlk {
                     .as_buf(),
             ],
             &[resp.as_buf_mut()],
+            false,
         )?;
-        self.transport.notify(0);
-        while !self.queue.borrow_mut().can_pop() {
-            spin_loop();
-        }
-        self.queue.borrow_mut().pop_used()?;
         match resp.status {
             RespStatus::Ok => Ok(()),
             RespStatus::IoErr => Err(Error::IoError),
             _ => Err(Error::InvalidParam),
         }
     }
-    pub fn flush_all(&mut self) -> Result {
-        for i in 0..self.capacity / self.blk_size {
+    pub fn flush_all(&self) -> Result {
+        if self.features & VIRTIO_BLK_F_FLUSH == 0 {
+            return Ok(());
+        }
+        for i in 0..self.capacity * SECTOR_SIZE / self.blk_size {
             match self.flush(i) {
                 Ok(_) => {}
                 Err(e) => return Err(e),
@@ -1,13 +1,26 @@
 #![allow(dead_code)]
 
-pub use backend::VirtioBlkTrans;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+pub use backend::{VirtioMmioTransport, VirtioPciTransport};
+
+use crate::arch::{free_irq, IntId, IRQ_NONE};
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::devices::driver::{Device, DeviceId, Driver, ProbeInfo};
+use crate::devices::virtio::transport::{Transport, VIRTIO_TYPE_BLOCK, VIRTIO_TYPE_NET};
+use crate::task::sync::Completion;
+use crate::{pr_notice, register_driver};
 
 pub const DISK_BLK_SIZE: usize = 512;
 
 // pub mod blk;
 pub mod backend;
 pub mod blk;
+pub mod net;
 pub mod queue;
+pub mod transport;
 #[macro_export]
 macro_rules! align {
     ($addr:expr, $page_size:ident) => {
@@ -16,6 +29,157 @@ macro_rules! align {
 }
 pub const PAGE_SIZE: usize = 0x1000;
 
+//the MSI-X vector of every queue and what its interrupt completes
+static QUEUE_EVENTS: Mutex<Vec<(IntId, &'static Completion)>> = Mutex::new_no_irq(Vec::new());
+
+pub fn register_queue_event(irq: IntId, event: &'static Completion) {
+    QUEUE_EVENTS.lock().push((irq, event));
+}
+
+pub fn unregister_queue_event(irq: IntId) {
+    QUEUE_EVENTS.lock().retain(|(vector, _)| *vector != irq);
+}
+
+//where a device without MSI-X tells why it interrupted
+#[derive(Copy, Clone)]
+pub enum InterruptStatus {
+    //the PCI isr register, reading it deasserts the line
+    Pci(usize),
+    //the base of the MMIO registers, the status has to be acked
+    Mmio(usize),
+}
+
+impl InterruptStatus {
+    //bit 0 is set for a used buffer, bit 1 for a config change
+    fn read_and_ack(&self) -> u32 {
+        match *self {
+            InterruptStatus::Pci(isr) => unsafe { (isr as *mut u8).read_volatile() as u32 },
+            InterruptStatus::Mmio(base) => unsafe {
+                let status = ((base + 0x60) as *mut u32).read_volatile();
+                ((base + 0x64) as *mut u32).write_volatile(status);
+                status
+            },
+        }
+    }
+}
+
+//devices without MSI-X on a shared line: the irq, their interrupt status and the
+//event of all of their queues
+static INTX_EVENTS: Mutex<Vec<(IntId, InterruptStatus, &'static Completion)>> = Mutex::new_no_irq(Vec::new());
+
+pub fn register_intx_event(irq: IntId, isr: InterruptStatus, event: &'static Completion) {
+    INTX_EVENTS.lock().push((irq, isr, event));
+}
+
+//the handler leaves the line with its last event
+pub fn unregister_intx_event(irq: IntId, event: &'static Completion) {
+    let mut events = INTX_EVENTS.lock();
+    events.retain(|(line, _, other)| *line != irq || !core::ptr::eq(*other, event));
+    let last = !events.iter().any(|(line, _, _)| *line == irq);
+    drop(events);
+    if last {
+        free_irq(irq, virtio_intx_irq);
+    }
+}
+
+pub fn virtio_intx_irq(irq: IntId) -> i32 {
+    let mut ret = IRQ_NONE;
+    for (line, isr, event) in INTX_EVENTS.lock().iter() {
+        if *line != irq {
+            continue;
+        }
+        let status = isr.read_and_ack();
+        if status != 0 {
+            ret = 0;
+        }
+        if status & 1 != 0 {
+            event.complete();
+        }
+    }
+    ret
+}
+
+//the handler of every queue vector, the device put buffers into the used ring
+pub fn virtio_irq(irq: IntId) -> i32 {
+    let event = QUEUE_EVENTS
+        .lock()
+        .iter()
+        .find(|(vector, _)| *vector == irq)
+        .map(|(_, event)| *event);
+    match event {
+        None => -1,
+        Some(event) => {
+            event.complete();
+            0
+        }
+    }
+}
+
+static VIRTIO_PCI_DRIVER: Driver = Driver {
+    name: "virtio-pci",
+    ids: &[
+        //transitional and modern network and block devices
+        DeviceId::Pci {
+            vendor: VirtioPciTransport::VENDOR,
+            device: 0x1000,
+        },
+        DeviceId::Pci {
+            vendor: VirtioPciTransport::VENDOR,
+            device: 0x1041,
+        },
+        DeviceId::Pci {
+            vendor: VirtioPciTransport::VENDOR,
+            device: 0x1001,
+        },
+        DeviceId::Pci {
+            vendor: VirtioPciTransport::VENDOR,
+            device: 0x1042,
+        },
+    ],
+    probe: virtio_pci_probe,
+};
+register_driver!(VIRTIO_PCI_DRIVER);
+
+fn virtio_pci_probe(info: &ProbeInfo) -> SysResult<Device> {
+    let (bus, dev) = match info {
+        ProbeInfo::Pci(bus, dev) => (bus, dev),
+        _ => return Err(Errno::ENODEV),
+    };
+    let transport = VirtioPciTransport::from_pci(bus, dev).ok_or(Errno::ENODEV)?;
+    probe_transport(Box::leak(Box::new(transport)))
+}
+
+static VIRTIO_MMIO_DRIVER: Driver = Driver {
+    name: "virtio-mmio",
+    ids: &[DeviceId::Compatible("virtio,mmio")],
+    probe: virtio_mmio_probe,
+};
+register_driver!(VIRTIO_MMIO_DRIVER);
+
+fn virtio_mmio_probe(info: &ProbeInfo) -> SysResult<Device> {
+    let node = match info {
+        ProbeInfo::Fdt(node) => node,
+        _ => return Err(Errno::ENODEV),
+    };
+    let transport = VirtioMmioTransport::from_fdt(node).ok_or(Errno::ENODEV)?;
+    probe_transport(Box::leak(Box::new(transport))).map_err(|errno| {
+        transport.release_irq();
+        errno
+    })
+}
+
+//the driver of whatever device sits behind the transport
+pub fn probe_transport(transport: &'static mut dyn Transport) -> SysResult<Device> {
+    match transport.device_type() {
+        VIRTIO_TYPE_NET => net::probe(transport),
+        VIRTIO_TYPE_BLOCK => blk::probe(transport),
+        ty => {
+            pr_notice!("virtio: no driver for device type {}\n", ty);
+            Err(Errno::ENODEV)
+        }
+    }
+}
+
 pub type Result<T = ()> = core::result::Result<T, Error>;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -32,4 +196,6 @@ pub enum Error {
     DmaError,
     /// I/O Error
     IoError,
+    /// The device did not accept the features.
+    Unsupported,
 }
\ No newline at end of file
@@ -0,0 +1,201 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::queue::VirtQueue;
+use super::transport::{config_u16, read_config, DeviceStatus, Transport, VIRTIO_F_VERSION_1};
+use super::{Error, Result};
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::devices::driver::{device_name, Device, DeviceClass, NetDevice};
+use crate::net::complete_checksum;
+use crate::task::sync::Completion;
+use crate::{pr_err, pr_notice};
+
+//5.1.3 Feature bits
+pub const VIRTIO_NET_F_CSUM: u64 = 1 << 0;
+pub const VIRTIO_NET_F_GUEST_CSUM: u64 = 1 << 1;
+pub const VIRTIO_NET_F_MTU: u64 = 1 << 3;
+pub const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const SUPPORTED_FEATURES: u64 =
+    VIRTIO_F_VERSION_1 | VIRTIO_NET_F_CSUM | VIRTIO_NET_F_GUEST_CSUM | VIRTIO_NET_F_MTU | VIRTIO_NET_F_MAC;
+
+//offsets into struct virtio_net_config
+const CONFIG_MAC: usize = 0;
+const CONFIG_MTU: usize = 10;
+
+//flags of struct virtio_net_hdr
+const HDR_F_NEEDS_CSUM: u8 = 1;
+const HDR_F_DATA_VALID: u8 = 2;
+
+const RX_QUEUE: u32 = 0;
+const TX_QUEUE: u32 = 1;
+const QUEUE_SIZE: u16 = 64;
+const ETH_HEADER: usize = 14;
+const DEFAULT_MTU: usize = 1500;
+//without a MAC from the device, a locally administered one
+const DEFAULT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+//every buffer is two descriptors, the header and the frame, so it works without
+//VIRTIO_F_ANY_LAYOUT on legacy devices too. Buffers are kept by the head descriptor
+pub struct VirtIONet {
+    transport: &'static mut dyn Transport,
+    rx: VirtQueue,
+    tx: VirtQueue,
+    rx_bufs: Vec<Option<Vec<u8>>>,
+    tx_bufs: Vec<Option<Vec<u8>>>,
+    features: u64,
+    //virtio_net_hdr, num_buffers is only there for modern devices
+    hdr_len: usize,
+    mac: [u8; 6],
+    mtu: usize,
+}
+
+impl VirtIONet {
+    pub fn new(transport: &'static mut dyn Transport) -> Result<VirtIONet> {
+        let features = transport.begin_init(SUPPORTED_FEATURES)?;
+        let mac = match features & VIRTIO_NET_F_MAC {
+            0 => DEFAULT_MAC,
+            _ => read_config::<6>(transport, CONFIG_MAC).ok_or(Error::NotReady)?,
+        };
+        let mtu = match features & VIRTIO_NET_F_MTU {
+            0 => DEFAULT_MTU,
+            _ => config_u16(transport, CONFIG_MTU).map_or(DEFAULT_MTU, |mtu| (mtu as usize).min(DEFAULT_MTU)),
+        };
+        let queues = VirtQueue::new(transport, RX_QUEUE as usize, QUEUE_SIZE)
+            .and_then(|rx| Ok((rx, VirtQueue::new(transport, TX_QUEUE as usize, QUEUE_SIZE)?)));
+        let (rx, tx) = match queues {
+            Ok(queues) => queues,
+            Err(e) => {
+                transport.set_status(DeviceStatus::FAILED);
+                return Err(e);
+            }
+        };
+        let hdr_len = match features & VIRTIO_F_VERSION_1 {
+            0 => 10,
+            _ => 12,
+        };
+        let mut net = VirtIONet {
+            transport,
+            rx,
+            tx,
+            rx_bufs: vec![None; QUEUE_SIZE as usize],
+            tx_bufs: vec![None; QUEUE_SIZE as usize],
+            features,
+            hdr_len,
+            mac,
+            mtu,
+        };
+        //the device may use the rx buffers as soon as the driver is ok
+        while net.rx.available_desc() >= 2 {
+            net.add_rx_buffer()?;
+        }
+        net.transport.finish_init();
+        net.transport.notify(RX_QUEUE);
+        Ok(net)
+    }
+
+    fn add_rx_buffer(&mut self) -> Result {
+        let mut buf = vec![0u8; self.hdr_len + ETH_HEADER + self.mtu];
+        let (hdr, frame) = buf.split_at_mut(self.hdr_len);
+        let token = self.rx.add(&[], &[hdr, frame])?;
+        self.rx_bufs[token as usize] = Some(buf);
+        Ok(())
+    }
+
+    //tx buffers the device is done with
+    fn reclaim_tx(&mut self) {
+        while let Ok((token, _)) = self.tx.pop_used() {
+            self.tx_bufs[token as usize] = None;
+        }
+    }
+
+    pub fn transmit(&mut self, frame: &[u8], csum: Option<(usize, usize)>) -> Result {
+        self.reclaim_tx();
+        let mut buf = vec![0u8; self.hdr_len + frame.len()];
+        buf[self.hdr_len..].copy_from_slice(frame);
+        match csum {
+            Some((start, offset)) if self.features & VIRTIO_NET_F_CSUM != 0 => {
+                buf[0] = HDR_F_NEEDS_CSUM;
+                buf[6..8].copy_from_slice(&(start as u16).to_le_bytes());
+                buf[8..10].copy_from_slice(&(offset as u16).to_le_bytes());
+            }
+            Some((start, offset)) => complete_checksum(&mut buf[self.hdr_len..], start, offset),
+            None => {}
+        }
+        let (hdr, data) = buf.split_at(self.hdr_len);
+        let token = self.tx.add(&[hdr, data], &[])?;
+        self.tx_bufs[token as usize] = Some(buf);
+        self.transport.notify(TX_QUEUE);
+        Ok(())
+    }
+
+    pub fn receive(&mut self) -> Option<(Vec<u8>, bool)> {
+        let (token, len) = self.rx.pop_used().ok()?;
+        let mut buf = self.rx_bufs[token as usize].take()?;
+        //a full queue of buffers again
+        if let Err(e) = self.add_rx_buffer() {
+            pr_err!("virtio-net: no rx buffer: {:?}\n", e);
+        }
+        self.transport.notify(RX_QUEUE);
+        //a partial checksum comes from the host itself, it is as good as a valid one
+        let checked = buf[0] & (HDR_F_DATA_VALID | HDR_F_NEEDS_CSUM) != 0;
+        buf.truncate(len as usize);
+        Some((buf.split_off(self.hdr_len.min(buf.len())), checked))
+    }
+}
+
+unsafe impl Sync for VirtIONet {}
+
+unsafe impl Send for VirtIONet {}
+
+//transmit is called from syscalls and the rx threads, neither sleeps on the device
+struct VirtioNetDevice {
+    net: Mutex<VirtIONet>,
+    mac: [u8; 6],
+    mtu: usize,
+    rx_event: Option<&'static Completion>,
+}
+
+impl NetDevice for VirtioNetDevice {
+    fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+    fn transmit(&self, frame: &[u8], csum: Option<(usize, usize)>) -> bool {
+        self.net.lock().transmit(frame, csum).is_ok()
+    }
+    fn receive(&self) -> Option<(Vec<u8>, bool)> {
+        self.net.lock().receive()
+    }
+    fn rx_event(&self) -> Option<&'static Completion> {
+        self.rx_event
+    }
+}
+
+pub fn probe(transport: &'static mut dyn Transport) -> SysResult<Device> {
+    let net = VirtIONet::new(transport).map_err(|e| {
+        pr_err!("virtio-net: init failed: {:?}\n", e);
+        Errno::EIO
+    })?;
+    let mac = net.mac;
+    pr_notice!(
+        "virtio-net: MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}, checksum offload {}\n",
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5],
+        net.features & VIRTIO_NET_F_CSUM != 0
+    );
+    let device: &'static VirtioNetDevice = Box::leak(Box::new(VirtioNetDevice {
+        mac,
+        mtu: net.mtu,
+        rx_event: net.transport.queue_event(RX_QUEUE),
+        net: Mutex::new(net),
+    }));
+    Ok(Device::new(device_name("eth"), DeviceClass::Net(device)))
+}
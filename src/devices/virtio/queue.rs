@@ -7,7 +7,7 @@ use tock_registers::interfaces::{Readable, Writeable};
 use tock_registers::registers::ReadWrite;
 
 use crate::align;
-use crate::devices::virtio::blk::Transport;
+use crate::devices::virtio::transport::Transport;
 use crate::mm::{PAGE_SIZE, VirtAddr};
 use crate::mm::heap::page_alloc;
 
@@ -42,12 +42,14 @@ impl VirtQueue {
         if transport.queue_used(idx as u32) {
             return Err(Error::AlreadyUsed);
         }
-        if !size.is_power_of_two() || transport.max_queue_size() < size as u32 {
+        if !size.is_power_of_two() || transport.max_queue_size(idx as u32) < size as u32 {
             return Err(Error::InvalidParam);
         }
         let layout = VirtQueueLayout::new(size);
         // Allocate contiguous pages.
-        let dma = page_alloc(layout.size / PAGE_SIZE).as_usize();
+        let dma = page_alloc(layout.size / PAGE_SIZE)
+            .map_err(|_| Error::DmaError)?
+            .as_usize();
         transport.queue_set(
             idx as u32,
             size as u32,
@@ -175,6 +177,17 @@ impl VirtQueue {
     ///
     /// Ref: linux virtio_ring.c virtqueue_get_buf_ctx
     pub fn pop_used(&mut self) -> Result<(u16, u32)> {
+        let (index, len) = self.take_used()?;
+        self.recycle_descriptors(index);
+        Ok((index, len))
+    }
+
+    /// Get a token from device used buffers like `pop_used`, its descriptors stay
+    /// allocated until `recycle`.
+    ///
+    /// For a queue shared by requests in flight: the token is not handed out again
+    /// before its owner saw it was used.
+    pub fn take_used(&mut self) -> Result<(u16, u32)> {
         if !self.can_pop() {
             return Err(Error::NotReady);
         }
@@ -185,12 +198,16 @@ impl VirtQueue {
         let index = self.get_used().ring[last_used_slot as usize].id.get() as u16;
         let len = self.get_used().ring[last_used_slot as usize].len.get();
 
-        self.recycle_descriptors(index);
         self.last_used_idx = self.last_used_idx.wrapping_add(1);
 
         Ok((index, len))
     }
 
+    /// Free the descriptors of a token taken with `take_used`.
+    pub fn recycle(&mut self, token: u16) {
+        self.recycle_descriptors(token)
+    }
+
     /// Return size of the queue.
     pub fn size(&self) -> u16 {
         self.queue_size
@@ -0,0 +1,102 @@
+#![allow(dead_code)]
+
+use bitflags::bitflags;
+
+use super::{Error, Result};
+use crate::task::sync::Completion;
+
+//http://docs.oasis-open.org/virtio/virtio/v1.3/virtio-v1.3.html
+bitflags! {
+    #[derive(Default, Debug)]
+    pub struct DeviceStatus: u8 {
+        const ACKNOWLEDGE = 1;
+        const DRIVER = 2;
+        const FAILED = 128;
+        const FEATURES_OK = 8;
+        const DRIVER_OK = 4;
+        const DEVICE_NEEDS_RESET = 64;
+    }
+}
+
+//device types, 5 Device Types
+pub const VIRTIO_TYPE_NET: u32 = 1;
+pub const VIRTIO_TYPE_BLOCK: u32 = 2;
+pub const VIRTIO_TYPE_CONSOLE: u32 = 3;
+pub const VIRTIO_TYPE_RNG: u32 = 4;
+
+//feature bits every device may offer, the device specific ones are below 24
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+//how the driver reaches a device: feature negotiation, its queues and the device
+//specific config space, whatever the bus
+pub trait Transport {
+    fn device_type(&self) -> u32;
+    fn read_device_features(&mut self) -> u64;
+    fn write_driver_features(&mut self, driver_features: u64);
+    //queues the device has
+    fn num_queues(&mut self) -> u32;
+    //0 when the queue does not exist
+    fn max_queue_size(&mut self, queue: u32) -> u32;
+    fn notify(&mut self, queue: u32);
+    fn status(&self) -> DeviceStatus;
+    fn set_status(&mut self, status: DeviceStatus);
+    fn reset(&mut self);
+    fn queue_set(&mut self, queue: u32, size: u32, desc: usize, driver: usize, device: usize);
+    fn queue_used(&mut self, queue: u32) -> bool;
+    //completed by the interrupt of queue, None when the device has no vector for it
+    fn queue_event(&self, queue: u32) -> Option<&'static Completion>;
+    //changes whenever the device config does
+    fn config_generation(&self) -> u32;
+    //bytes of the device config space at offset, false when it is not that large
+    fn read_config(&self, offset: usize, buf: &mut [u8]) -> bool;
+
+    //reset the device and take the features both sides support, 3.1.1 Driver Requirements:
+    //Device Initialization. The queues are set up next, then finish_init
+    fn begin_init(&mut self, supported: u64) -> Result<u64> {
+        self.reset();
+        self.set_status(DeviceStatus::ACKNOWLEDGE);
+        self.set_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER);
+        let features = self.read_device_features() & supported;
+        self.write_driver_features(features);
+        self.set_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER | DeviceStatus::FEATURES_OK);
+        if !self.status().contains(DeviceStatus::FEATURES_OK) {
+            self.set_status(DeviceStatus::FAILED);
+            return Err(Error::Unsupported);
+        }
+        Ok(features)
+    }
+    fn finish_init(&mut self) {
+        let status = self.status();
+        self.set_status(status | DeviceStatus::DRIVER_OK);
+    }
+}
+
+//a little endian config field, read again until the generation did not change
+pub fn read_config<const N: usize>(transport: &dyn Transport, offset: usize) -> Option<[u8; N]> {
+    let mut buf = [0u8; N];
+    loop {
+        let generation = transport.config_generation();
+        if !transport.read_config(offset, &mut buf) {
+            return None;
+        }
+        if generation == transport.config_generation() {
+            return Some(buf);
+        }
+    }
+}
+
+pub fn config_u8(transport: &dyn Transport, offset: usize) -> Option<u8> {
+    read_config::<1>(transport, offset).map(|buf| buf[0])
+}
+
+pub fn config_u16(transport: &dyn Transport, offset: usize) -> Option<u16> {
+    read_config(transport, offset).map(u16::from_le_bytes)
+}
+
+pub fn config_u32(transport: &dyn Transport, offset: usize) -> Option<u32> {
+    read_config(transport, offset).map(u32::from_le_bytes)
+}
+
+pub fn config_u64(transport: &dyn Transport, offset: usize) -> Option<u64> {
+    read_config(transport, offset).map(u64::from_le_bytes)
+}
@@ -14,6 +14,7 @@
 #![feature(const_option)]
 #![feature(stdsimd)]
 #![feature(mem_copy_fn)]
+#![cfg_attr(feature = "lockdep", feature(const_caller_location))]
 extern crate alloc;
 
 mod arch;
@@ -21,14 +22,17 @@ mod common;
 mod config;
 mod devices;
 mod mm;
+mod net;
 mod task;
 
 #[no_mangle]
 fn kernel_main() -> ! {
     arch::reg::DAIF::Irq.disable();
+    devices::early_console();
     mm::init();
     arch::init();
     devices::init();
+    net::init();
     #[cfg(feature = "test")]
     test::test_abort();
     task::init();
@@ -9,6 +9,10 @@ pub struct PTE(pub usize);
 
 impl PTE {
     const PHYS_ADDR_MASK: usize = PhyAddr::MAX & !(PAGE_SIZE - 1);
+    const SWAP_MARK: usize = 1 << 1;
+    const SWAP_SLOT_SHIFT: usize = 12;
+    //software bit of a valid descriptor, the frame is reference counted in shm
+    const SHARED_MARK: usize = 1 << 55;
 
     pub const fn empty() -> Self {
         Self(0)
@@ -45,10 +49,36 @@ impl PTE {
     pub fn clear(&mut self) {
         self.0 = 0
     }
+    //Access flag, cleared by page aging and set again by the access flag fault
+    pub fn is_accessed(&self) -> bool {
+        PTEAttr::from_bits_truncate(self.0).contains(PTEAttr::AF)
+    }
+    pub fn set_accessed(&mut self, accessed: bool) {
+        match accessed {
+            true => self.0 |= PTEAttr::AF.bits(),
+            false => self.0 &= !PTEAttr::AF.bits(),
+        }
+    }
+    pub fn is_shared(&self) -> bool {
+        self.is_valid() && self.0 & Self::SHARED_MARK != 0
+    }
+    pub fn set_shared(&mut self) {
+        self.0 |= Self::SHARED_MARK
+    }
+    //invalid descriptor holding a swap slot, bit 0 clear and SWAP_MARK set
+    pub fn new_swap(slot: usize) -> Self {
+        Self(Self::SWAP_MARK | (slot << Self::SWAP_SLOT_SHIFT))
+    }
+    pub fn is_swap(&self) -> bool {
+        !self.is_valid() && self.0 & Self::SWAP_MARK != 0
+    }
+    pub fn swap_slot(&self) -> usize {
+        self.0 >> Self::SWAP_SLOT_SHIFT
+    }
 
-    pub fn as_page<'a>(&mut self, mut allocator: impl FnMut() -> PhyAddr) -> Option<&'a mut [PTE]> {
+    pub fn as_page<'a>(&mut self, mut allocator: impl FnMut() -> Option<PhyAddr>) -> Option<&'a mut [PTE]> {
         if self.is_unused() {
-            let phy_addr = allocator();
+            let phy_addr = allocator()?;
             *self = PTE::new_table(phy_addr);
             Some(addr2slice!(
                 phy_addr.into_vaddr().as_mut_ptr(),
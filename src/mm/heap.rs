@@ -1,16 +1,20 @@
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use linked_list_allocator::Heap;
 
+use crate::common::errno::{Errno, SysResult};
 use crate::common::sync::MutexNoIrq;
-use crate::config::MEM_SIZE;
+use crate::arch::dtb;
 use crate::lds_address;
-use crate::mm::{PAGE_SIZE, VirtAddr};
+use crate::mm::{PhyAddr, PAGE_SIZE, VirtAddr};
 
+//user memory fails with ENOMEM and leaves swap::KERNEL_RESERVE alone,
+//running out of kernel heap is fatal
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: Layout) -> ! {
-    panic!("Heap allocation error, layout = {:?}", layout);
+    panic!("Heap allocation error, layout = {:?}", layout)
 }
 
 #[global_allocator]
@@ -49,10 +53,13 @@ impl LockedHeap {
 
 
 #[allow(dead_code)]
-pub fn page_alloc(pages: usize) -> VirtAddr {
+pub fn page_alloc(pages: usize) -> SysResult<VirtAddr> {
     let layout = Layout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap();
     let addr = unsafe { ALLOCATOR.alloc_zeroed(layout) };
-    VirtAddr::new(addr.addr())
+    match addr.is_null() {
+        true => Err(Errno::ENOMEM),
+        false => Ok(VirtAddr::new(addr.addr())),
+    }
 }
 
 #[allow(dead_code)]
@@ -65,9 +72,18 @@ pub fn page_free(start: VirtAddr, pages: usize) {
     }
 }
 
+//from the end of the kernel image to the end of its memory region
+static HEAP_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn heap_size() -> usize {
+    HEAP_SIZE.load(Ordering::Relaxed)
+}
+
 pub fn init_heap() {
-    let mem_size = MEM_SIZE - (lds_address!(heap_start) - lds_address!(text_start));
     let heap_start = lds_address!(heap_start);
+    let mem_end = dtb::memory_end(PhyAddr::from_virt(heap_start)).expect("no memory node for the kernel");
+    let mem_size = mem_end.as_usize() - PhyAddr::from_virt(heap_start).as_usize();
+    HEAP_SIZE.store(mem_size, Ordering::Relaxed);
     use crate::{pr_delimiter, pr_notice};
     pr_delimiter!();
     pr_notice!("{: ^56} \r\n", "Heap init");
@@ -1,12 +1,14 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use arrayvec::ArrayVec;
 use lazy_static::lazy_static;
 
-use crate::{lds_address, reg_write_p};
+use crate::{align_down, align_up, lds_address, reg_write_p};
 use crate::{pr_address, pr_delimiter, pr_notice};
-use crate::config::{
-    GICC_BASE, GICC_SIZE, GICD_BASE, GICD_SIZE, MEM_SIZE, PCIE_CONFIG_SPACE_START,
-    PCIE_MEM_64_START, UART_ADDRESS,
-};
+use crate::common::errno::SysResult;
+use crate::mm::heap;
 use crate::mm::{BLOCK_2M, PAGE_SIZE, PageTable, PhyAddr, PTEFlags, VirtAddr};
+use crate::arch::reg::SCTLR_EL1;
 use crate::mm::flush::{dsb_all, isb_all, tlb_all};
 
 use super::super::common::sync::Mutex;
@@ -15,7 +17,7 @@ lazy_static! {
     #[link_section = ".data.kernel_root"]
     static ref KERNEL_SPACE: Mutex<PageTable> = {
         let mut k = PageTable::empty();
-        k.init();
+        k.init().expect("kernel page table");
         Mutex::new(k)
     };
 }
@@ -23,60 +25,93 @@ pub fn map_area(va_start: VirtAddr, pa_start: PhyAddr, size: usize, flags: PTEFl
     pr_delimiter!();
     pr_address!(name, va_start, size, flags);
     match KERNEL_SPACE.lock() {
-        mut lock => lock.map_area(va_start, pa_start, size, flags, true),
+        mut lock => lock.map_area(va_start, pa_start, size, flags, true).expect("kernel page table"),
     }
 }
 
+//map pages into kernel space at runtime, without the boot time log
+pub fn map_kernel(va_start: VirtAddr, pa_start: PhyAddr, size: usize, flags: PTEFlags) -> SysResult<()> {
+    let result = KERNEL_SPACE.lock().map_area(va_start, pa_start, size, flags, false);
+    dsb_all();
+    isb_all();
+    result
+}
+
+//device memory in 2M blocks, such as PCI config space. Blocks mapped before are kept
+fn map_kernel_blocks(pa_start: PhyAddr, size: usize, flags: PTEFlags) {
+    let mut lock = KERNEL_SPACE.lock();
+    let start = pa_start.as_usize();
+    let mut pa = align_down!(start, BLOCK_2M);
+    while pa < start + size {
+        let va = VirtAddr::from_phy(pa);
+        if lock.query(va, PageTable::L1).is_none() {
+            lock.map_block_2m(va, PhyAddr::new(pa), flags, false);
+        }
+        pa += BLOCK_2M;
+    }
+    drop(lock);
+    dsb_all();
+    isb_all();
+}
+
+pub fn unmap_kernel(va_start: VirtAddr, size: usize) {
+    KERNEL_SPACE.lock().unmap_area(va_start, size);
+    dsb_all();
+    tlb_all();
+    isb_all();
+}
+
+//ioremap before the kernel page table is built, e.g. for the console. The boot page
+//table maps the first 1G of device memory until then
+static EARLY_IOREMAP: Mutex<ArrayVec<(PhyAddr, usize), 4>> = Mutex::new(ArrayVec::new_const());
+static KERNEL_SPACE_READY: AtomicBool = AtomicBool::new(false);
+
+//device registers for the kernel at the linear address of pa. Regions of 2M or more
+//go in blocks, pages already mapped by another ioremap are kept
+pub fn ioremap(pa: PhyAddr, size: usize) -> SysResult<VirtAddr> {
+    let va = VirtAddr::from_phy(pa.as_usize());
+    if !KERNEL_SPACE_READY.load(Ordering::Acquire) {
+        EARLY_IOREMAP.lock().push((pa, size));
+        return Ok(va);
+    }
+    if size >= BLOCK_2M {
+        map_kernel_blocks(pa, size, PTEFlags::RW | PTEFlags::D);
+        return Ok(va);
+    }
+    let start = align_down!(pa.as_usize(), PAGE_SIZE);
+    let end = align_up!(pa.as_usize() + size, PAGE_SIZE);
+    let mut lock = KERNEL_SPACE.lock();
+    for page in (start..end).step_by(PAGE_SIZE) {
+        let page_va = VirtAddr::from_phy(page);
+        if lock.query(page_va, PageTable::L1).is_some() || lock.query(page_va, PageTable::L0).is_some() {
+            continue;
+        }
+        lock.map_page(page_va, PhyAddr::new(page), PTEFlags::RW | PTEFlags::D, false)?;
+    }
+    drop(lock);
+    dsb_all();
+    isb_all();
+    Ok(va)
+}
+
+//pages only, a block stays
+#[allow(dead_code)]
+pub fn iounmap(va: VirtAddr, size: usize) {
+    let start = align_down!(va.as_usize(), PAGE_SIZE);
+    let end = align_up!(va.as_usize() + size, PAGE_SIZE);
+    unmap_kernel(VirtAddr::new(start), end - start);
+}
+
 pub fn init_kernel_space() {
     pr_notice!("{: ^56} \r\n", "Init Kernel page table");
-    match KERNEL_SPACE.lock() {
-        mut lock => {
-            pr_delimiter!();
-            pr_address!("pcie", VirtAddr::from_phy(PCIE_CONFIG_SPACE_START), BLOCK_2M, PTEFlags::RW | PTEFlags::D);
-            //pcie config space
-            lock.map_block_2m(
-                VirtAddr::from_phy(PCIE_CONFIG_SPACE_START),
-                PhyAddr::new(PCIE_CONFIG_SPACE_START),
-                PTEFlags::RW | PTEFlags::D,
-                true,
-            );
-            pr_address!("", VirtAddr::from_phy(PCIE_MEM_64_START), BLOCK_2M, PTEFlags::RW | PTEFlags::D);
-            //pcie mem64
-            lock.map_block_2m(
-                VirtAddr::from_phy(PCIE_MEM_64_START),
-                PhyAddr::new(PCIE_MEM_64_START),
-                PTEFlags::RW | PTEFlags::D,
-                true,
-            );
-        }
+    //devices are mapped with ioremap, the ones from before now
+    KERNEL_SPACE_READY.store(true, Ordering::Release);
+    let early = core::mem::take(&mut *EARLY_IOREMAP.lock());
+    for (pa, size) in early {
+        pr_delimiter!();
+        pr_address!("io", VirtAddr::from_phy(pa.as_usize()), size, PTEFlags::RW | PTEFlags::D);
+        ioremap(pa, size).expect("kernel page table");
     }
-    //uart
-    let (start, size) = (UART_ADDRESS, PAGE_SIZE);
-    map_area(
-        VirtAddr::from_phy(start),
-        PhyAddr::new(start),
-        size,
-        PTEFlags::RW | PTEFlags::D,
-        "uart",
-    );
-    //gicd
-    let (start, size) = (GICD_BASE, GICD_SIZE);
-    map_area(
-        VirtAddr::from_phy(start),
-        PhyAddr::new(start),
-        size,
-        PTEFlags::RW | PTEFlags::D,
-        "gic",
-    );
-    //gicc
-    let (start, size) = (GICC_BASE, GICC_SIZE);
-    map_area(
-        VirtAddr::from_phy(start),
-        PhyAddr::new(start),
-        size,
-        PTEFlags::RW | PTEFlags::D,
-        "",
-    );
 
     //text
     let (start, size) = (
@@ -154,10 +189,7 @@ pub fn init_kernel_space() {
         "symbols",
     );
     //heap
-    let (start, size) = (
-        lds_address!(heap_start),
-        MEM_SIZE - (lds_address!(heap_start) - lds_address!(text_start)),
-    );
+    let (start, size) = (lds_address!(heap_start), heap::heap_size());
     map_area(
         VirtAddr::new(start),
         PhyAddr::from_virt(start),
@@ -170,6 +202,9 @@ pub fn init_kernel_space() {
     let page_table_root = kernel_space.root_addr();
     enable_table(page_table_root.as_usize(), true);
     enable_table(0, false);
+    //boot page table maps the kernel RWX, W^X only after switching away from it
+    SCTLR_EL1::set_field(SCTLR_EL1::WXN);
+    tlb_all();
 }
 
 #[no_mangle]
@@ -1,7 +1,8 @@
 pub use address::{PhyAddr, VirtAddr};
 pub use attr::PTEFlags;
 pub use entry::PTE;
-pub use mem::enable_table;
+#[allow(unused_imports)]
+pub use mem::{enable_table, ioremap, iounmap, map_kernel, unmap_kernel};
 pub use page::PageTable;
 #[allow(unused_imports)]
 pub use user::{UserBuffer, UserPtr};
@@ -14,12 +15,17 @@ mod attr;
 mod entry;
 mod mem;
 mod user;
+pub mod swap;
+pub mod oom;
+pub mod shm;
 
 pub const PAGE_SIZE: usize = 0x1000;
 pub const BLOCK_2M: usize = PAGE_SIZE * 0x200;
 pub const USER_START: usize = 0x0000_0000_0000_0000;
 pub const USER_END: usize = 0x0000_FFFF_FFFF_FFFF;
 pub const KERNEL_START: usize = 0xFFFF_0000_0000_0000;
+//kernel stacks with guard pages, outside of the linear mapping
+pub const KERNEL_STACK_AREA: usize = 0xFFFF_FF00_0000_0000;
 #[allow(dead_code)]
 pub const KERNEL_END: usize = 0xFFFF_FFFF_FFFF_FFFF;
 #[macro_export]
@@ -63,5 +69,6 @@ macro_rules! mem_set {
 
 pub fn init() {
     heap::init_heap();
+    crate::arch::dtb::init();
     mem::init_kernel_space();
 }
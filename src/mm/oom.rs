@@ -0,0 +1,51 @@
+use alloc::sync::{Arc, Weak};
+
+use crate::common::sync::Mutex;
+use crate::pr_err;
+use crate::task::scheduler;
+use crate::task::signal::{self, SIGKILL};
+use crate::task::mem::UserSpace;
+use crate::task::types::TaskId;
+
+//the address space of the last victim, its memory is freed once every thread using it
+//exited and was switched away from
+static VICTIM: Mutex<Option<Weak<Mutex<UserSpace>>>> = Mutex::new(None);
+
+//a victim was killed and did not free its memory yet
+pub fn pending() -> bool {
+    VICTIM.lock().as_ref().is_some_and(|vm| vm.strong_count() > 0)
+}
+
+//the address space holding the most resident and swapped out pages,
+//one locked by a faulting task is not considered
+fn select_victim() -> Option<(Arc<Mutex<UserSpace>>, TaskId, usize)> {
+    let mut victim: Option<(Arc<Mutex<UserSpace>>, TaskId, usize)> = None;
+    scheduler::for_each_task(|task| {
+        if !task.is_user() || task.state.is_exited() {
+            return;
+        }
+        let pages = match task.page.try_lock() {
+            Some(mut vm) => vm.pages(),
+            None => return,
+        };
+        if victim.as_ref().map_or(true, |(_, _, max)| pages > *max) {
+            victim = Some((task.page.clone(), task.pid(), pages));
+        }
+    });
+    victim
+}
+
+//SIGKILL to every thread of the largest address space, its memory is freed on their
+//own exit path. Nobody else is picked while the last victim is still dying
+pub fn out_of_memory() {
+    if pending() {
+        return;
+    }
+    let (vm, pid, pages) = match select_victim() {
+        None => return,
+        Some(victim) => victim,
+    };
+    pr_err!("Out of memory: kill {} with {} pages\n", pid.as_usize(), pages);
+    let _ = signal::send_where(SIGKILL, |task| Arc::ptr_eq(&task.page, &vm));
+    *VICTIM.lock() = Some(Arc::downgrade(&vm));
+}
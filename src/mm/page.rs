@@ -5,6 +5,7 @@ use core::alloc::{GlobalAlloc, Layout};
 use lazy_static::lazy_static;
 
 use crate::{addr2slice, align_up};
+use crate::common::errno::{Errno, SysResult};
 use crate::mm::attr::PTEFlags;
 use crate::mm::entry::PTE;
 use crate::mm::heap::{LockedHeap, page_alloc};
@@ -18,17 +19,20 @@ pub const PAGE_ENTRY_COUNT: usize = 512;
 lazy_static!{
     static ref PTALLOCATOR: LockedHeap = {
         let pt = LockedHeap::empty();
-        let addr = page_alloc(PAGE_SIZE);
+        let addr = page_alloc(PAGE_SIZE).expect("page table pool");
         pt.init(addr.as_usize(), PAGE_SIZE * PAGE_SIZE);
         pt
     };
 }
 
 #[allow(dead_code)]
-pub fn frame_alloc(pages: usize) -> VirtAddr {
+pub fn frame_alloc(pages: usize) -> SysResult<VirtAddr> {
     let layout = Layout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap();
     let addr = unsafe { PTALLOCATOR.alloc_zeroed(layout) };
-    VirtAddr::new(addr.addr())
+    match addr.is_null() {
+        true => Err(Errno::ENOMEM),
+        false => Ok(VirtAddr::new(addr.addr())),
+    }
 }
 
 #[allow(dead_code)]
@@ -55,11 +59,12 @@ impl PageTable {
             root_addr: PhyAddr::new(0),
         }
     }
-    pub fn init(&mut self) {
-        self.root_addr = frame_alloc(1).as_phy()
+    pub fn init(&mut self) -> SysResult<()> {
+        self.root_addr = frame_alloc(1)?.as_phy();
+        Ok(())
     }
-    fn alloc_page(&mut self) -> PhyAddr {
-        frame_alloc(1).as_phy()
+    fn alloc_page(&mut self) -> Option<PhyAddr> {
+        frame_alloc(1).ok().map(|page| page.as_phy())
     }
     pub const fn root_addr(&self) -> PhyAddr {
         self.root_addr
@@ -91,6 +96,50 @@ impl PageTable {
         ))
     }
 
+    //last level entry of a 4k page
+    pub fn entry(&mut self, vaddr: VirtAddr) -> Option<&mut PTE> {
+        self.find_entry(vaddr.align_down_4k(), Self::L0)
+    }
+    //walk all used last level entries, invalid ones (swap entries) included
+    pub fn for_each_page(&mut self, mut f: impl FnMut(VirtAddr, &mut PTE)) {
+        fn walk(entrys: &mut [PTE], level: usize, va: usize, f: &mut dyn FnMut(VirtAddr, &mut PTE)) {
+            for (i, entry) in entrys.iter_mut().enumerate() {
+                let va = va | (i << (VirtAddr::PAGE_DIR_OFFSET + 9 * level));
+                if entry.is_unused() {
+                    continue;
+                }
+                if level == PageTable::L0 {
+                    f(VirtAddr::new(va), entry);
+                } else if entry.is_valid() && !entry.is_block() {
+                    let next = addr2slice!(entry.as_phy_addr().into_vaddr().as_mut_ptr(), PAGE_ENTRY_COUNT, PTE);
+                    walk(next, level - 1, va, f);
+                }
+            }
+        }
+        if self.root_addr.as_usize() != 0 {
+            walk(self.entrys(), Self::L3, 0, &mut f);
+        }
+    }
+    //hand every last level entry to f, then free the table frames themselves
+    pub fn destroy(&mut self, f: impl FnMut(VirtAddr, &mut PTE)) {
+        fn free(entrys: &mut [PTE], level: usize) {
+            for entry in entrys.iter_mut().filter(|entry| entry.is_valid() && !entry.is_block()) {
+                let next = entry.as_phy_addr().into_vaddr();
+                if level > PageTable::L1 {
+                    free(addr2slice!(next.as_mut_ptr(), PAGE_ENTRY_COUNT, PTE), level - 1);
+                }
+                frame_free(next, 1);
+            }
+        }
+        if self.root_addr.as_usize() == 0 {
+            return;
+        }
+        self.for_each_page(f);
+        free(self.entrys(), Self::L3);
+        frame_free(self.root_addr.into_vaddr(), 1);
+        self.root_addr = PhyAddr::new(0);
+    }
+
     fn find_block(&mut self, vaddr: VirtAddr) -> Option<&mut PTE> {
         self.find_entry(vaddr, Self::L1)
     }
@@ -125,9 +174,10 @@ impl PageTable {
             }
         }
     }
-    pub fn map_page(&mut self, vaddr: VirtAddr, phy_addr: PhyAddr, flags: PTEFlags, force: bool) {
+    //Err(ENOMEM) when a table frame for the walk can not be allocated
+    pub fn map_page(&mut self, vaddr: VirtAddr, phy_addr: PhyAddr, flags: PTEFlags, force: bool) -> SysResult<()> {
         match self.find_entry(vaddr.align_down_4k(), Self::L0) {
-            None => panic!("can not find entry of addr: {:#x}", vaddr.as_usize()),
+            None => Err(Errno::ENOMEM),
             Some(entry) => {
                 if !entry.is_unused() && !force {
                     panic!(
@@ -138,6 +188,7 @@ impl PageTable {
                     );
                 }
                 *entry = PTE::new_entry(phy_addr.align_down(), flags, false);
+                Ok(())
             }
         }
     }
@@ -158,17 +209,18 @@ impl PageTable {
         size: usize,
         flags: PTEFlags,
         force: bool,
-    ) {
+    ) -> SysResult<()> {
         let mut va_start = vaddr.align_down_4k().as_usize();
         let mut pa_start = phy_addr.align_down().as_usize();
         let size = align_up!(size, PAGE_SIZE);
         let end = va_start + size;
         while va_start < end {
             let start_pa = PhyAddr::new(pa_start);
-            self.map_page(VirtAddr::new(va_start), start_pa, flags, force);
+            self.map_page(VirtAddr::new(va_start), start_pa, flags, force)?;
             va_start += PAGE_SIZE;
             pa_start += PAGE_SIZE;
         }
+        Ok(())
     }
 
     pub fn unmap_area(&mut self, vaddr: VirtAddr, size: usize) {
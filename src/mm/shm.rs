@@ -0,0 +1,131 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::mm::{PAGE_SIZE, PhyAddr, swap};
+use crate::mm::heap::page_free;
+
+//https://man7.org/linux/man-pages/man2/shmget.2.html
+pub const IPC_PRIVATE: usize = 0;
+pub const IPC_CREAT: usize = 0o1000;
+pub const IPC_EXCL: usize = 0o2000;
+pub const IPC_RMID: usize = 0;
+pub const SHM_RDONLY: usize = 0o10000;
+const SHM_MAX: usize = 0x400_0000;
+
+//frames mapped by more than one owner, an owner being a page table entry
+//or a segment, the frame goes back to the heap with its last reference
+static FRAME_REFS: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+static SEGMENTS: Mutex<BTreeMap<usize, Segment>> = Mutex::new(BTreeMap::new());
+
+struct Segment {
+    key: usize,
+    frames: Vec<PhyAddr>,
+    attached: usize,
+    removed: bool,
+}
+
+impl Segment {
+    fn destroy(self) {
+        self.frames.into_iter().for_each(put_frame);
+    }
+}
+
+pub fn get_frame(frame: PhyAddr) {
+    *FRAME_REFS.lock().entry(frame.as_usize()).or_insert(0) += 1;
+}
+
+pub fn put_frame(frame: PhyAddr) {
+    let mut refs = FRAME_REFS.lock();
+    let count = (*refs).get_mut(&frame.as_usize()).expect("put of an unshared frame");
+    *count -= 1;
+    if *count == 0 {
+        refs.remove(&frame.as_usize());
+        drop(refs);
+        page_free(frame.into_vaddr(), 1);
+    }
+}
+
+//zeroed frames holding one reference each
+pub fn alloc_frames(pages: usize) -> SysResult<Vec<PhyAddr>> {
+    let mut frames = Vec::new();
+    frames.try_reserve_exact(pages).map_err(|_| Errno::ENOMEM)?;
+    for _ in 0..pages {
+        match swap::alloc_user_page() {
+            Ok(page) => {
+                get_frame(page.as_phy());
+                frames.push(page.as_phy());
+            }
+            Err(errno) => {
+                frames.into_iter().for_each(put_frame);
+                return Err(errno);
+            }
+        }
+    }
+    Ok(frames)
+}
+
+pub fn shmget(key: usize, size: usize, flags: usize) -> SysResult {
+    let mut segments = SEGMENTS.lock();
+    if key != IPC_PRIVATE {
+        if let Some((&id, _)) = segments.iter().find(|(_, seg)| seg.key == key && !seg.removed) {
+            return match flags & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL {
+                true => Err(Errno::EEXIST),
+                false => Ok(id),
+            };
+        }
+        if flags & IPC_CREAT == 0 {
+            return Err(Errno::ENOENT);
+        }
+    }
+    if size == 0 || size > SHM_MAX {
+        return Err(Errno::EINVAL);
+    }
+    let frames = alloc_frames((size + PAGE_SIZE - 1) / PAGE_SIZE)?;
+    let id = segments.last_key_value().map_or(1, |(id, _)| id + 1);
+    segments.insert(id, Segment { key, frames, attached: 0, removed: false });
+    Ok(id)
+}
+
+//frames of the segment with an extra reference each, for the caller to map
+pub fn attach(id: usize) -> SysResult<Vec<PhyAddr>> {
+    let mut segments = SEGMENTS.lock();
+    let segment = (*segments).get_mut(&id).filter(|seg| !seg.removed).ok_or(Errno::EINVAL)?;
+    segment.attached += 1;
+    segment.frames.iter().for_each(|frame| get_frame(*frame));
+    Ok(segment.frames.clone())
+}
+
+//an inherited attachment, the frames are already referenced by the copied entries
+pub fn attach_fork(id: usize) {
+    if let Some(segment) = (*SEGMENTS.lock()).get_mut(&id) {
+        segment.attached += 1;
+    }
+}
+
+//the caller drops the references of its entries
+pub fn detach(id: usize) {
+    let mut segments = SEGMENTS.lock();
+    if let Some(segment) = (*segments).get_mut(&id) {
+        segment.attached -= 1;
+        if segment.removed && segment.attached == 0 {
+            segments.remove(&id).unwrap().destroy();
+        }
+    }
+}
+
+pub fn shmctl(id: usize, cmd: usize) -> SysResult {
+    let mut segments = SEGMENTS.lock();
+    let segment = (*segments).get_mut(&id).ok_or(Errno::EINVAL)?;
+    match cmd {
+        IPC_RMID => {
+            segment.removed = true;
+            if segment.attached == 0 {
+                segments.remove(&id).unwrap().destroy();
+            }
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
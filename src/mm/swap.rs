@@ -0,0 +1,247 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::devices::{block_size, read_block_atomic, write_block_atomic};
+use crate::devices::mbr::{partition, PARTITION_LINUX_SWAP};
+use crate::mm::{oom, PAGE_SIZE, PageTable, PTE, PTEFlags, VirtAddr};
+use crate::mm::flush::{dsb_all, isb_all, tlb_all};
+use crate::mm::heap::{ALLOCATOR, page_alloc, page_free};
+use crate::pr_notice;
+use crate::task::mem::for_each_vm;
+
+//free heap below LOW_WATERMARK starts reclaim, which stops at HIGH_WATERMARK
+const LOW_WATERMARK: usize = 0x40_0000;
+const HIGH_WATERMARK: usize = 0x80_0000;
+//heap left to the kernel, whose own allocations can not fail. User pages never take it
+const KERNEL_RESERVE: usize = 0x20_0000;
+
+static SWAP: Mutex<Option<SwapArea>> = Mutex::new(None);
+
+struct SwapArea {
+    partition: usize,
+    start_sector: usize,
+    slots: usize,
+    used: usize,
+    bitmap: Vec<u64>,
+}
+
+impl SwapArea {
+    fn sectors_per_page() -> usize {
+        PAGE_SIZE / block_size()
+    }
+    fn alloc_slot(&mut self) -> Option<usize> {
+        let (index, bits) = self.bitmap.iter_mut().enumerate().find(|(_, bits)| **bits != u64::MAX)?;
+        let slot = index * 64 + bits.trailing_ones() as usize;
+        if slot >= self.slots {
+            return None;
+        }
+        *bits |= 1 << (slot % 64);
+        self.used += 1;
+        Some(slot)
+    }
+    fn free_slot(&mut self, slot: usize) {
+        self.bitmap[slot / 64] &= !(1 << (slot % 64));
+        self.used -= 1;
+    }
+    //swap I/O runs under SWAP and often under the page table lock of a faulting task,
+    //it polls the disk instead of sleeping
+    fn write_page(&self, slot: usize, page: VirtAddr) -> bool {
+        let sector = self.start_sector + slot * Self::sectors_per_page();
+        let data = unsafe { core::slice::from_raw_parts(page.as_mut_ptr(), PAGE_SIZE) };
+        data.chunks(block_size())
+            .enumerate()
+            .all(|(i, buf)| write_block_atomic(sector + i, buf))
+    }
+    fn read_page(&self, slot: usize, page: VirtAddr) -> bool {
+        let sector = self.start_sector + slot * Self::sectors_per_page();
+        let data = unsafe { core::slice::from_raw_parts_mut(page.as_mut_ptr(), PAGE_SIZE) };
+        data.chunks_mut(block_size())
+            .enumerate()
+            .all(|(i, buf)| read_block_atomic(sector + i, buf))
+    }
+    //evict one page, the entry becomes a swap entry
+    fn swap_out(&mut self, entry: &mut PTE) -> bool {
+        let page = entry.as_phy_addr().into_vaddr();
+        let slot = match self.alloc_slot() {
+            None => return false,
+            Some(slot) => slot,
+        };
+        if !self.write_page(slot, page) {
+            self.free_slot(slot);
+            return false;
+        }
+        *entry = PTE::new_swap(slot);
+        page_free(page, 1);
+        true
+    }
+    fn load(&mut self, entry: &mut PTE, page: VirtAddr) -> bool {
+        let slot = entry.swap_slot();
+        if !self.read_page(slot, page) {
+            return false;
+        }
+        self.free_slot(slot);
+        *entry = PTE::new_entry(page.as_phy(), PTEFlags::RW | PTEFlags::U, false);
+        true
+    }
+}
+
+fn free_memory() -> usize {
+    ALLOCATOR.get().lock().free()
+}
+
+//only private writable user pages are swapped, text, rodata and shared memory stay resident
+fn swappable(entry: &PTE) -> bool {
+    entry.is_valid() && !entry.is_shared() && entry.flags().contains(PTEFlags::U | PTEFlags::W)
+}
+
+//second chance over all user pages: a page accessed since the last pass
+//gets its access flag cleared, an old one is written out to swap
+fn reclaim(area: &mut SwapArea, pages: usize) -> usize {
+    let mut reclaimed = 0;
+    for _pass in 0..2 {
+        //the address space of a faulting task is locked and left alone
+        for_each_vm(|vm| {
+            vm.page_table().for_each_page(|_, entry| {
+                if reclaimed >= pages || !swappable(entry) {
+                    return;
+                }
+                if entry.is_accessed() {
+                    entry.set_accessed(false);
+                } else if area.swap_out(entry) {
+                    reclaimed += 1;
+                }
+            })
+        });
+        tlb_all();
+        if reclaimed >= pages {
+            break;
+        }
+    }
+    dsb_all();
+    isb_all();
+    reclaimed
+}
+
+//frame for a user page, reclaims memory first when running low. ENOMEM when even
+//that is not enough or only KERNEL_RESERVE is left, the OOM killer frees memory for
+//a later attempt
+pub fn alloc_user_page() -> SysResult<VirtAddr> {
+    if free_memory() < LOW_WATERMARK {
+        if let Some(area) = SWAP.lock().as_mut() {
+            reclaim(area, (HIGH_WATERMARK - free_memory()) / PAGE_SIZE);
+        }
+    }
+    alloc_above_reserve()
+}
+
+fn alloc_above_reserve() -> SysResult<VirtAddr> {
+    let page = match free_memory() < KERNEL_RESERVE + PAGE_SIZE {
+        true => Err(Errno::ENOMEM),
+        false => page_alloc(1),
+    };
+    page.map_err(|errno| {
+        oom::out_of_memory();
+        errno
+    })
+}
+
+//copy of a swapped out page that stays in swap, for fork
+pub fn read_slot(entry: &PTE, page: VirtAddr) -> bool {
+    match SWAP.lock().as_ref() {
+        Some(area) => area.read_page(entry.swap_slot(), page),
+        None => false,
+    }
+}
+
+//slot of a swap entry that is thrown away without being read back
+pub fn free_slot(entry: &PTE) {
+    if let Some(area) = SWAP.lock().as_mut() {
+        area.free_slot(entry.swap_slot());
+    }
+}
+
+pub fn swap_in(page_table: &mut PageTable, vaddr: VirtAddr) -> bool {
+    match page_table.entry(vaddr) {
+        Some(entry) if entry.is_swap() => {}
+        _ => return false,
+    }
+    let page = match alloc_user_page() {
+        Ok(page) => page,
+        Err(_) => return false,
+    };
+    let loaded = match (SWAP.lock().as_mut(), page_table.entry(vaddr)) {
+        (Some(area), Some(entry)) => area.load(entry, page),
+        _ => false,
+    };
+    if !loaded {
+        page_free(page, 1);
+    }
+    dsb_all();
+    isb_all();
+    loaded
+}
+
+pub fn swapon(index: usize) -> SysResult {
+    //reading the partition table may sleep, before SWAP is taken
+    let part = partition(index).ok_or(Errno::ENOENT)?;
+    let mut swap = SWAP.lock();
+    if swap.is_some() {
+        return Err(Errno::EBUSY);
+    }
+    if part.part_type != PARTITION_LINUX_SWAP {
+        return Err(Errno::EINVAL);
+    }
+    let slots = part.sectors / SwapArea::sectors_per_page();
+    if slots == 0 {
+        return Err(Errno::EINVAL);
+    }
+    pr_notice!("swap: partition {} {}KB\n", index, slots * PAGE_SIZE / 1024);
+    swap.replace(SwapArea {
+        partition: index,
+        start_sector: part.start_lba,
+        slots,
+        used: 0,
+        bitmap: vec![0; (slots + 63) / 64],
+    });
+    Ok(0)
+}
+
+//bring every swapped out page back before the area goes away. Reclaim would only
+//write pages to this area again, so all of them have to fit into free memory
+//above KERNEL_RESERVE up front. An area left half drained stays on and is consistent
+pub fn swapoff(index: usize) -> SysResult {
+    let mut swap = SWAP.lock();
+    let area = match swap.as_mut() {
+        Some(area) if area.partition == index => area,
+        _ => return Err(Errno::EINVAL),
+    };
+    if area.used * PAGE_SIZE + KERNEL_RESERVE > free_memory() {
+        return Err(Errno::ENOMEM);
+    }
+    let mut result = Ok(0);
+    let complete = for_each_vm(|vm| {
+        vm.page_table().for_each_page(|_, entry| {
+            if result.is_err() || !entry.is_swap() {
+                return;
+            }
+            match alloc_above_reserve() {
+                Err(errno) => result = Err(errno),
+                Ok(page) => if !area.load(entry, page) {
+                    page_free(page, 1);
+                    result = Err(Errno::EIO);
+                },
+            }
+        })
+    });
+    dsb_all();
+    isb_all();
+    if result.is_ok() && !complete {
+        result = Err(Errno::EBUSY);
+    }
+    if result.is_ok() {
+        swap.take();
+    }
+    result
+}
@@ -1,8 +1,8 @@
 use alloc::string::String;
-use alloc::vec;
 use alloc::vec::Vec;
 
 use crate::addr2slice;
+use crate::arch::reg::set_pan;
 use crate::mm::{USER_END, USER_START};
 
 macro_rules! user_ptr_ok {
@@ -20,8 +20,11 @@ where
 }
 
 impl<T: Clone + From<u8>> UserBuffer<T> for Vec<T> {
+    //None when the kernel heap can not hold the copy
     fn copy_from_user(user_src: UserPtr<T>) -> Option<Vec<T>> {
-        let mut buffer = vec![T::from(0u8); user_src.len()];
+        let mut buffer = Vec::new();
+        buffer.try_reserve_exact(user_src.len()).ok()?;
+        buffer.resize(user_src.len(), T::from(0u8));
         user_src.copy_to(buffer.as_mut_slice(), user_src.len());
         Some(buffer)
     }
@@ -47,6 +50,24 @@ impl UserBuffer<u8> for String {
     }
 }
 
+//PSTATE.PAN is cleared only while a guard is alive, inside the user copy routines
+struct UserAccess;
+
+impl UserAccess {
+    #[inline(always)]
+    fn begin() -> Self {
+        set_pan(false);
+        Self
+    }
+}
+
+impl Drop for UserAccess {
+    #[inline(always)]
+    fn drop(&mut self) {
+        set_pan(true)
+    }
+}
+
 #[repr(transparent)]
 pub struct UserPtr<T: 'static> {
     ptr: &'static mut [T],
@@ -72,10 +93,12 @@ impl<T> UserPtr<T> {
 
     pub fn copy_to(&self, dst: &mut [T], len: usize) {
         user_ptr_ok!(self.as_ptr().addr(), self.len());
+        let _access = UserAccess::begin();
         unsafe { self.as_ptr().copy_to_nonoverlapping(dst.as_mut_ptr(), len) }
     }
     pub fn copy_from(&mut self, buf: &[T], len: usize) {
         user_ptr_ok!(self.as_ptr().addr(), self.len());
+        let _access = UserAccess::begin();
         unsafe {
             self.as_mut_ptr()
                 .copy_from_nonoverlapping(buf.as_ptr(), len)
@@ -0,0 +1,162 @@
+use alloc::vec::Vec;
+
+use super::ethernet::{self, ETH_P_ARP, ETH_P_IP};
+use super::{Interface, Ipv4Addr, MacAddr};
+use crate::arch::time_ms;
+use crate::common::sync::Mutex;
+
+//RFC 826 for IPv4 over ethernet. Packets to an address that is not resolved yet wait
+//for the reply, the request is sent again every tick until they expire
+
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+const PACKET_LEN: usize = 28;
+const ENTRY_MS: u64 = 5 * 60 * 1000;
+const PENDING_MS: u64 = 3000;
+const MAX_PENDING: usize = 64;
+
+struct Entry {
+    addr: Ipv4Addr,
+    mac: MacAddr,
+    expires: u64,
+}
+
+struct Pending {
+    iface: &'static Interface,
+    next_hop: Ipv4Addr,
+    packet: Vec<u8>,
+    csum: Option<(usize, usize)>,
+    expires: u64,
+}
+
+struct Arp {
+    cache: Vec<Entry>,
+    pending: Vec<Pending>,
+}
+
+static ARP: Mutex<Arp> = Mutex::new(Arp {
+    cache: Vec::new(),
+    pending: Vec::new(),
+});
+
+pub fn lookup(addr: Ipv4Addr) -> Option<MacAddr> {
+    let now = time_ms();
+    ARP.lock()
+        .cache
+        .iter()
+        .find(|entry| entry.addr == addr && entry.expires > now)
+        .map(|entry| entry.mac)
+}
+
+fn update(arp: &mut Arp, addr: Ipv4Addr, mac: MacAddr) {
+    let expires = time_ms() + ENTRY_MS;
+    match arp.cache.iter_mut().find(|entry| entry.addr == addr) {
+        Some(entry) => {
+            entry.mac = mac;
+            entry.expires = expires;
+        }
+        None => arp.cache.push(Entry { addr, mac, expires }),
+    }
+}
+
+fn send(iface: &Interface, op: u16, target_mac: MacAddr, target: Ipv4Addr) {
+    let mut packet = Vec::with_capacity(PACKET_LEN);
+    packet.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+    packet.extend_from_slice(&ETH_P_IP.to_be_bytes());
+    packet.extend_from_slice(&[6, 4]);
+    packet.extend_from_slice(&op.to_be_bytes());
+    packet.extend_from_slice(&iface.mac.0);
+    packet.extend_from_slice(&iface.addr.0);
+    packet.extend_from_slice(&target_mac.0);
+    packet.extend_from_slice(&target.0);
+    let dst = match op {
+        OP_REQUEST => MacAddr::BROADCAST,
+        _ => target_mac,
+    };
+    ethernet::output(iface, dst, ETH_P_ARP, &packet, None);
+}
+
+pub fn request(iface: &Interface, addr: Ipv4Addr) {
+    send(iface, OP_REQUEST, MacAddr::default(), addr)
+}
+
+//an IPv4 packet to next_hop, queued until its address is resolved
+pub fn output(iface: &'static Interface, next_hop: Ipv4Addr, packet: Vec<u8>, csum: Option<(usize, usize)>) -> bool {
+    if next_hop == Ipv4Addr::BROADCAST || next_hop == iface.broadcast() {
+        return ethernet::output(iface, MacAddr::BROADCAST, ETH_P_IP, &packet, csum);
+    }
+    if let Some(mac) = lookup(next_hop) {
+        return ethernet::output(iface, mac, ETH_P_IP, &packet, csum);
+    }
+    let mut arp = ARP.lock();
+    let asked = arp.pending.iter().any(|pending| pending.next_hop == next_hop);
+    if arp.pending.len() >= MAX_PENDING {
+        return false;
+    }
+    arp.pending.push(Pending {
+        iface,
+        next_hop,
+        packet,
+        csum,
+        expires: time_ms() + PENDING_MS,
+    });
+    drop(arp);
+    if !asked {
+        request(iface, next_hop);
+    }
+    true
+}
+
+pub fn input(iface: &'static Interface, packet: &[u8]) {
+    if packet.len() < PACKET_LEN
+        || u16::from_be_bytes([packet[0], packet[1]]) != HTYPE_ETHERNET
+        || u16::from_be_bytes([packet[2], packet[3]]) != ETH_P_IP
+        || packet[4] != 6
+        || packet[5] != 4
+    {
+        return;
+    }
+    let op = u16::from_be_bytes([packet[6], packet[7]]);
+    let sender_mac = MacAddr(packet[8..14].try_into().unwrap());
+    let sender = Ipv4Addr(packet[14..18].try_into().unwrap());
+    let target = Ipv4Addr(packet[24..28].try_into().unwrap());
+    if sender.is_unspecified() {
+        return;
+    }
+    let mut arp = ARP.lock();
+    //only a host talking to us gets an entry, one we know is kept up to date
+    let known = arp.cache.iter().any(|entry| entry.addr == sender);
+    let for_us = iface.is_up() && target == iface.addr;
+    if known || for_us {
+        update(&mut arp, sender, sender_mac);
+    }
+    let (ready, pending) = core::mem::take(&mut arp.pending)
+        .into_iter()
+        .partition::<Vec<_>, _>(|pending| pending.next_hop == sender);
+    arp.pending = pending;
+    drop(arp);
+    if for_us && op == OP_REQUEST {
+        send(iface, OP_REPLY, sender_mac, sender);
+    }
+    for pending in ready {
+        ethernet::output(pending.iface, sender_mac, ETH_P_IP, &pending.packet, pending.csum);
+    }
+}
+
+//drop what waited too long, ask again for the rest
+pub fn tick(now: u64) {
+    let mut arp = ARP.lock();
+    arp.pending.retain(|pending| pending.expires > now);
+    arp.cache.retain(|entry| entry.expires > now);
+    let mut asked: Vec<(&'static Interface, Ipv4Addr)> = Vec::new();
+    for pending in arp.pending.iter() {
+        if !asked.iter().any(|(_, addr)| *addr == pending.next_hop) {
+            asked.push((pending.iface, pending.next_hop));
+        }
+    }
+    drop(arp);
+    for (iface, addr) in asked {
+        request(iface, addr);
+    }
+}
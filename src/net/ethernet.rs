@@ -0,0 +1,37 @@
+use alloc::vec::Vec;
+
+use super::{arp, ip, Interface, Link, MacAddr};
+
+pub const HEADER_LEN: usize = 14;
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_ARP: u16 = 0x0806;
+
+//frames for us or everyone, the rest of a promiscuous device is dropped
+pub fn input(iface: &'static Interface, frame: &[u8], checked: bool) {
+    if frame.len() < HEADER_LEN {
+        return;
+    }
+    let dst = MacAddr(frame[0..6].try_into().unwrap());
+    if dst != iface.mac && dst != MacAddr::BROADCAST {
+        return;
+    }
+    match u16::from_be_bytes([frame[12], frame[13]]) {
+        ETH_P_IP => ip::input(iface, &frame[HEADER_LEN..], checked),
+        ETH_P_ARP => arp::input(iface, &frame[HEADER_LEN..]),
+        _ => {}
+    }
+}
+
+//csum is relative to payload, false when the device dropped the frame
+pub fn output(iface: &Interface, dst: MacAddr, ethertype: u16, payload: &[u8], csum: Option<(usize, usize)>) -> bool {
+    let dev = match iface.link {
+        Link::Device(dev) => dev,
+        Link::Loopback(..) => return false,
+    };
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&dst.0);
+    frame.extend_from_slice(&iface.mac.0);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    dev.transmit(&frame, csum.map(|(start, offset)| (HEADER_LEN + start, offset)))
+}
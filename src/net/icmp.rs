@@ -0,0 +1,26 @@
+use alloc::vec::Vec;
+
+use super::{checksum, is_local};
+use super::ip::{self, Ipv4Header, PROTO_ICMP};
+
+//RFC 792, echo requests are answered
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+const HEADER_LEN: usize = 8;
+
+pub fn input(header: &Ipv4Header, message: &[u8]) {
+    if message.len() < HEADER_LEN || checksum(message) != 0 {
+        return;
+    }
+    //a broadcast ping goes unanswered
+    if message[0] != ECHO_REQUEST || !is_local(header.dst) {
+        return;
+    }
+    let mut reply = Vec::from(message);
+    reply[0] = ECHO_REPLY;
+    reply[2..4].copy_from_slice(&[0, 0]);
+    let sum = checksum(&reply);
+    reply[2..4].copy_from_slice(&sum.to_be_bytes());
+    let _ = ip::output(header.dst, header.src, PROTO_ICMP, &reply, None);
+}
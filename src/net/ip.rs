@@ -0,0 +1,119 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use super::{arp, checksum, checksum_add, icmp, route, tcp, udp, Interface, Ipv4Addr};
+use crate::common::errno::{Errno, SysResult};
+
+//RFC 791 without options or fragments, packets go out with don't fragment set
+
+pub const HEADER_LEN: usize = 20;
+pub const PROTO_ICMP: u8 = 1;
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+const DEFAULT_TTL: u8 = 64;
+const FLAG_DF: u16 = 0x4000;
+const FLAG_MF: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1fff;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+#[derive(Copy, Clone, Debug)]
+pub struct Ipv4Header {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+}
+
+impl Ipv4Header {
+    //the sum of the pseudo header of TCP and UDP, len is the transport header and data
+    pub fn pseudo_sum(&self, len: usize) -> u32 {
+        pseudo_sum(self.src, self.dst, self.protocol, len)
+    }
+}
+
+pub fn pseudo_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let sum = checksum_add(&src.0, 0);
+    let sum = checksum_add(&dst.0, sum);
+    sum + protocol as u32 + len as u32
+}
+
+//the header and the payload of a packet, None for what is not a whole IPv4 packet
+fn parse(packet: &[u8]) -> Option<(Ipv4Header, &[u8])> {
+    if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = (packet[0] & 0xf) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < HEADER_LEN || total_len < header_len || total_len > packet.len() {
+        return None;
+    }
+    if checksum(&packet[..header_len]) != 0 {
+        return None;
+    }
+    let flags = u16::from_be_bytes([packet[6], packet[7]]);
+    if flags & FLAG_MF != 0 || flags & FRAGMENT_OFFSET != 0 {
+        return None;
+    }
+    let header = Ipv4Header {
+        src: Ipv4Addr(packet[12..16].try_into().unwrap()),
+        dst: Ipv4Addr(packet[16..20].try_into().unwrap()),
+        protocol: packet[9],
+        ttl: packet[8],
+    };
+    Some((header, &packet[header_len..total_len]))
+}
+
+//checked is whether the device verified the transport checksum
+pub fn input(iface: &'static Interface, packet: &[u8], checked: bool) {
+    let (header, payload) = match parse(packet) {
+        None => return,
+        Some(parsed) => parsed,
+    };
+    let for_us = iface.is_loopback()
+        || (iface.is_up() && header.dst == iface.addr)
+        || header.dst == Ipv4Addr::BROADCAST
+        || (iface.is_up() && header.dst == iface.broadcast());
+    if !for_us {
+        return;
+    }
+    match header.protocol {
+        PROTO_ICMP => icmp::input(&header, payload),
+        PROTO_UDP => udp::input(&header, payload, checked),
+        PROTO_TCP => tcp::input(&header, payload, checked),
+        _ => {}
+    }
+}
+
+//payload is the transport header and data. csum_offset is where its checksum goes,
+//the pseudo header sum is there already and the device or loopback completes it
+pub fn output(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8], csum_offset: Option<usize>) -> SysResult<()> {
+    let (iface, next_hop) = route(dst).ok_or(Errno::ENETUNREACH)?;
+    let total_len = HEADER_LEN + payload.len();
+    if total_len > iface.mtu {
+        return Err(Errno::EMSGSIZE);
+    }
+    let mut packet = Vec::with_capacity(total_len);
+    packet.push(0x45);
+    packet.push(0);
+    packet.extend_from_slice(&(total_len as u16).to_be_bytes());
+    packet.extend_from_slice(&NEXT_ID.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    packet.extend_from_slice(&FLAG_DF.to_be_bytes());
+    packet.push(DEFAULT_TTL);
+    packet.push(protocol);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    let sent = match iface.is_loopback() {
+        //nobody checks the checksum on the way back in
+        true => iface.loopback(packet),
+        false => arp::output(iface, next_hop, packet, csum_offset.map(|offset| (HEADER_LEN, offset))),
+    };
+    match sent {
+        true => Ok(()),
+        false => Err(Errno::EAGAIN),
+    }
+}
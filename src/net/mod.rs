@@ -0,0 +1,330 @@
+#![allow(dead_code)]
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use crate::arch::time_ms;
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::{Mutex, MutexGuard};
+use crate::devices::driver::{net_devices, NetDevice};
+use crate::task::kthread::{kthread_spawn, sleep_ms};
+use crate::task::scheduler;
+use crate::task::signal;
+use crate::task::sync::Completion;
+use crate::task::wait::WaitQueue;
+use crate::{pr_err, pr_notice};
+
+pub mod arp;
+pub mod ethernet;
+pub mod icmp;
+pub mod ip;
+pub mod socket;
+pub mod tcp;
+pub mod udp;
+pub mod unix;
+
+//IPv4 over ethernet and a loopback interface. Every interface has a kernel thread that
+//takes its received packets through the stack, another one runs the TCP timers.
+//Sockets transmit from the task that sends, nothing on the way sleeps
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Self = Self([0, 0, 0, 0]);
+    pub const BROADCAST: Self = Self([255, 255, 255, 255]);
+    pub const LOCALHOST: Self = Self([127, 0, 0, 1]);
+
+    pub const fn from_u32(addr: u32) -> Self {
+        Self(addr.to_be_bytes())
+    }
+    pub const fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+    pub fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+    pub fn in_subnet(&self, addr: Ipv4Addr, netmask: Ipv4Addr) -> bool {
+        self.to_u32() & netmask.to_u32() == addr.to_u32() & netmask.to_u32()
+    }
+}
+
+impl Display for Ipv4Addr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: Self = Self([0xff; 6]);
+}
+
+impl Display for MacAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let m = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", m[0], m[1], m[2], m[3], m[4], m[5])
+    }
+}
+
+//an address and a port
+pub type Endpoint = (Ipv4Addr, u16);
+
+//the internet checksum, RFC 1071. sum adds 16 bit big endian words to an unfolded sum
+pub fn checksum_add(data: &[u8], mut sum: u32) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = words.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+pub fn checksum_fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+pub fn checksum(data: &[u8]) -> u16 {
+    !checksum_fold(checksum_add(data, 0))
+}
+
+//the checksum from start to the end of buf, stored at start + offset where the pseudo
+//header sum is. 0 goes out as 0xffff, a UDP checksum of 0 would mean none
+pub fn complete_checksum(buf: &mut [u8], start: usize, offset: usize) {
+    let sum = match !checksum_fold(checksum_add(&buf[start..], 0)) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    buf[start + offset..start + offset + 2].copy_from_slice(&sum.to_be_bytes());
+}
+
+pub enum Link {
+    Device(&'static dyn NetDevice),
+    //IPv4 packets sent to the host itself, delivered by the thread of the interface
+    Loopback(Mutex<VecDeque<Vec<u8>>>, Completion),
+}
+
+pub struct Interface {
+    pub name: String,
+    pub mac: MacAddr,
+    pub addr: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Option<Ipv4Addr>,
+    pub mtu: usize,
+    pub link: Link,
+}
+
+impl Interface {
+    pub fn is_loopback(&self) -> bool {
+        matches!(self.link, Link::Loopback(..))
+    }
+    pub fn is_up(&self) -> bool {
+        !self.addr.is_unspecified()
+    }
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.addr.to_u32() | !self.netmask.to_u32())
+    }
+    //a frame from the device, an IPv4 packet from loopback
+    fn receive(&self) -> Option<(Vec<u8>, bool)> {
+        match &self.link {
+            Link::Device(dev) => dev.receive(),
+            Link::Loopback(queue, _) => queue.lock().pop_front().map(|packet| (packet, true)),
+        }
+    }
+    fn wait(&self, deadline: u64) {
+        match &self.link {
+            Link::Device(dev) => match dev.rx_event() {
+                Some(event) => {
+                    event.wait_until(deadline);
+                }
+                None => sleep_ms(deadline.saturating_sub(time_ms())),
+            },
+            Link::Loopback(_, event) => {
+                event.wait_until(deadline);
+            }
+        }
+    }
+    pub fn loopback(&self, packet: Vec<u8>) -> bool {
+        match &self.link {
+            Link::Loopback(queue, event) => {
+                let mut queue = queue.lock();
+                if queue.len() >= LOOPBACK_QUEUE {
+                    return false;
+                }
+                queue.push_back(packet);
+                drop(queue);
+                event.complete();
+                true
+            }
+            Link::Device(_) => false,
+        }
+    }
+}
+
+//QEMU user networking: the guest is 10.0.2.15 in 10.0.2.0/24, the host is .2
+const QEMU_USER_ADDR: Ipv4Addr = Ipv4Addr([10, 0, 2, 15]);
+const QEMU_USER_NETMASK: Ipv4Addr = Ipv4Addr([255, 255, 255, 0]);
+const QEMU_USER_GATEWAY: Ipv4Addr = Ipv4Addr([10, 0, 2, 2]);
+const LOOPBACK_MTU: usize = 1500;
+const LOOPBACK_QUEUE: usize = 256;
+//how often a device without an rx interrupt is polled, and the TCP timer tick
+const POLL_MS: u64 = 100;
+
+static INTERFACES: Mutex<Vec<&'static Interface>> = Mutex::new(Vec::new());
+
+pub fn interfaces() -> Vec<&'static Interface> {
+    INTERFACES.lock().clone()
+}
+
+//whether addr is one of ours
+pub fn is_local(addr: Ipv4Addr) -> bool {
+    is_local_locked(&INTERFACES.lock(), addr)
+}
+
+//the interface packets to dst leave through and the next hop. Packets to the host
+//itself go through loopback
+pub fn route(dst: Ipv4Addr) -> Option<(&'static Interface, Ipv4Addr)> {
+    let interfaces = INTERFACES.lock();
+    let up = || interfaces.iter().copied().filter(|iface| iface.is_up());
+    if is_local_locked(&interfaces, dst) {
+        return up().find(|iface| iface.is_loopback()).map(|iface| (iface, dst));
+    }
+    let devices = || up().filter(|iface| !iface.is_loopback());
+    if dst == Ipv4Addr::BROADCAST {
+        return devices().next().map(|iface| (iface, dst));
+    }
+    devices()
+        .find(|iface| dst.in_subnet(iface.addr, iface.netmask))
+        .map(|iface| (iface, dst))
+        .or_else(|| devices().find_map(|iface| iface.gateway.map(|gateway| (iface, gateway))))
+}
+
+fn is_local_locked(interfaces: &MutexGuard<Vec<&'static Interface>>, addr: Ipv4Addr) -> bool {
+    addr.is_loopback() || interfaces.iter().any(|iface| iface.is_up() && iface.addr == addr)
+}
+
+//the address packets to dst are sent from
+pub fn source_for(dst: Ipv4Addr) -> SysResult<Ipv4Addr> {
+    match route(dst) {
+        None => Err(Errno::ENETUNREACH),
+        Some((iface, _)) if iface.is_loopback() && !dst.is_loopback() => Ok(dst),
+        Some((iface, _)) => Ok(iface.addr),
+    }
+}
+
+//the ephemeral range of RFC 6335, taken from where the last search stopped
+const EPHEMERAL_START: u16 = 49152;
+static NEXT_EPHEMERAL: AtomicU16 = AtomicU16::new(EPHEMERAL_START);
+
+pub fn ephemeral_port(in_use: impl Fn(u16) -> bool) -> SysResult<u16> {
+    let next = |port: u16| Some(if port == u16::MAX { EPHEMERAL_START } else { port + 1 });
+    for _ in EPHEMERAL_START..=u16::MAX {
+        let port = NEXT_EPHEMERAL.fetch_update(Ordering::Relaxed, Ordering::Relaxed, next).unwrap();
+        if !in_use(port) {
+            return Ok(port);
+        }
+    }
+    Err(Errno::EADDRINUSE)
+}
+
+//sleep on queue and release guard, until a wake up, deadline or a signal. The caller
+//rechecks its condition, Err when it has to give up instead
+pub fn sleep_on<T>(queue: &WaitQueue, guard: MutexGuard<'_, T>, deadline: Option<u64>) -> SysResult<()> {
+    if signal::interrupted() {
+        return Err(Errno::EINTR);
+    }
+    if deadline.is_some_and(|deadline| time_ms() >= deadline) {
+        return Err(Errno::EAGAIN);
+    }
+    queue.sleep_until(guard, deadline);
+    //woken by the deadline, the entry is still queued
+    if let Some(task) = scheduler::current() {
+        queue.cancel(unsafe { (*task).pid() });
+    }
+    Ok(())
+}
+
+fn rx_thread(iface: usize) -> isize {
+    let iface = unsafe { &*(iface as *const Interface) };
+    loop {
+        while let Some((packet, checked)) = iface.receive() {
+            match iface.link {
+                Link::Device(_) => ethernet::input(iface, &packet, checked),
+                Link::Loopback(..) => ip::input(iface, &packet, checked),
+            }
+        }
+        iface.wait(time_ms() + POLL_MS);
+    }
+}
+
+fn timer_thread(_: usize) -> isize {
+    loop {
+        sleep_ms(POLL_MS);
+        let now = time_ms();
+        arp::tick(now);
+        tcp::tick(now);
+    }
+}
+
+fn add_interface(iface: Interface) {
+    let iface: &'static Interface = Box::leak(Box::new(iface));
+    match iface.gateway {
+        Some(gateway) => pr_notice!("net: {} {} mask {} via {}\n", iface.name, iface.addr, iface.netmask, gateway),
+        None if iface.is_up() => pr_notice!("net: {} {} mask {}\n", iface.name, iface.addr, iface.netmask),
+        None => pr_notice!("net: {} without an address\n", iface.name),
+    }
+    INTERFACES.lock().push(iface);
+    if let Err(errno) = kthread_spawn(format!("net/{}", iface.name), rx_thread, iface as *const Interface as usize) {
+        pr_err!("net: no thread for {}: {:?}\n", iface.name, errno);
+    }
+}
+
+//loopback and the network devices, the first one gets the address of QEMU user
+//networking, the others stay down
+pub fn init() {
+    add_interface(Interface {
+        name: String::from("lo"),
+        mac: MacAddr::default(),
+        addr: Ipv4Addr::LOCALHOST,
+        netmask: Ipv4Addr([255, 0, 0, 0]),
+        gateway: None,
+        mtu: LOOPBACK_MTU,
+        link: Link::Loopback(Mutex::new(VecDeque::new()), Completion::new()),
+    });
+    for (n, (name, dev)) in net_devices().into_iter().enumerate() {
+        let first = n == 0;
+        add_interface(Interface {
+            name,
+            mac: MacAddr(dev.mac()),
+            addr: if first { QEMU_USER_ADDR } else { Ipv4Addr::UNSPECIFIED },
+            netmask: if first { QEMU_USER_NETMASK } else { Ipv4Addr::UNSPECIFIED },
+            gateway: first.then_some(QEMU_USER_GATEWAY),
+            mtu: dev.mtu(),
+            link: Link::Device(dev),
+        });
+    }
+    if let Err(errno) = kthread_spawn(String::from("net/timer"), timer_thread, 0) {
+        pr_err!("net: no timer thread: {:?}\n", errno);
+    }
+    //the gateway answers before the first packet has to wait for it
+    for iface in interfaces() {
+        if let Some(gateway) = iface.gateway {
+            arp::request(iface, gateway);
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::tcp::{State, TcpSocket};
+use super::udp::UdpSocket;
+use super::unix::UnixSocket;
+use super::{Endpoint, Ipv4Addr};
+use crate::arch::time_ms;
+use crate::common::errno::{Errno, SysResult};
+use crate::mm::{UserBuffer, UserPtr};
+use crate::task::file::File;
+use crate::task::scheduler;
+
+//the BSD socket calls. A socket is a File in the descriptor table, read and write are
+//recv and send without an address. Waits end with EAGAIN after SO_RCVTIMEO or
+//SO_SNDTIMEO, there are no non-blocking sockets
+
+pub const AF_UNIX: usize = 1;
+pub const AF_INET: usize = 2;
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+const SOCK_NONBLOCK: usize = 0o4000;
+const SOCK_CLOEXEC: usize = 0o2000000;
+const IPPROTO_TCP: usize = 6;
+const IPPROTO_UDP: usize = 17;
+const SHUT_RD: usize = 0;
+const SHUT_WR: usize = 1;
+const SHUT_RDWR: usize = 2;
+
+const SOL_SOCKET: usize = 1;
+const SO_REUSEADDR: usize = 2;
+const SO_TYPE: usize = 3;
+const SO_ERROR: usize = 4;
+const SO_RCVTIMEO: usize = 20;
+const SO_SNDTIMEO: usize = 21;
+const SO_ACCEPTCONN: usize = 30;
+const TCP_NODELAY: usize = 1;
+
+//struct sockaddr_in and struct sockaddr_un
+const SOCKADDR_IN_LEN: usize = 16;
+const UNIX_PATH_MAX: usize = 108;
+//struct timeval { tv_sec, tv_usec }
+const TIMEVAL_LEN: usize = 16;
+
+pub enum SockAddr {
+    Inet(Endpoint),
+    //the path without its NUL, an abstract name keeps its leading NUL
+    Unix(Vec<u8>),
+}
+
+impl SockAddr {
+    fn parse(bytes: &[u8]) -> SysResult<Self> {
+        if bytes.len() < 2 {
+            return Err(Errno::EINVAL);
+        }
+        match u16::from_ne_bytes([bytes[0], bytes[1]]) as usize {
+            AF_INET if bytes.len() < SOCKADDR_IN_LEN => Err(Errno::EINVAL),
+            AF_INET => {
+                let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+                Ok(SockAddr::Inet((Ipv4Addr(bytes[4..8].try_into().unwrap()), port)))
+            }
+            AF_UNIX if bytes.len() > 2 + UNIX_PATH_MAX => Err(Errno::EINVAL),
+            AF_UNIX => {
+                let path = &bytes[2..];
+                let len = match path.first() {
+                    Some(0) => path.len(),
+                    _ => path.iter().position(|byte| *byte == 0).unwrap_or(path.len()),
+                };
+                Ok(SockAddr::Unix(Vec::from(&path[..len])))
+            }
+            _ => Err(Errno::EAFNOSUPPORT),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            SockAddr::Inet((addr, port)) => {
+                let mut bytes = Vec::from((AF_INET as u16).to_ne_bytes());
+                bytes.extend_from_slice(&port.to_be_bytes());
+                bytes.extend_from_slice(&addr.0);
+                bytes.resize(SOCKADDR_IN_LEN, 0);
+                bytes
+            }
+            SockAddr::Unix(path) => {
+                let mut bytes = Vec::from((AF_UNIX as u16).to_ne_bytes());
+                bytes.extend_from_slice(path);
+                if path.first().is_some_and(|byte| *byte != 0) {
+                    bytes.push(0);
+                }
+                bytes
+            }
+        }
+    }
+}
+
+enum Kind {
+    Tcp(Arc<TcpSocket>),
+    Udp(Arc<UdpSocket>),
+    Unix(Arc<UnixSocket>),
+}
+
+pub struct Socket {
+    kind: Kind,
+    //SO_RCVTIMEO and SO_SNDTIMEO in ms, 0 waits forever
+    rcv_timeout: AtomicU64,
+    snd_timeout: AtomicU64,
+}
+
+fn deadline(timeout: &AtomicU64) -> Option<u64> {
+    match timeout.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(time_ms() + ms),
+    }
+}
+
+fn int_option(value: usize) -> Vec<u8> {
+    Vec::from((value as i32).to_ne_bytes())
+}
+
+fn int_of(value: &[u8]) -> SysResult<i32> {
+    match value.get(..4) {
+        Some(bytes) => Ok(i32::from_ne_bytes(bytes.try_into().unwrap())),
+        None => Err(Errno::EINVAL),
+    }
+}
+
+fn timeval_option(timeout: &AtomicU64) -> Vec<u8> {
+    let ms = timeout.load(Ordering::Relaxed);
+    let mut bytes = Vec::from(((ms / 1000) as i64).to_ne_bytes());
+    bytes.extend_from_slice(&((ms % 1000 * 1000) as i64).to_ne_bytes());
+    bytes
+}
+
+//a timeout below a millisecond is one, not forever
+fn timeval_ms(value: &[u8]) -> SysResult<u64> {
+    if value.len() < TIMEVAL_LEN {
+        return Err(Errno::EINVAL);
+    }
+    let sec = i64::from_ne_bytes(value[..8].try_into().unwrap());
+    let usec = i64::from_ne_bytes(value[8..16].try_into().unwrap());
+    if sec < 0 || !(0..1_000_000).contains(&usec) {
+        return Err(Errno::EINVAL);
+    }
+    Ok((sec as u64).saturating_mul(1000).saturating_add((usec as u64).div_ceil(1000)))
+}
+
+impl Socket {
+    fn from_kind(kind: Kind) -> Self {
+        Self { kind, rcv_timeout: AtomicU64::new(0), snd_timeout: AtomicU64::new(0) }
+    }
+
+    fn new(domain: usize, ty: usize, protocol: usize) -> SysResult<Self> {
+        let kind = match (domain, ty, protocol) {
+            (AF_INET, SOCK_STREAM, 0 | IPPROTO_TCP) => Kind::Tcp(TcpSocket::new()),
+            (AF_INET, SOCK_DGRAM, 0 | IPPROTO_UDP) => Kind::Udp(UdpSocket::new()),
+            (AF_UNIX, SOCK_STREAM, 0) => Kind::Unix(UnixSocket::new()),
+            (AF_INET | AF_UNIX, SOCK_STREAM, _) | (AF_INET, SOCK_DGRAM, _) => return Err(Errno::EPROTONOSUPPORT),
+            (AF_INET | AF_UNIX, _, _) => return Err(Errno::ESOCKTNOSUPPORT),
+            _ => return Err(Errno::EAFNOSUPPORT),
+        };
+        Ok(Self::from_kind(kind))
+    }
+
+    fn ty(&self) -> usize {
+        match self.kind {
+            Kind::Udp(_) => SOCK_DGRAM,
+            _ => SOCK_STREAM,
+        }
+    }
+
+    fn bind(&self, addr: SockAddr) -> SysResult<()> {
+        match (&self.kind, addr) {
+            (Kind::Tcp(tcp), SockAddr::Inet((addr, port))) => tcp.bind(addr, port),
+            (Kind::Udp(udp), SockAddr::Inet((addr, port))) => udp.bind(addr, port),
+            (Kind::Unix(unix), SockAddr::Unix(path)) => unix.bind(&path),
+            _ => Err(Errno::EAFNOSUPPORT),
+        }
+    }
+
+    fn listen(&self, backlog: usize) -> SysResult<()> {
+        match &self.kind {
+            Kind::Tcp(tcp) => tcp.listen(backlog),
+            Kind::Unix(unix) => unix.listen(backlog),
+            Kind::Udp(_) => Err(Errno::EOPNOTSUPP),
+        }
+    }
+
+    fn listening(&self) -> bool {
+        match &self.kind {
+            Kind::Tcp(tcp) => tcp.state() == State::Listen,
+            Kind::Unix(unix) => unix.is_listening(),
+            Kind::Udp(_) => false,
+        }
+    }
+
+    //the new connection and the address of its peer
+    fn accept(&self) -> SysResult<(Socket, SockAddr)> {
+        let deadline = deadline(&self.rcv_timeout);
+        match &self.kind {
+            Kind::Tcp(tcp) => {
+                let socket = tcp.accept(deadline)?;
+                let peer = socket.remote().unwrap_or((Ipv4Addr::UNSPECIFIED, 0));
+                Ok((Socket::from_kind(Kind::Tcp(socket)), SockAddr::Inet(peer)))
+            }
+            Kind::Unix(unix) => {
+                let (socket, peer) = unix.accept(deadline)?;
+                Ok((Socket::from_kind(Kind::Unix(socket)), SockAddr::Unix(peer)))
+            }
+            Kind::Udp(_) => Err(Errno::EOPNOTSUPP),
+        }
+    }
+
+    fn connect(&self, addr: SockAddr) -> SysResult<()> {
+        match (&self.kind, addr) {
+            (Kind::Tcp(tcp), SockAddr::Inet((addr, port))) => tcp.connect(addr, port, deadline(&self.snd_timeout)),
+            (Kind::Udp(udp), SockAddr::Inet((addr, port))) => udp.connect(addr, port),
+            (Kind::Unix(unix), SockAddr::Unix(path)) => unix.connect(&path),
+            _ => Err(Errno::EAFNOSUPPORT),
+        }
+    }
+
+    //the address is for datagrams, a stream goes where it is connected
+    fn send_to(&self, data: &[u8], addr: Option<SockAddr>) -> SysResult {
+        let deadline = deadline(&self.snd_timeout);
+        match &self.kind {
+            Kind::Tcp(tcp) => tcp.send(data, deadline),
+            Kind::Udp(udp) => match addr {
+                None => udp.send_to(data, None),
+                Some(SockAddr::Inet(dst)) => udp.send_to(data, Some(dst)),
+                Some(SockAddr::Unix(_)) => Err(Errno::EAFNOSUPPORT),
+            },
+            Kind::Unix(unix) => unix.send(data, deadline),
+        }
+    }
+
+    //the sender of a datagram, None for a stream
+    fn recv_from(&self, buf: &mut [u8]) -> SysResult<(usize, Option<SockAddr>)> {
+        let deadline = deadline(&self.rcv_timeout);
+        match &self.kind {
+            Kind::Tcp(tcp) => Ok((tcp.recv(buf, deadline)?, None)),
+            Kind::Udp(udp) => {
+                let (n, from) = udp.recv_from(buf, deadline)?;
+                Ok((n, (from.1 != 0).then_some(SockAddr::Inet(from))))
+            }
+            Kind::Unix(unix) => Ok((unix.recv(buf, deadline)?, None)),
+        }
+    }
+
+    fn shutdown(&self, how: usize) -> SysResult<()> {
+        let (read, write) = match how {
+            SHUT_RD => (true, false),
+            SHUT_WR => (false, true),
+            SHUT_RDWR => (true, true),
+            _ => return Err(Errno::EINVAL),
+        };
+        match &self.kind {
+            Kind::Tcp(tcp) => tcp.shutdown(read, write),
+            //nothing to send a FIN for, receiving stops
+            Kind::Udp(udp) => {
+                if udp.remote().is_none() {
+                    return Err(Errno::ENOTCONN);
+                }
+                if read {
+                    udp.shutdown_read();
+                }
+                Ok(())
+            }
+            Kind::Unix(unix) => unix.shutdown(read, write),
+        }
+    }
+
+    fn get_option(&self, level: usize, name: usize) -> SysResult<Vec<u8>> {
+        match (level, name, &self.kind) {
+            (SOL_SOCKET, SO_TYPE, _) => Ok(int_option(self.ty())),
+            (SOL_SOCKET, SO_ERROR, Kind::Tcp(tcp)) => Ok(int_option(tcp.take_error().map_or(0, |errno| errno as usize))),
+            (SOL_SOCKET, SO_ERROR, _) => Ok(int_option(0)),
+            (SOL_SOCKET, SO_REUSEADDR, Kind::Tcp(tcp)) => Ok(int_option(tcp.reuse_addr() as usize)),
+            (SOL_SOCKET, SO_REUSEADDR, _) => Ok(int_option(0)),
+            (SOL_SOCKET, SO_ACCEPTCONN, _) => Ok(int_option(self.listening() as usize)),
+            (SOL_SOCKET, SO_RCVTIMEO, _) => Ok(timeval_option(&self.rcv_timeout)),
+            (SOL_SOCKET, SO_SNDTIMEO, _) => Ok(timeval_option(&self.snd_timeout)),
+            //segments go out as soon as they are sent, there is no Nagle
+            (IPPROTO_TCP, TCP_NODELAY, Kind::Tcp(_)) => Ok(int_option(1)),
+            _ => Err(Errno::ENOPROTOOPT),
+        }
+    }
+
+    fn set_option(&self, level: usize, name: usize, value: &[u8]) -> SysResult<()> {
+        match (level, name, &self.kind) {
+            (SOL_SOCKET, SO_REUSEADDR, Kind::Tcp(tcp)) => tcp.set_reuse_addr(int_of(value)? != 0),
+            //UDP ports and unix names are never shared, it is accepted and changes nothing
+            (SOL_SOCKET, SO_REUSEADDR, _) => {
+                int_of(value)?;
+            }
+            (SOL_SOCKET, SO_RCVTIMEO, _) => self.rcv_timeout.store(timeval_ms(value)?, Ordering::Relaxed),
+            (SOL_SOCKET, SO_SNDTIMEO, _) => self.snd_timeout.store(timeval_ms(value)?, Ordering::Relaxed),
+            (IPPROTO_TCP, TCP_NODELAY, Kind::Tcp(_)) => {
+                int_of(value)?;
+            }
+            _ => return Err(Errno::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+}
+
+impl File for Socket {
+    fn read(&self, buf: &mut [u8]) -> SysResult {
+        self.recv_from(buf).map(|(n, _)| n)
+    }
+    fn write(&self, buf: &[u8]) -> SysResult {
+        self.send_to(buf, None)
+    }
+    fn socket(&self) -> Option<&Socket> {
+        Some(self)
+    }
+}
+
+//the last descriptor is gone, a unix socket closes as its last reference goes
+impl Drop for Socket {
+    fn drop(&mut self) {
+        match &self.kind {
+            Kind::Tcp(tcp) => tcp.close(),
+            Kind::Udp(udp) => udp.close(),
+            Kind::Unix(_) => {}
+        }
+    }
+}
+
+fn file(fd: usize) -> SysResult<Arc<dyn File>> {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    unsafe { (*(*task).files.lock()).get(fd) }
+}
+
+fn install(socket: Socket) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    unsafe { (*task).files.lock().alloc(Arc::new(socket)) }
+}
+
+//f on the socket of fd, ENOTSOCK for any other file
+fn with_socket<R>(fd: usize, f: impl FnOnce(&Socket) -> SysResult<R>) -> SysResult<R> {
+    let file = file(fd)?;
+    f(file.socket().ok_or(Errno::ENOTSOCK)?)
+}
+
+//every user pointer of these calls goes through here, a range that is not mapped
+//with the access is EFAULT instead of a data abort in the copy
+fn checked<T>(addr: usize, len: usize, write: bool) -> SysResult<UserPtr<T>> {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    if !unsafe { (*task).page.lock().access_ok(addr, len * size_of::<T>(), write) } {
+        return Err(Errno::EFAULT);
+    }
+    Ok(UserPtr::new(addr, len))
+}
+
+fn read_addr(addr: usize, len: usize) -> SysResult<SockAddr> {
+    if !(2..=2 + UNIX_PATH_MAX).contains(&len) {
+        return Err(Errno::EINVAL);
+    }
+    let bytes = Vec::<u8>::copy_from_user(checked(addr, len, false)?).ok_or(Errno::ENOMEM)?;
+    SockAddr::parse(&bytes)
+}
+
+//the socklen_t at len, read and the room behind addr checked before anything is
+//taken off a socket so that a bad pointer fails the call before it consumed a
+//connection or a datagram. addr 0 skips it
+fn addr_room(addr: usize, len: usize) -> SysResult<usize> {
+    if addr == 0 {
+        return Ok(0);
+    }
+    let room = Vec::<u32>::copy_from_user(checked(len, 1, true)?).ok_or(Errno::ENOMEM)?[0] as usize;
+    if room > 0 {
+        checked::<u8>(addr, room, true)?;
+    }
+    Ok(room)
+}
+
+//cut to room, the socklen_t at len is set to the whole length. Checked again, a
+//blocking call may have slept while the pages were swapped out
+fn write_addr(sockaddr: &SockAddr, addr: usize, len: usize, room: usize) -> SysResult<()> {
+    if addr == 0 {
+        return Ok(());
+    }
+    let bytes = sockaddr.to_bytes();
+    let n = room.min(bytes.len());
+    if n > 0 {
+        bytes[..n].to_vec().copy_to_user(&mut checked(addr, n, true)?);
+    }
+    vec![bytes.len() as u32].copy_to_user(&mut checked(len, 1, true)?);
+    Ok(())
+}
+
+pub fn socket(domain: usize, ty: usize, protocol: usize) -> SysResult {
+    if ty & SOCK_NONBLOCK != 0 {
+        return Err(Errno::EINVAL);
+    }
+    //there is no exec, SOCK_CLOEXEC changes nothing
+    install(Socket::new(domain, ty & !SOCK_CLOEXEC, protocol)?)
+}
+
+pub fn bind(fd: usize, addr: usize, len: usize) -> SysResult {
+    let addr = read_addr(addr, len)?;
+    with_socket(fd, |socket| socket.bind(addr))?;
+    Ok(0)
+}
+
+pub fn listen(fd: usize, backlog: usize) -> SysResult {
+    with_socket(fd, |socket| socket.listen(backlog))?;
+    Ok(0)
+}
+
+//addr and len receive the address of the peer
+pub fn accept(fd: usize, addr: usize, len: usize) -> SysResult {
+    let room = addr_room(addr, len)?;
+    let (socket, peer) = with_socket(fd, |socket| socket.accept())?;
+    write_addr(&peer, addr, len, room)?;
+    install(socket)
+}
+
+pub fn connect(fd: usize, addr: usize, len: usize) -> SysResult {
+    let addr = read_addr(addr, len)?;
+    with_socket(fd, |socket| socket.connect(addr))?;
+    Ok(0)
+}
+
+//no flags, MSG_* are not supported
+pub fn sendto(fd: usize, buf: usize, len: usize, flags: usize, addr: usize, addr_len: usize) -> SysResult {
+    if flags != 0 {
+        return Err(Errno::EOPNOTSUPP);
+    }
+    let dst = match addr {
+        0 => None,
+        addr => Some(read_addr(addr, addr_len)?),
+    };
+    let data = match len {
+        0 => Vec::new(),
+        len => Vec::<u8>::copy_from_user(checked(buf, len, false)?).ok_or(Errno::ENOMEM)?,
+    };
+    with_socket(fd, |socket| socket.send_to(&data, dst))
+}
+
+//addr and len receive the sender of a datagram, len is 0 for a stream
+pub fn recvfrom(fd: usize, buf: usize, len: usize, flags: usize, addr: usize, addr_len: usize) -> SysResult {
+    if flags != 0 {
+        return Err(Errno::EOPNOTSUPP);
+    }
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(len).map_err(|_| Errno::ENOMEM)?;
+    buffer.resize(len, 0);
+    if len > 0 {
+        checked::<u8>(buf, len, true)?;
+    }
+    let room = addr_room(addr, addr_len)?;
+    let (n, from) = with_socket(fd, |socket| socket.recv_from(&mut buffer))?;
+    if n > 0 {
+        buffer[..n].to_vec().copy_to_user(&mut checked(buf, n, true)?);
+    }
+    match from {
+        Some(from) => write_addr(&from, addr, addr_len, room)?,
+        None if addr != 0 => vec![0u32].copy_to_user(&mut checked(addr_len, 1, true)?),
+        None => {}
+    }
+    Ok(n)
+}
+
+pub fn shutdown(fd: usize, how: usize) -> SysResult {
+    with_socket(fd, |socket| socket.shutdown(how))?;
+    Ok(0)
+}
+
+//len is a socklen_t, the value is cut to it and it is set to what was written
+pub fn getsockopt(fd: usize, level: usize, name: usize, value: usize, len: usize) -> SysResult {
+    let option = with_socket(fd, |socket| socket.get_option(level, name))?;
+    let room = Vec::<u32>::copy_from_user(checked(len, 1, true)?).ok_or(Errno::ENOMEM)?[0] as usize;
+    let n = room.min(option.len());
+    if n > 0 {
+        option[..n].to_vec().copy_to_user(&mut checked(value, n, true)?);
+    }
+    vec![n as u32].copy_to_user(&mut checked(len, 1, true)?);
+    Ok(0)
+}
+
+pub fn setsockopt(fd: usize, level: usize, name: usize, value: usize, len: usize) -> SysResult {
+    if value == 0 || len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let value = Vec::<u8>::copy_from_user(checked(value, len.min(TIMEVAL_LEN), false)?).ok_or(Errno::ENOMEM)?;
+    with_socket(fd, |socket| socket.set_option(level, name, &value))?;
+    Ok(0)
+}
@@ -0,0 +1,1016 @@
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::ip::{self, pseudo_sum, Ipv4Header, PROTO_TCP};
+use super::{checksum_add, checksum_fold, ephemeral_port, is_local, route, sleep_on, source_for, Endpoint, Ipv4Addr};
+use crate::arch::time_ms;
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::task::signal;
+use crate::task::wait::WaitQueue;
+
+//RFC 793 with RFC 1122's fixes, go-back-N retransmission with a doubling timeout and
+//no congestion control. Segments out of order are dropped and acked, the peer sends
+//them again. A socket is in SOCKETS from bind until it is closed, a listener keeps
+//the connections that completed the handshake until they are accepted
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl State {
+    //a connection that completed the handshake
+    fn synchronized(&self) -> bool {
+        !matches!(self, State::Closed | State::Listen | State::SynSent | State::SynReceived)
+    }
+}
+
+const FIN: u8 = 1;
+const SYN: u8 = 2;
+const RST: u8 = 4;
+const PSH: u8 = 8;
+const ACK: u8 = 16;
+
+const HEADER_LEN: usize = 20;
+const OPTION_MSS: u8 = 2;
+//what the peer may send without the option
+const DEFAULT_MSS: usize = 536;
+//without window scaling the window is 16 bits
+pub const BUFFER_SIZE: usize = 65535;
+const RTO_INITIAL: u64 = 1000;
+const RTO_MAX: u64 = 60_000;
+const MAX_RETRIES: usize = 8;
+//2 MSL with a short MSL, ports of closed connections come back soon
+const TIME_WAIT_MS: u64 = 4000;
+//an orphan stuck in FIN-WAIT-2 is dropped after this
+const FIN_WAIT_2_MS: u64 = 60_000;
+pub const MAX_BACKLOG: usize = 128;
+
+//sequence numbers wrap, a comes before b when b - a is less than half the space
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+static ISS: AtomicU32 = AtomicU32::new(0);
+
+//RFC 793's clock ticking every 4us, moved on for every connection
+fn new_iss() -> u32 {
+    (time_ms() as u32).wrapping_mul(250).wrapping_add(ISS.fetch_add(64000, Ordering::Relaxed))
+}
+
+struct Segment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<usize>,
+    data: &'a [u8],
+}
+
+impl Segment<'_> {
+    //sequence space taken, SYN and FIN count as one
+    fn len(&self) -> u32 {
+        self.data.len() as u32 + (self.flags & SYN != 0) as u32 + (self.flags & FIN != 0) as u32
+    }
+}
+
+fn parse<'a>(header: &Ipv4Header, segment: &'a [u8], checked: bool) -> Option<Segment<'a>> {
+    if segment.len() < HEADER_LEN {
+        return None;
+    }
+    let data_offset = (segment[12] >> 4) as usize * 4;
+    if data_offset < HEADER_LEN || data_offset > segment.len() {
+        return None;
+    }
+    if !checked && checksum_fold(checksum_add(segment, header.pseudo_sum(segment.len()))) != 0xffff {
+        return None;
+    }
+    let mut mss = None;
+    let mut options = &segment[HEADER_LEN..data_offset];
+    while let [kind, rest @ ..] = options {
+        options = match (*kind, rest) {
+            (0, _) => break,
+            (1, rest) => rest,
+            (OPTION_MSS, [4, high, low, rest @ ..]) => {
+                mss = Some(u16::from_be_bytes([*high, *low]) as usize);
+                rest
+            }
+            (_, [len, ..]) if *len >= 2 && (*len as usize) <= options.len() => &options[*len as usize..],
+            _ => break,
+        };
+    }
+    let word = |at: usize| u32::from_be_bytes(segment[at..at + 4].try_into().unwrap());
+    Some(Segment {
+        src_port: u16::from_be_bytes([segment[0], segment[1]]),
+        dst_port: u16::from_be_bytes([segment[2], segment[3]]),
+        seq: word(4),
+        ack: word(8),
+        flags: segment[13],
+        window: u16::from_be_bytes([segment[14], segment[15]]),
+        mss,
+        data: &segment[data_offset..],
+    })
+}
+
+//a segment outside of any connection
+#[allow(clippy::too_many_arguments)]
+fn transmit(local: Endpoint, remote: Endpoint, seq: u32, ack: u32, flags: u8, window: u16, mss: Option<u16>, data: &[u8]) {
+    let options = if mss.is_some() { 4 } else { 0 };
+    let len = HEADER_LEN + options + data.len();
+    let mut segment = Vec::with_capacity(len);
+    segment.extend_from_slice(&local.1.to_be_bytes());
+    segment.extend_from_slice(&remote.1.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push((((HEADER_LEN + options) / 4) as u8) << 4);
+    segment.push(flags);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&checksum_fold(pseudo_sum(local.0, remote.0, PROTO_TCP, len)).to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    if let Some(mss) = mss {
+        segment.extend_from_slice(&[OPTION_MSS, 4]);
+        segment.extend_from_slice(&mss.to_be_bytes());
+    }
+    segment.extend_from_slice(data);
+    //a lost segment is sent again or the peer asks for it
+    let _ = ip::output(local.0, remote.0, PROTO_TCP, &segment, Some(16));
+}
+
+//the answer to a segment nobody wants
+fn reset(header: &Ipv4Header, segment: &Segment) {
+    if segment.flags & RST != 0 {
+        return;
+    }
+    let local = (header.dst, segment.dst_port);
+    let remote = (header.src, segment.src_port);
+    match segment.flags & ACK {
+        0 => transmit(local, remote, 0, segment.seq.wrapping_add(segment.len()), RST | ACK, 0, None, &[]),
+        _ => transmit(local, remote, segment.ack, 0, RST, 0, None, &[]),
+    }
+}
+
+struct Tcb {
+    state: State,
+    local: Endpoint,
+    remote: Endpoint,
+    bound: bool,
+    //send sequence space: send holds the bytes from snd_una on
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    //the highest snd_nxt so far, acks up to it are valid after going back
+    snd_max: u32,
+    snd_wnd: usize,
+    snd_mss: usize,
+    send: VecDeque<u8>,
+    //the FIN goes after send, fin_seq once it was sent
+    fin_queued: bool,
+    fin_seq: Option<u32>,
+    //receive sequence space
+    rcv_nxt: u32,
+    recv: VecDeque<u8>,
+    rcv_fin: bool,
+    //the window the last segment offered
+    rcv_adv: usize,
+    shut_rd: bool,
+    ack_pending: bool,
+    rto: u64,
+    retransmit_at: Option<u64>,
+    retries: usize,
+    //end of TIME-WAIT, or of an orphan in FIN-WAIT-2
+    linger_until: Option<u64>,
+    //no descriptor refers to it anymore
+    orphan: bool,
+    error: Option<Errno>,
+    backlog: VecDeque<Arc<TcpSocket>>,
+    max_backlog: usize,
+    listener: Weak<TcpSocket>,
+    reuse_addr: bool,
+}
+
+pub struct TcpSocket {
+    tcb: Mutex<Tcb>,
+    //data, a FIN, an error or a connection to accept
+    readable: WaitQueue,
+    //room in the send buffer, or the end of connect
+    writable: WaitQueue,
+}
+
+static SOCKETS: Mutex<Vec<Arc<TcpSocket>>> = Mutex::new(Vec::new());
+
+impl Tcb {
+    fn new() -> Self {
+        Self {
+            state: State::Closed,
+            local: (Ipv4Addr::UNSPECIFIED, 0),
+            remote: (Ipv4Addr::UNSPECIFIED, 0),
+            bound: false,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_max: 0,
+            snd_wnd: 0,
+            snd_mss: DEFAULT_MSS,
+            send: VecDeque::new(),
+            fin_queued: false,
+            fin_seq: None,
+            rcv_nxt: 0,
+            recv: VecDeque::new(),
+            rcv_fin: false,
+            rcv_adv: 0,
+            shut_rd: false,
+            ack_pending: false,
+            rto: RTO_INITIAL,
+            retransmit_at: None,
+            retries: 0,
+            linger_until: None,
+            orphan: false,
+            error: None,
+            backlog: VecDeque::new(),
+            max_backlog: 0,
+            listener: Weak::new(),
+            reuse_addr: false,
+        }
+    }
+
+    fn rcv_wnd(&self) -> usize {
+        BUFFER_SIZE - self.recv.len()
+    }
+
+    //the MSS we offer, what fits into the interface towards the peer
+    fn our_mss(&self) -> u16 {
+        let mtu = route(self.remote.0).map_or(DEFAULT_MSS + 40, |(iface, _)| iface.mtu);
+        (mtu - 40) as u16
+    }
+
+    fn start(&mut self) {
+        self.iss = new_iss();
+        self.snd_una = self.iss;
+        self.snd_nxt = self.iss;
+        self.snd_max = self.iss;
+    }
+
+    fn segment(&mut self, seq: u32, flags: u8, data: &[u8]) {
+        let window = self.rcv_wnd();
+        let mss = match flags & SYN {
+            0 => None,
+            _ => Some(self.our_mss()),
+        };
+        let (ack, flags) = match self.state {
+            State::SynSent if flags & ACK == 0 => (0, flags),
+            _ => (self.rcv_nxt, flags | ACK),
+        };
+        self.rcv_adv = window;
+        self.ack_pending = false;
+        transmit(self.local, self.remote, seq, ack, flags, window as u16, mss, data);
+    }
+
+    fn arm_timer(&mut self) {
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(time_ms() + self.rto);
+        }
+    }
+
+    //what the window allows of the data not sent yet, the SYN or FIN if they are due.
+    //probe sends a byte into a closed window
+    fn output(&mut self, probe: bool) {
+        match self.state {
+            State::SynSent | State::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = match self.state {
+                        State::SynSent => SYN,
+                        _ => SYN | ACK,
+                    };
+                    self.segment(self.iss, flags, &[]);
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                    if seq_lt(self.snd_max, self.snd_nxt) {
+                        self.snd_max = self.snd_nxt;
+                    }
+                    self.arm_timer();
+                }
+                return;
+            }
+            State::Established | State::CloseWait | State::FinWait1 | State::Closing | State::LastAck => {}
+            _ => return,
+        }
+        let window = match probe {
+            true => self.snd_wnd.max(1),
+            false => self.snd_wnd,
+        };
+        loop {
+            let sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            if sent >= self.send.len() || sent >= window {
+                break;
+            }
+            let n = self.snd_mss.min(self.send.len() - sent).min(window - sent);
+            let data: Vec<u8> = self.send.range(sent..sent + n).copied().collect();
+            let flags = match sent + n == self.send.len() {
+                true => PSH,
+                false => 0,
+            };
+            self.segment(self.snd_nxt, flags, &data);
+            self.snd_nxt = self.snd_nxt.wrapping_add(n as u32);
+            self.arm_timer();
+        }
+        let end = self.snd_una.wrapping_add(self.send.len() as u32);
+        //sent again after going back, the FIN stays at the end of the data
+        if self.fin_queued && self.snd_nxt == end {
+            self.segment(end, FIN, &[]);
+            self.fin_seq = Some(end);
+            self.snd_nxt = end.wrapping_add(1);
+            self.arm_timer();
+        }
+        if seq_lt(self.snd_max, self.snd_nxt) {
+            self.snd_max = self.snd_nxt;
+        }
+        //a closed window is probed by the timer
+        if self.snd_nxt != end && self.snd_wnd == 0 {
+            self.arm_timer();
+        }
+    }
+
+    fn fin_acked(&self) -> bool {
+        self.fin_seq.is_some_and(|fin| seq_lt(fin, self.snd_una))
+    }
+
+    //the bytes and FIN up to ack left the send buffer
+    fn acked(&mut self, ack: u32) {
+        let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+        if self.fin_seq.is_some_and(|fin| seq_lt(fin, ack)) {
+            acked -= 1;
+        }
+        self.send.drain(..acked.min(self.send.len()));
+        self.snd_una = ack;
+        if seq_lt(self.snd_nxt, ack) {
+            self.snd_nxt = ack;
+        }
+        self.retries = 0;
+        self.rto = RTO_INITIAL;
+        self.retransmit_at = None;
+        if self.snd_una != self.snd_nxt {
+            self.arm_timer();
+        }
+    }
+
+    fn enter_time_wait(&mut self) {
+        self.state = State::TimeWait;
+        self.retransmit_at = None;
+        self.linger_until = Some(time_ms() + TIME_WAIT_MS);
+    }
+
+    //a RST for a connection that is synchronized
+    fn abort(&mut self) {
+        if self.state.synchronized() || self.state == State::SynReceived {
+            transmit(self.local, self.remote, self.snd_nxt, 0, RST, 0, None, &[]);
+        }
+        self.state = State::Closed;
+    }
+}
+
+//what has to happen once the socket is unlocked
+enum Action {
+    None,
+    //the handshake completed, hand the connection to its listener
+    Accept,
+    //closed, out of the table
+    Remove,
+}
+
+impl TcpSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            tcb: Mutex::new(Tcb::new()),
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        })
+    }
+
+    fn wake(&self) {
+        self.readable.wake_all();
+        self.writable.wake_all();
+    }
+
+    fn remove(self: &Arc<Self>) {
+        SOCKETS.lock().retain(|socket| !Arc::ptr_eq(socket, self));
+    }
+
+    pub fn state(&self) -> State {
+        self.tcb.lock().state
+    }
+
+    pub fn local(&self) -> Option<Endpoint> {
+        let tcb = self.tcb.lock();
+        tcb.bound.then_some(tcb.local)
+    }
+
+    pub fn remote(&self) -> Option<Endpoint> {
+        let tcb = self.tcb.lock();
+        match tcb.state {
+            State::Closed | State::Listen => None,
+            _ => Some(tcb.remote),
+        }
+    }
+
+    //SO_ERROR, cleared by reading it
+    pub fn take_error(&self) -> Option<Errno> {
+        self.tcb.lock().error.take()
+    }
+
+    pub fn set_reuse_addr(&self, reuse: bool) {
+        self.tcb.lock().reuse_addr = reuse;
+    }
+
+    pub fn reuse_addr(&self) -> bool {
+        self.tcb.lock().reuse_addr
+    }
+
+    //data waiting to be received, or the end of it
+    pub fn readable(&self) -> bool {
+        let tcb = self.tcb.lock();
+        !tcb.recv.is_empty() || !tcb.backlog.is_empty() || tcb.rcv_fin || tcb.error.is_some()
+    }
+
+    //port 0 takes an ephemeral one. With reuse_addr a port whose connections are
+    //closing can be bound again
+    pub fn bind(self: &Arc<Self>, addr: Ipv4Addr, port: u16) -> SysResult<()> {
+        if !addr.is_unspecified() && !is_local(addr) {
+            return Err(Errno::EADDRNOTAVAIL);
+        }
+        //binds are serialized by SOCKETS, a socket is only locked on its own
+        let mut sockets = SOCKETS.lock();
+        let (bound, reuse) = {
+            let tcb = self.tcb.lock();
+            (tcb.bound, tcb.reuse_addr)
+        };
+        if bound {
+            return Err(Errno::EINVAL);
+        }
+        let in_use = |port: u16| {
+            sockets.iter().any(|socket| {
+                let other = socket.tcb.lock();
+                other.bound
+                    && other.local.1 == port
+                    && (other.local.0.is_unspecified() || addr.is_unspecified() || other.local.0 == addr)
+                    && !(reuse && other.reuse_addr && other.state != State::Listen)
+            })
+        };
+        let port = match port {
+            0 => ephemeral_port(in_use)?,
+            port if in_use(port) => return Err(Errno::EADDRINUSE),
+            port => port,
+        };
+        let mut tcb = self.tcb.lock();
+        tcb.local = (addr, port);
+        tcb.bound = true;
+        drop(tcb);
+        sockets.push(self.clone());
+        Ok(())
+    }
+
+    pub fn listen(self: &Arc<Self>, backlog: usize) -> SysResult<()> {
+        if self.local().is_none() {
+            self.bind(Ipv4Addr::UNSPECIFIED, 0)?;
+        }
+        let mut tcb = self.tcb.lock();
+        match tcb.state {
+            State::Closed | State::Listen => {
+                tcb.state = State::Listen;
+                tcb.max_backlog = backlog.clamp(1, MAX_BACKLOG);
+                Ok(())
+            }
+            _ => Err(Errno::EISCONN),
+        }
+    }
+
+    //the next connection that completed the handshake
+    pub fn accept(&self, deadline: Option<u64>) -> SysResult<Arc<TcpSocket>> {
+        loop {
+            let mut tcb = self.tcb.lock();
+            if tcb.state != State::Listen {
+                return Err(Errno::EINVAL);
+            }
+            if let Some(socket) = tcb.backlog.pop_front() {
+                return Ok(socket);
+            }
+            sleep_on(&self.readable, tcb, deadline)?;
+        }
+    }
+
+    //blocks until the handshake is done, refused or timed out
+    pub fn connect(self: &Arc<Self>, addr: Ipv4Addr, port: u16, deadline: Option<u64>) -> SysResult<()> {
+        if addr.is_unspecified() || port == 0 {
+            return Err(Errno::EINVAL);
+        }
+        match self.state() {
+            State::Closed => {}
+            State::Listen => return Err(Errno::EINVAL),
+            State::SynSent => return Err(Errno::EALREADY),
+            _ => return Err(Errno::EISCONN),
+        }
+        let src = source_for(addr)?;
+        if self.local().is_none() {
+            self.bind(Ipv4Addr::UNSPECIFIED, 0)?;
+        }
+        let mut tcb = self.tcb.lock();
+        if tcb.state != State::Closed {
+            return Err(Errno::EALREADY);
+        }
+        if tcb.local.0.is_unspecified() {
+            tcb.local.0 = src;
+        }
+        tcb.remote = (addr, port);
+        tcb.start();
+        tcb.state = State::SynSent;
+        tcb.error = None;
+        tcb.output(false);
+        drop(tcb);
+        self.wait_connected(deadline)
+    }
+
+    fn wait_connected(&self, deadline: Option<u64>) -> SysResult<()> {
+        loop {
+            let mut tcb = self.tcb.lock();
+            match tcb.state {
+                State::SynSent | State::SynReceived => {}
+                State::Closed => return Err(tcb.error.take().unwrap_or(Errno::ECONNREFUSED)),
+                _ => return Ok(()),
+            }
+            sleep_on(&self.writable, tcb, deadline)?;
+        }
+    }
+
+    //blocks until all of data is in the send buffer, EPIPE and SIGPIPE once the
+    //sending side is shut down. A signal or the deadline end it with what was queued
+    pub fn send(&self, data: &[u8], deadline: Option<u64>) -> SysResult {
+        let mut written = 0;
+        loop {
+            let mut tcb = self.tcb.lock();
+            match tcb.state {
+                State::Established | State::CloseWait if !tcb.fin_queued => {}
+                State::Closed if tcb.error.is_some() => return Err(tcb.error.take().unwrap()),
+                State::Closed | State::Listen | State::SynSent | State::SynReceived => return Err(Errno::ENOTCONN),
+                _ => {
+                    drop(tcb);
+                    signal::raise(signal::SIGPIPE);
+                    return Err(Errno::EPIPE);
+                }
+            }
+            let n = (BUFFER_SIZE - tcb.send.len()).min(data.len() - written);
+            tcb.send.extend(&data[written..written + n]);
+            written += n;
+            tcb.output(false);
+            if written == data.len() {
+                return Ok(written);
+            }
+            if let Err(errno) = sleep_on(&self.writable, tcb, deadline) {
+                return match written {
+                    0 => Err(errno),
+                    _ => Ok(written),
+                };
+            }
+        }
+    }
+
+    //blocks until there is data, 0 at the end of the stream
+    pub fn recv(&self, buf: &mut [u8], deadline: Option<u64>) -> SysResult {
+        loop {
+            let mut tcb = self.tcb.lock();
+            if !tcb.recv.is_empty() {
+                let n = buf.len().min(tcb.recv.len());
+                for (byte, data) in buf.iter_mut().zip(tcb.recv.drain(..n)) {
+                    *byte = data;
+                }
+                //a window that was too small to send into opens again
+                if tcb.rcv_adv < tcb.snd_mss && tcb.rcv_wnd() >= tcb.snd_mss && tcb.state.synchronized() {
+                    let seq = tcb.snd_nxt;
+                    tcb.segment(seq, 0, &[]);
+                }
+                return Ok(n);
+            }
+            if let Some(errno) = tcb.error.take() {
+                return Err(errno);
+            }
+            match tcb.state {
+                _ if tcb.rcv_fin || tcb.shut_rd || buf.is_empty() => return Ok(0),
+                State::Closed | State::Listen => return Err(Errno::ENOTCONN),
+                _ => {}
+            }
+            sleep_on(&self.readable, tcb, deadline)?;
+        }
+    }
+
+    //stop receiving, sending or both. A FIN follows what is in the send buffer
+    pub fn shutdown(&self, read: bool, write: bool) -> SysResult<()> {
+        let mut tcb = self.tcb.lock();
+        if !tcb.state.synchronized() && tcb.state != State::SynReceived {
+            return Err(Errno::ENOTCONN);
+        }
+        if read {
+            tcb.shut_rd = true;
+        }
+        if write && !tcb.fin_queued {
+            tcb.fin_queued = true;
+            tcb.state = match tcb.state {
+                State::CloseWait => State::LastAck,
+                State::Established | State::SynReceived => State::FinWait1,
+                state => state,
+            };
+            tcb.output(false);
+        }
+        drop(tcb);
+        self.wake();
+        Ok(())
+    }
+
+    //the last descriptor is gone. A connection is closed gracefully unless data was
+    //left unread, that is a RST
+    pub fn close(self: &Arc<Self>) {
+        let mut tcb = self.tcb.lock();
+        tcb.orphan = true;
+        let backlog = core::mem::take(&mut tcb.backlog);
+        match tcb.state {
+            State::Closed | State::Listen | State::SynSent => tcb.state = State::Closed,
+            State::SynReceived | State::Established | State::CloseWait if !tcb.recv.is_empty() => tcb.abort(),
+            State::SynReceived | State::Established => {
+                tcb.fin_queued = true;
+                tcb.state = State::FinWait1;
+                tcb.output(false);
+            }
+            State::CloseWait => {
+                tcb.fin_queued = true;
+                tcb.state = State::LastAck;
+                tcb.output(false);
+            }
+            State::FinWait2 => tcb.linger_until = Some(time_ms() + FIN_WAIT_2_MS),
+            _ => {}
+        }
+        let closed = tcb.state == State::Closed;
+        drop(tcb);
+        if closed {
+            self.remove();
+        }
+        self.wake();
+        //connections nobody will accept
+        for socket in backlog {
+            socket.tcb.lock().abort();
+            socket.remove();
+        }
+    }
+
+    //a segment for this socket, header is the IP header it came in
+    fn input(self: &Arc<Self>, header: &Ipv4Header, segment: &Segment) -> Action {
+        let mut tcb = self.tcb.lock();
+        let action = match tcb.state {
+            State::Closed => {
+                drop(tcb);
+                reset(header, segment);
+                return Action::None;
+            }
+            State::Listen => {
+                drop(tcb);
+                self.input_listen(header, segment);
+                return Action::None;
+            }
+            State::SynSent => Self::input_syn_sent(&mut tcb, segment),
+            _ => Self::input_synchronized(&mut tcb, segment),
+        };
+        if tcb.ack_pending {
+            let seq = tcb.snd_nxt;
+            tcb.segment(seq, 0, &[]);
+        }
+        let action = match (action, tcb.state) {
+            (_, State::Closed) => Action::Remove,
+            (action, _) => action,
+        };
+        drop(tcb);
+        self.wake();
+        action
+    }
+
+    //connections of this listener that are still in SYN-RECEIVED
+    fn embryonic(self: &Arc<Self>) -> usize {
+        let sockets = SOCKETS.lock();
+        sockets
+            .iter()
+            .filter(|socket| {
+                let tcb = socket.tcb.lock();
+                tcb.state == State::SynReceived && tcb.listener.as_ptr() == Arc::as_ptr(self)
+            })
+            .count()
+    }
+
+    //a SYN makes a connection in SYN-RECEIVED, it is accepted once it is established.
+    //the ones not established yet count against the backlog, a SYN beyond it is dropped
+    fn input_listen(self: &Arc<Self>, header: &Ipv4Header, segment: &Segment) {
+        if segment.flags & RST != 0 {
+            return;
+        }
+        if segment.flags & ACK != 0 || segment.flags & SYN == 0 {
+            return reset(header, segment);
+        }
+        let tcb = self.tcb.lock();
+        let (queued, max_backlog, reuse_addr) = (tcb.backlog.len(), tcb.max_backlog, tcb.reuse_addr);
+        drop(tcb);
+        if queued + self.embryonic() >= max_backlog {
+            return;
+        }
+        let mut child = Tcb::new();
+        child.state = State::SynReceived;
+        child.bound = true;
+        child.reuse_addr = reuse_addr;
+        child.local = (header.dst, segment.dst_port);
+        child.remote = (header.src, segment.src_port);
+        child.rcv_nxt = segment.seq.wrapping_add(1);
+        child.snd_wnd = segment.window as usize;
+        //what the peer takes, cut to what leaves our interface
+        child.snd_mss = segment.mss.unwrap_or(DEFAULT_MSS).min(child.our_mss() as usize);
+        child.listener = Arc::downgrade(self);
+        child.start();
+        child.output(false);
+        let child = Arc::new(TcpSocket {
+            tcb: Mutex::new(child),
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        });
+        SOCKETS.lock().push(child);
+    }
+
+    fn input_syn_sent(tcb: &mut Tcb, segment: &Segment) -> Action {
+        let ack_ok = segment.flags & ACK != 0 && segment.ack == tcb.iss.wrapping_add(1);
+        if segment.flags & ACK != 0 && !ack_ok {
+            if segment.flags & RST == 0 {
+                transmit(tcb.local, tcb.remote, segment.ack, 0, RST, 0, None, &[]);
+            }
+            return Action::None;
+        }
+        if segment.flags & RST != 0 {
+            if ack_ok {
+                tcb.error = Some(Errno::ECONNREFUSED);
+                tcb.state = State::Closed;
+            }
+            return Action::None;
+        }
+        if segment.flags & SYN == 0 {
+            return Action::None;
+        }
+        tcb.rcv_nxt = segment.seq.wrapping_add(1);
+        tcb.snd_wnd = segment.window as usize;
+        tcb.snd_mss = segment.mss.unwrap_or(DEFAULT_MSS).min(tcb.our_mss() as usize);
+        match ack_ok {
+            true => {
+                tcb.acked(segment.ack);
+                tcb.state = State::Established;
+                tcb.ack_pending = true;
+                tcb.output(false);
+            }
+            //both sides opened at once
+            false => {
+                tcb.state = State::SynReceived;
+                tcb.snd_nxt = tcb.iss;
+                tcb.output(false);
+            }
+        }
+        Action::None
+    }
+
+    fn input_synchronized(tcb: &mut Tcb, segment: &Segment) -> Action {
+        let mut action = Action::None;
+        let mut seq = segment.seq;
+        let mut data = segment.data;
+        let mut flags = segment.flags;
+        //the SYN-ACK got lost, the peer sends its SYN again
+        if tcb.state == State::SynReceived && flags & SYN != 0 && seq.wrapping_add(1) == tcb.rcv_nxt {
+            tcb.snd_nxt = tcb.iss;
+            tcb.output(false);
+            return action;
+        }
+        //what came before rcv_nxt was received already
+        if seq_lt(seq, tcb.rcv_nxt) {
+            if flags & SYN != 0 {
+                flags &= !SYN;
+                seq = seq.wrapping_add(1);
+            }
+            let old = tcb.rcv_nxt.wrapping_sub(seq) as usize;
+            if old > data.len() || (old == data.len() && flags & FIN == 0) {
+                if flags & RST == 0 {
+                    tcb.ack_pending = true;
+                }
+                return action;
+            }
+            data = &data[old..];
+            seq = tcb.rcv_nxt;
+        }
+        //out of order, or beyond the window
+        if seq != tcb.rcv_nxt {
+            if flags & RST == 0 {
+                tcb.ack_pending = true;
+            }
+            return action;
+        }
+        if flags & RST != 0 {
+            //a connection a listener made goes away quietly
+            if tcb.state != State::SynReceived {
+                tcb.error = Some(Errno::ECONNRESET);
+            }
+            tcb.state = State::Closed;
+            return action;
+        }
+        if flags & SYN != 0 {
+            tcb.ack_pending = true;
+            return action;
+        }
+        if flags & ACK == 0 {
+            return action;
+        }
+        let ack = segment.ack;
+        if tcb.state == State::SynReceived {
+            if !seq_lt(tcb.snd_una, ack) || !seq_le(ack, tcb.snd_max) {
+                transmit(tcb.local, tcb.remote, ack, 0, RST, 0, None, &[]);
+                return action;
+            }
+            tcb.state = State::Established;
+            action = Action::Accept;
+        }
+        if seq_lt(tcb.snd_una, ack) && seq_le(ack, tcb.snd_max) {
+            tcb.acked(ack);
+        } else if seq_lt(tcb.snd_max, ack) {
+            //acks what was never sent
+            tcb.ack_pending = true;
+            return action;
+        }
+        if seq_le(tcb.snd_una, ack) {
+            tcb.snd_wnd = segment.window as usize;
+        }
+        if tcb.fin_acked() {
+            match tcb.state {
+                State::FinWait1 => {
+                    tcb.state = State::FinWait2;
+                    if tcb.orphan {
+                        tcb.linger_until = Some(time_ms() + FIN_WAIT_2_MS);
+                    }
+                }
+                State::Closing => tcb.enter_time_wait(),
+                State::LastAck => {
+                    tcb.state = State::Closed;
+                    return action;
+                }
+                _ => {}
+            }
+        }
+        let mut fin = flags & FIN != 0;
+        if !data.is_empty() {
+            match tcb.state {
+                State::Established | State::FinWait1 | State::FinWait2 if !tcb.shut_rd => {
+                    let n = data.len().min(tcb.rcv_wnd());
+                    tcb.recv.extend(&data[..n]);
+                    tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(n as u32);
+                    //the FIN after data that did not fit comes again
+                    fin &= n == data.len();
+                }
+                //nobody reads it anymore
+                State::Established | State::FinWait1 | State::FinWait2 => {
+                    tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(data.len() as u32);
+                }
+                _ => fin = false,
+            }
+            tcb.ack_pending = true;
+        }
+        if fin && !tcb.rcv_fin {
+            tcb.rcv_fin = true;
+            tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(1);
+            tcb.ack_pending = true;
+            match tcb.state {
+                State::Established => tcb.state = State::CloseWait,
+                State::FinWait1 if tcb.fin_acked() => tcb.enter_time_wait(),
+                State::FinWait1 => tcb.state = State::Closing,
+                State::FinWait2 => tcb.enter_time_wait(),
+                _ => {}
+            }
+        } else if fin && tcb.state == State::TimeWait {
+            //our last ACK got lost
+            tcb.ack_pending = true;
+            tcb.enter_time_wait();
+        }
+        tcb.output(false);
+        action
+    }
+
+    //hand a connection that completed the handshake to its listener
+    fn accepted(self: &Arc<Self>) {
+        let listener = self.tcb.lock().listener.upgrade();
+        let queued = listener.as_ref().is_some_and(|listener| {
+            let mut tcb = listener.tcb.lock();
+            if tcb.state != State::Listen || tcb.backlog.len() >= tcb.max_backlog {
+                return false;
+            }
+            tcb.backlog.push_back(self.clone());
+            true
+        });
+        match (queued, listener) {
+            (true, Some(listener)) => listener.readable.wake_all(),
+            _ => {
+                self.tcb.lock().abort();
+                self.remove();
+            }
+        }
+    }
+
+    //retransmission and the end of TIME-WAIT
+    fn timer(self: &Arc<Self>, now: u64) {
+        let mut tcb = self.tcb.lock();
+        if tcb.linger_until.is_some_and(|until| until <= now) {
+            tcb.state = State::Closed;
+        }
+        if tcb.retransmit_at.is_some_and(|at| at <= now) {
+            tcb.retransmit_at = None;
+            tcb.retries += 1;
+            if tcb.retries > MAX_RETRIES {
+                tcb.error = Some(Errno::ETIMEDOUT);
+                tcb.abort();
+            } else {
+                tcb.rto = (tcb.rto * 2).min(RTO_MAX);
+                //go back to the oldest byte the peer did not ack
+                tcb.snd_nxt = tcb.snd_una;
+                if matches!(tcb.state, State::SynSent | State::SynReceived) {
+                    tcb.snd_nxt = tcb.iss;
+                }
+                tcb.output(true);
+            }
+        }
+        let closed = tcb.state == State::Closed;
+        drop(tcb);
+        if closed {
+            self.remove();
+            self.wake();
+        }
+    }
+}
+
+//an established connection first, a listener on the port otherwise
+fn lookup(header: &Ipv4Header, segment: &Segment) -> Option<Arc<TcpSocket>> {
+    let local = (header.dst, segment.dst_port);
+    let remote = (header.src, segment.src_port);
+    let sockets = SOCKETS.lock().clone();
+    let mut listener = None;
+    for socket in sockets {
+        let tcb = socket.tcb.lock();
+        match tcb.state {
+            State::Closed => {}
+            State::Listen
+                if tcb.local.1 == local.1 && (tcb.local.0.is_unspecified() || tcb.local.0 == local.0) =>
+            {
+                drop(tcb);
+                listener = Some(socket);
+            }
+            State::Listen => {}
+            _ if tcb.local == local && tcb.remote == remote => {
+                drop(tcb);
+                return Some(socket);
+            }
+            _ => {}
+        }
+    }
+    listener
+}
+
+pub fn input(header: &Ipv4Header, segment: &[u8], checked: bool) {
+    let segment = match parse(header, segment, checked) {
+        None => return,
+        Some(segment) => segment,
+    };
+    let socket = match lookup(header, &segment) {
+        None => return reset(header, &segment),
+        Some(socket) => socket,
+    };
+    match socket.input(header, &segment) {
+        Action::None => {}
+        Action::Accept => socket.accepted(),
+        Action::Remove => socket.remove(),
+    }
+}
+
+pub fn tick(now: u64) {
+    let sockets = SOCKETS.lock().clone();
+    for socket in sockets {
+        socket.timer(now);
+    }
+}
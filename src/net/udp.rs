@@ -0,0 +1,211 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::ip::{self, pseudo_sum, Ipv4Header, PROTO_UDP};
+use super::{checksum_add, checksum_fold, ephemeral_port, is_local, sleep_on, source_for, Endpoint, Ipv4Addr};
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::task::wait::WaitQueue;
+
+//RFC 768. A bound socket is in SOCKETS, datagrams for it queue up to RX_LIMIT bytes
+//and the rest is dropped
+
+const HEADER_LEN: usize = 8;
+const RX_LIMIT: usize = 64 * 1024;
+//what fits in an IPv4 packet
+pub const MAX_PAYLOAD: usize = 65507;
+
+struct Datagram {
+    from: Endpoint,
+    data: Vec<u8>,
+}
+
+struct UdpInner {
+    local: Option<Endpoint>,
+    remote: Option<Endpoint>,
+    rx: VecDeque<Datagram>,
+    rx_bytes: usize,
+    shut_rd: bool,
+    closed: bool,
+}
+
+pub struct UdpSocket {
+    inner: Mutex<UdpInner>,
+    readable: WaitQueue,
+}
+
+static SOCKETS: Mutex<Vec<Arc<UdpSocket>>> = Mutex::new(Vec::new());
+
+fn port_in_use(sockets: &[Arc<UdpSocket>], addr: Ipv4Addr, port: u16) -> bool {
+    sockets.iter().any(|socket| {
+        socket.inner.lock().local.is_some_and(|(local, local_port)| {
+            local_port == port && (local.is_unspecified() || addr.is_unspecified() || local == addr)
+        })
+    })
+}
+
+impl UdpSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(UdpInner {
+                local: None,
+                remote: None,
+                rx: VecDeque::new(),
+                rx_bytes: 0,
+                shut_rd: false,
+                closed: false,
+            }),
+            readable: WaitQueue::new(),
+        })
+    }
+
+    //port 0 takes an ephemeral one
+    pub fn bind(self: &Arc<Self>, addr: Ipv4Addr, port: u16) -> SysResult<()> {
+        if !addr.is_unspecified() && !is_local(addr) && addr != Ipv4Addr::BROADCAST {
+            return Err(Errno::EADDRNOTAVAIL);
+        }
+        let mut sockets = SOCKETS.lock();
+        if self.inner.lock().local.is_some() {
+            return Err(Errno::EINVAL);
+        }
+        let port = match port {
+            0 => ephemeral_port(|port| port_in_use(&sockets, addr, port))?,
+            port if port_in_use(&sockets, addr, port) => return Err(Errno::EADDRINUSE),
+            port => port,
+        };
+        self.inner.lock().local = Some((addr, port));
+        sockets.push(self.clone());
+        Ok(())
+    }
+
+    fn autobind(self: &Arc<Self>) -> SysResult<()> {
+        if self.local().is_some() {
+            return Ok(());
+        }
+        self.bind(Ipv4Addr::UNSPECIFIED, 0)
+    }
+
+    //the default destination, and only datagrams from there are received
+    pub fn connect(self: &Arc<Self>, addr: Ipv4Addr, port: u16) -> SysResult<()> {
+        if port == 0 {
+            return Err(Errno::EINVAL);
+        }
+        self.autobind()?;
+        self.inner.lock().remote = Some((addr, port));
+        Ok(())
+    }
+
+    pub fn local(&self) -> Option<Endpoint> {
+        self.inner.lock().local
+    }
+
+    pub fn remote(&self) -> Option<Endpoint> {
+        self.inner.lock().remote
+    }
+
+    //to dst, or where the socket is connected
+    pub fn send_to(self: &Arc<Self>, data: &[u8], dst: Option<Endpoint>) -> SysResult {
+        let (addr, port) = match dst.or(self.remote()) {
+            None => return Err(Errno::EDESTADDRREQ),
+            Some((_, 0)) => return Err(Errno::EINVAL),
+            Some(dst) => dst,
+        };
+        if data.len() > MAX_PAYLOAD {
+            return Err(Errno::EMSGSIZE);
+        }
+        self.autobind()?;
+        let (local, local_port) = self.local().unwrap();
+        let src = match local.is_unspecified() {
+            true => source_for(addr)?,
+            false => local,
+        };
+        let len = HEADER_LEN + data.len();
+        let mut datagram = Vec::with_capacity(len);
+        datagram.extend_from_slice(&local_port.to_be_bytes());
+        datagram.extend_from_slice(&port.to_be_bytes());
+        datagram.extend_from_slice(&(len as u16).to_be_bytes());
+        datagram.extend_from_slice(&checksum_fold(pseudo_sum(src, addr, PROTO_UDP, len)).to_be_bytes());
+        datagram.extend_from_slice(data);
+        ip::output(src, addr, PROTO_UDP, &datagram, Some(6))?;
+        Ok(data.len())
+    }
+
+    //the next datagram, cut to buf, and where it came from. Blocks until deadline
+    //(time_ms), EAGAIN after it
+    pub fn recv_from(&self, buf: &mut [u8], deadline: Option<u64>) -> SysResult<(usize, Endpoint)> {
+        loop {
+            let mut inner = self.inner.lock();
+            if let Some(datagram) = inner.rx.pop_front() {
+                inner.rx_bytes -= datagram.data.len();
+                let n = buf.len().min(datagram.data.len());
+                buf[..n].copy_from_slice(&datagram.data[..n]);
+                return Ok((n, datagram.from));
+            }
+            if inner.shut_rd || inner.closed {
+                return Ok((0, (Ipv4Addr::UNSPECIFIED, 0)));
+            }
+            sleep_on(&self.readable, inner, deadline)?;
+        }
+    }
+
+    pub fn has_data(&self) -> bool {
+        !self.inner.lock().rx.is_empty()
+    }
+
+    //what is queued is still received
+    pub fn shutdown_read(&self) {
+        self.inner.lock().shut_rd = true;
+        self.readable.wake_all();
+    }
+
+    //unbinds, the last handle is gone
+    pub fn close(self: &Arc<Self>) {
+        SOCKETS.lock().retain(|socket| !Arc::ptr_eq(socket, self));
+        self.inner.lock().closed = true;
+        self.readable.wake_all();
+    }
+
+    fn deliver(&self, from: Endpoint, to: Ipv4Addr, port: u16, data: &[u8]) -> bool {
+        let mut inner = self.inner.lock();
+        let accepts = inner.local.is_some_and(|(local, local_port)| {
+            local_port == port && (local.is_unspecified() || local == to)
+        }) && inner.remote.map_or(true, |remote| remote == from);
+        if !accepts {
+            return false;
+        }
+        if !inner.shut_rd && inner.rx_bytes + data.len() <= RX_LIMIT {
+            inner.rx_bytes += data.len();
+            inner.rx.push_back(Datagram { from, data: Vec::from(data) });
+            drop(inner);
+            self.readable.wake_all();
+        }
+        true
+    }
+}
+
+pub fn input(header: &Ipv4Header, datagram: &[u8], checked: bool) {
+    if datagram.len() < HEADER_LEN {
+        return;
+    }
+    let len = u16::from_be_bytes([datagram[4], datagram[5]]) as usize;
+    if len < HEADER_LEN || len > datagram.len() {
+        return;
+    }
+    let datagram = &datagram[..len];
+    //0 is a datagram sent without a checksum
+    let sum = u16::from_be_bytes([datagram[6], datagram[7]]);
+    if !checked && sum != 0 && checksum_fold(checksum_add(datagram, header.pseudo_sum(len))) != 0xffff {
+        return;
+    }
+    let from = (header.src, u16::from_be_bytes([datagram[0], datagram[1]]));
+    let port = u16::from_be_bytes([datagram[2], datagram[3]]);
+    //connected sockets first, they are the more specific ones
+    let mut sockets = SOCKETS.lock().clone();
+    sockets.sort_by_key(|socket| socket.remote().is_none());
+    for socket in sockets {
+        if socket.deliver(from, header.dst, port, &datagram[HEADER_LEN..]) {
+            return;
+        }
+    }
+}
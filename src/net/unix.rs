@@ -0,0 +1,306 @@
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use super::sleep_on;
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::task::signal;
+use crate::task::wait::WaitQueue;
+
+//AF_UNIX stream sockets. There is no file system, a name is bound in NAMES for as long
+//as its socket lives. connect completes at once, the connection waits in the backlog
+//of the listener until it is accepted
+
+const BUFFER_SIZE: usize = 16 * 1024;
+pub const MAX_BACKLOG: usize = 128;
+
+struct ChannelInner {
+    data: VecDeque<u8>,
+    reader_open: bool,
+    writer_open: bool,
+}
+
+//one direction of a connection, as a pipe
+struct Channel {
+    inner: Mutex<ChannelInner>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+}
+
+impl Channel {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(ChannelInner { data: VecDeque::new(), reader_open: true, writer_open: true }),
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        })
+    }
+
+    //0 once either end is shut down and nothing is left
+    fn read(&self, buf: &mut [u8], deadline: Option<u64>) -> SysResult {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut inner = self.inner.lock();
+            if !inner.reader_open {
+                return Ok(0);
+            }
+            if !inner.data.is_empty() {
+                let n = buf.len().min(inner.data.len());
+                for (byte, data) in buf.iter_mut().zip(inner.data.drain(..n)) {
+                    *byte = data;
+                }
+                drop(inner);
+                self.writable.wake_all();
+                return Ok(n);
+            }
+            if !inner.writer_open {
+                return Ok(0);
+            }
+            sleep_on(&self.readable, inner, deadline)?;
+        }
+    }
+
+    //EPIPE and SIGPIPE once either end is shut down, a signal or the deadline end the
+    //wait with what was written so far
+    fn write(&self, data: &[u8], deadline: Option<u64>) -> SysResult {
+        let mut written = 0;
+        loop {
+            let mut inner = self.inner.lock();
+            if !inner.reader_open || !inner.writer_open {
+                drop(inner);
+                return match written {
+                    0 => {
+                        signal::raise(signal::SIGPIPE);
+                        Err(Errno::EPIPE)
+                    }
+                    _ => Ok(written),
+                };
+            }
+            let n = (BUFFER_SIZE - inner.data.len()).min(data.len() - written);
+            inner.data.extend(&data[written..written + n]);
+            written += n;
+            if n > 0 {
+                self.readable.wake_all();
+            }
+            if written == data.len() {
+                return Ok(written);
+            }
+            if let Err(errno) = sleep_on(&self.writable, inner, deadline) {
+                return match written {
+                    0 => Err(errno),
+                    _ => Ok(written),
+                };
+            }
+        }
+    }
+
+    fn close_reader(&self) {
+        self.inner.lock().reader_open = false;
+        self.readable.wake_all();
+        self.writable.wake_all();
+    }
+
+    fn close_writer(&self) {
+        self.inner.lock().writer_open = false;
+        self.readable.wake_all();
+        self.writable.wake_all();
+    }
+
+    fn has_data(&self) -> bool {
+        let inner = self.inner.lock();
+        !inner.data.is_empty() || !inner.writer_open
+    }
+}
+
+struct Connection {
+    rx: Arc<Channel>,
+    tx: Arc<Channel>,
+    //the name the peer was bound to, empty when it was not
+    peer: Vec<u8>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.rx.close_reader();
+        self.tx.close_writer();
+    }
+}
+
+struct Backlog {
+    pending: VecDeque<Arc<UnixSocket>>,
+    //0 until listen
+    max: usize,
+}
+
+//what a name is bound to, connections are queued here once the socket listens
+struct Listener {
+    backlog: Mutex<Backlog>,
+    readable: WaitQueue,
+}
+
+enum State {
+    Unbound,
+    Bound(Vec<u8>, Arc<Listener>),
+    Connected(Connection),
+}
+
+pub struct UnixSocket {
+    state: Mutex<State>,
+}
+
+static NAMES: Mutex<Vec<(Vec<u8>, Weak<Listener>)>> = Mutex::new(Vec::new());
+
+fn lookup(name: &[u8]) -> Option<Arc<Listener>> {
+    let names = NAMES.lock();
+    names.iter().find(|(bound, _)| bound == name).and_then(|(_, listener)| listener.upgrade())
+}
+
+impl UnixSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { state: Mutex::new(State::Unbound) })
+    }
+
+    pub fn bind(&self, name: &[u8]) -> SysResult<()> {
+        if name.is_empty() {
+            return Err(Errno::EINVAL);
+        }
+        let mut state = self.state.lock();
+        if !matches!(*state, State::Unbound) {
+            return Err(Errno::EINVAL);
+        }
+        let mut names = NAMES.lock();
+        //the names of closed sockets are free again
+        names.retain(|(_, listener)| listener.strong_count() > 0);
+        if names.iter().any(|(bound, _)| bound == name) {
+            return Err(Errno::EADDRINUSE);
+        }
+        let listener = Arc::new(Listener {
+            backlog: Mutex::new(Backlog { pending: VecDeque::new(), max: 0 }),
+            readable: WaitQueue::new(),
+        });
+        names.push((Vec::from(name), Arc::downgrade(&listener)));
+        *state = State::Bound(Vec::from(name), listener);
+        Ok(())
+    }
+
+    pub fn local(&self) -> Option<Vec<u8>> {
+        match &*self.state.lock() {
+            State::Bound(name, _) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn listen(&self, backlog: usize) -> SysResult<()> {
+        match &*self.state.lock() {
+            State::Bound(_, listener) => {
+                listener.backlog.lock().max = backlog.clamp(1, MAX_BACKLOG);
+                Ok(())
+            }
+            State::Unbound => Err(Errno::EINVAL),
+            State::Connected(_) => Err(Errno::EISCONN),
+        }
+    }
+
+    pub fn is_listening(&self) -> bool {
+        match &*self.state.lock() {
+            State::Bound(_, listener) => listener.backlog.lock().max > 0,
+            _ => false,
+        }
+    }
+
+    //the next connection and the name of its peer
+    pub fn accept(&self, deadline: Option<u64>) -> SysResult<(Arc<UnixSocket>, Vec<u8>)> {
+        let listener = match &*self.state.lock() {
+            State::Bound(_, listener) => listener.clone(),
+            _ => return Err(Errno::EINVAL),
+        };
+        loop {
+            let mut backlog = listener.backlog.lock();
+            if backlog.max == 0 {
+                return Err(Errno::EINVAL);
+            }
+            if let Some(socket) = backlog.pending.pop_front() {
+                drop(backlog);
+                let peer = match &*socket.state.lock() {
+                    State::Connected(connection) => connection.peer.clone(),
+                    _ => Vec::new(),
+                };
+                return Ok((socket, peer));
+            }
+            sleep_on(&listener.readable, backlog, deadline)?;
+        }
+    }
+
+    //ECONNREFUSED when nobody listens on name, EAGAIN when its backlog is full
+    pub fn connect(&self, name: &[u8]) -> SysResult<()> {
+        let mut state = self.state.lock();
+        let local = match &*state {
+            State::Unbound => Vec::new(),
+            State::Bound(_, listener) if listener.backlog.lock().max > 0 => return Err(Errno::EINVAL),
+            State::Bound(name, _) => name.clone(),
+            State::Connected(_) => return Err(Errno::EISCONN),
+        };
+        let listener = lookup(name).ok_or(Errno::ECONNREFUSED)?;
+        let mut backlog = listener.backlog.lock();
+        if backlog.max == 0 {
+            return Err(Errno::ECONNREFUSED);
+        }
+        if backlog.pending.len() >= backlog.max {
+            return Err(Errno::EAGAIN);
+        }
+        let (to_server, to_client) = (Channel::new(), Channel::new());
+        let server = Connection { rx: to_server.clone(), tx: to_client.clone(), peer: local };
+        backlog.pending.push_back(Arc::new(Self { state: Mutex::new(State::Connected(server)) }));
+        drop(backlog);
+        listener.readable.wake_all();
+        *state = State::Connected(Connection { rx: to_client, tx: to_server, peer: Vec::from(name) });
+        Ok(())
+    }
+
+    pub fn peer(&self) -> Option<Vec<u8>> {
+        match &*self.state.lock() {
+            State::Connected(connection) => Some(connection.peer.clone()),
+            _ => None,
+        }
+    }
+
+    fn channels(&self) -> SysResult<(Arc<Channel>, Arc<Channel>)> {
+        match &*self.state.lock() {
+            State::Connected(connection) => Ok((connection.rx.clone(), connection.tx.clone())),
+            _ => Err(Errno::ENOTCONN),
+        }
+    }
+
+    pub fn send(&self, data: &[u8], deadline: Option<u64>) -> SysResult {
+        let (_, tx) = self.channels()?;
+        tx.write(data, deadline)
+    }
+
+    pub fn recv(&self, buf: &mut [u8], deadline: Option<u64>) -> SysResult {
+        let (rx, _) = self.channels()?;
+        rx.read(buf, deadline)
+    }
+
+    //data waiting to be received, the end of it or a connection to accept
+    pub fn readable(&self) -> bool {
+        match &*self.state.lock() {
+            State::Connected(connection) => connection.rx.has_data(),
+            State::Bound(_, listener) => !listener.backlog.lock().pending.is_empty(),
+            State::Unbound => false,
+        }
+    }
+
+    pub fn shutdown(&self, read: bool, write: bool) -> SysResult<()> {
+        let (rx, tx) = self.channels()?;
+        if read {
+            rx.close_reader();
+        }
+        if write {
+            tx.close_writer();
+        }
+        Ok(())
+    }
+}
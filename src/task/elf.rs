@@ -0,0 +1,126 @@
+#![allow(dead_code)]
+
+use core::mem::size_of;
+
+use crate::mm::PTEFlags;
+
+//https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.eheader.html
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LSB: u8 = 1;
+const EM_AARCH64: u16 = 183;
+const PT_LOAD: u32 = 1;
+
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+const PF_R: u32 = 1 << 2;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ElfHeader {
+    ident: [u8; 16],
+    e_type: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    ph_off: u64,
+    sh_off: u64,
+    flags: u32,
+    eh_size: u16,
+    ph_ent_size: u16,
+    ph_num: u16,
+    sh_ent_size: u16,
+    sh_num: u16,
+    sh_str_index: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ProgramHeader {
+    p_type: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    file_size: u64,
+    mem_size: u64,
+    align: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Segment<'a> {
+    pub vaddr: usize,
+    pub mem_size: usize,
+    pub data: &'a [u8],
+    pub flags: PTEFlags,
+}
+
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: ElfHeader,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < size_of::<ElfHeader>() {
+            return None;
+        }
+        let header = unsafe { (data.as_ptr() as *const ElfHeader).read_unaligned() };
+        if header.ident[0..4] != ELF_MAGIC
+            || header.ident[4] != ELF_CLASS_64
+            || header.ident[5] != ELF_DATA_LSB
+            || header.machine != EM_AARCH64
+            || header.ph_ent_size as usize != size_of::<ProgramHeader>()
+        {
+            return None;
+        }
+        let ph_end = header.ph_off as usize + header.ph_num as usize * size_of::<ProgramHeader>();
+        if ph_end > data.len() {
+            return None;
+        }
+        Some(Self { data, header })
+    }
+    pub fn entry(&self) -> usize {
+        self.header.entry as usize
+    }
+    fn program_header(&self, index: usize) -> ProgramHeader {
+        let offset = self.header.ph_off as usize + index * size_of::<ProgramHeader>();
+        unsafe { (self.data.as_ptr().add(offset) as *const ProgramHeader).read_unaligned() }
+    }
+    //PT_LOAD segments only, with the least privilege flags for user space
+    pub fn segments(&self) -> impl Iterator<Item=Option<Segment<'a>>> + '_ {
+        (0..self.header.ph_num as usize)
+            .map(|i| self.program_header(i))
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .map(|ph| {
+                let start = ph.offset as usize;
+                let end = start + ph.file_size as usize;
+                if end > self.data.len() || ph.file_size > ph.mem_size {
+                    return None;
+                }
+                Some(Segment {
+                    vaddr: ph.vaddr as usize,
+                    mem_size: ph.mem_size as usize,
+                    data: &self.data[start..end],
+                    flags: Self::segment_flags(ph.flags)?,
+                })
+            })
+    }
+    fn segment_flags(p_flags: u32) -> Option<PTEFlags> {
+        //W^X: a segment is either writable or executable, never both
+        if p_flags & PF_W != 0 && p_flags & PF_X != 0 {
+            return None;
+        }
+        let mut flags = PTEFlags::U;
+        if p_flags & (PF_R | PF_W | PF_X) != 0 {
+            flags |= PTEFlags::R;
+        }
+        if p_flags & PF_W != 0 {
+            flags |= PTEFlags::W;
+        }
+        if p_flags & PF_X != 0 {
+            flags |= PTEFlags::X;
+        }
+        Some(flags)
+    }
+}
@@ -0,0 +1,98 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::common::errno::{Errno, SysResult};
+use crate::devices::gets;
+use crate::net::socket::Socket;
+use crate::print;
+
+pub trait File: Send + Sync {
+    fn read(&self, buf: &mut [u8]) -> SysResult;
+    fn write(&self, buf: &[u8]) -> SysResult;
+    //the console, the only file with a foreground process group
+    fn is_tty(&self) -> bool {
+        false
+    }
+    //what the socket calls work on
+    fn socket(&self) -> Option<&Socket> {
+        None
+    }
+}
+
+//stdin polls the uart rx buffer, 0 bytes when nothing was typed
+pub struct Console;
+
+impl File for Console {
+    fn read(&self, buf: &mut [u8]) -> SysResult {
+        Ok(gets(buf))
+    }
+    fn write(&self, buf: &[u8]) -> SysResult {
+        print!("{}", String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+    fn is_tty(&self) -> bool {
+        true
+    }
+}
+
+//per task descriptor table, shared open files are reference counted
+#[derive(Clone)]
+pub struct FdTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FdTable {
+    pub const MAX_FDS: usize = 64;
+
+    pub const fn empty() -> Self {
+        Self { files: Vec::new() }
+    }
+    //stdin, stdout and stderr on the console
+    pub fn console() -> Self {
+        let console: Arc<dyn File> = Arc::new(Console);
+        Self {
+            files: vec![Some(console.clone()), Some(console.clone()), Some(console)],
+        }
+    }
+    pub fn get(&self, fd: usize) -> SysResult<Arc<dyn File>> {
+        self.files.get(fd).cloned().flatten().ok_or(Errno::EBADF)
+    }
+    //lowest free descriptor
+    pub fn alloc(&mut self, file: Arc<dyn File>) -> SysResult {
+        match self.files.iter().position(|file| file.is_none()) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                Ok(fd)
+            }
+            None if self.files.len() < Self::MAX_FDS => {
+                self.files.push(Some(file));
+                Ok(self.files.len() - 1)
+            }
+            None => Err(Errno::EMFILE),
+        }
+    }
+    pub fn close(&mut self, fd: usize) -> SysResult {
+        match self.files.get_mut(fd).and_then(|file| file.take()) {
+            Some(_) => Ok(0),
+            None => Err(Errno::EBADF),
+        }
+    }
+    pub fn dup(&mut self, fd: usize) -> SysResult {
+        let file = self.get(fd)?;
+        self.alloc(file)
+    }
+    //new_fd is closed first if it is open
+    pub fn dup3(&mut self, old_fd: usize, new_fd: usize) -> SysResult {
+        let file = self.get(old_fd)?;
+        if old_fd == new_fd || new_fd >= Self::MAX_FDS {
+            return Err(Errno::EINVAL);
+        }
+        if new_fd >= self.files.len() {
+            self.files.resize(new_fd + 1, None);
+        }
+        self.files[new_fd] = Some(file);
+        Ok(new_fd)
+    }
+}
@@ -0,0 +1,122 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use crate::arch::time_ms;
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::mm::{PAGE_SIZE, UserPtr, VirtAddr};
+use crate::task::scheduler;
+use crate::task::signal;
+use crate::task::task::Task;
+use crate::task::wait::WaitQueue;
+
+//https://man7.org/linux/man-pages/man2/futex.2.html
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+pub const FUTEX_CLOCK_REALTIME: usize = 256;
+pub const FUTEX_CMD_MASK: usize = !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+enum FutexKey {
+    //address space and virtual address, a page that is not shared can only be
+    //reached through this one address (and may be swapped out and back elsewhere)
+    Private(usize, usize),
+    //physical address, the same word seen through any shm or MAP_SHARED mapping
+    Shared(usize),
+}
+
+//a queue lives as long as somebody sleeps on it
+static FUTEXES: Mutex<BTreeMap<FutexKey, Arc<WaitQueue>>> = Mutex::new(BTreeMap::new());
+
+fn key(task: &Task, uaddr: usize, private: bool) -> SysResult<FutexKey> {
+    if uaddr % 4 != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut vm = task.page.lock();
+    if !vm.access_ok(uaddr, 4, false) {
+        return Err(Errno::EFAULT);
+    }
+    let vm_id = Arc::as_ptr(&task.page) as usize;
+    if private {
+        return Ok(FutexKey::Private(vm_id, uaddr));
+    }
+    match vm.page_table().entry(VirtAddr::new(uaddr)) {
+        Some(entry) if entry.is_shared() => {
+            Ok(FutexKey::Shared(entry.as_phy_addr().as_usize() + uaddr % PAGE_SIZE))
+        }
+        _ => Ok(FutexKey::Private(vm_id, uaddr)),
+    }
+}
+
+//sleep while *uaddr == val, for at most timeout_ms
+pub fn wait(uaddr: usize, val: u32, timeout_ms: Option<u64>, private: bool) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let key = unsafe { key(&*task, uaddr, private)? };
+    let deadline = timeout_ms.map(|ms| time_ms() + ms.max(1));
+    let mut futexes = FUTEXES.lock();
+    //the word is read under the lock FUTEX_WAKE takes, a wake up after the
+    //user changed it but before we sleep is not lost
+    let mut word = [0u32];
+    UserPtr::<u32>::new(uaddr, 1).copy_to(&mut word, 1);
+    if word[0] != val {
+        return Err(Errno::EAGAIN);
+    }
+    if signal::interrupted() {
+        return Err(Errno::EINTR);
+    }
+    let queue = (*futexes).entry(key).or_insert_with(|| Arc::new(WaitQueue::new())).clone();
+    queue.sleep_until(futexes, deadline);
+
+    let mut futexes = FUTEXES.lock();
+    let woken = unsafe { !queue.cancel((*task).pid()) };
+    if queue.is_empty() && (*futexes).get(&key).is_some_and(|other| Arc::ptr_eq(other, &queue)) {
+        (*futexes).remove(&key);
+    }
+    if woken {
+        return Ok(0);
+    }
+    match deadline {
+        Some(deadline) if time_ms() >= deadline => Err(Errno::ETIMEDOUT),
+        _ if signal::interrupted() => Err(Errno::EINTR),
+        //spurious, the caller rechecks the word anyway
+        _ => Ok(0),
+    }
+}
+
+//wake up to n waiters on uaddr, returns how many
+pub fn wake(uaddr: usize, n: usize, private: bool) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let key = unsafe { key(&*task, uaddr, private)? };
+    wake_key(key, n)
+}
+
+fn wake_key(key: FutexKey, n: usize) -> SysResult {
+    let mut futexes = FUTEXES.lock();
+    let woken = match (*futexes).get(&key) {
+        None => return Ok(0),
+        Some(queue) => queue.wake(n),
+    };
+    if (*futexes).get(&key).is_some_and(|queue| queue.is_empty()) {
+        (*futexes).remove(&key);
+    }
+    Ok(woken)
+}
+
+//struct timespec { tv_sec, tv_nsec }, relative for FUTEX_WAIT
+pub fn timeout_ms(addr: usize) -> SysResult<Option<u64>> {
+    if addr == 0 {
+        return Ok(None);
+    }
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    if !unsafe { (*task).page.lock().access_ok(addr, 16, false) } {
+        return Err(Errno::EFAULT);
+    }
+    let mut timespec = [0isize; 2];
+    UserPtr::<isize>::new(addr, 2).copy_to(&mut timespec, 2);
+    let (sec, nsec) = (timespec[0], timespec[1]);
+    if sec < 0 || !(0..1_000_000_000).contains(&nsec) {
+        return Err(Errno::EINVAL);
+    }
+    Ok(Some(sec as u64 * 1000 + (nsec as u64).div_ceil(1_000_000)))
+}
@@ -0,0 +1,102 @@
+#![allow(dead_code)]
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+
+use crate::arch::time_ms;
+use crate::common::errno::SysResult;
+use crate::common::sync::Mutex;
+use crate::task::scheduler;
+use crate::task::sync::Completion;
+use crate::task::task::{Task, TaskFn};
+use crate::task::types::TaskId;
+use crate::task::wait::WaitQueue;
+
+//kernel threads: a TaskFn run in its own kernel task. The handle stops and joins it,
+//a dropped handle leaves the thread running. An exited kernel thread is freed by the
+//scheduler, its return value stays with the handle
+
+struct KThreadState {
+    pid: TaskId,
+    func: TaskFn,
+    arg: usize,
+    stop: AtomicBool,
+    ret: AtomicIsize,
+    done: Completion,
+}
+
+pub struct KThread {
+    state: Arc<KThreadState>,
+}
+
+//running kernel threads, for kthread_should_stop
+static KTHREADS: Mutex<Vec<Arc<KThreadState>>> = Mutex::new(Vec::new());
+
+fn kthread_main(state: usize) -> isize {
+    let state = unsafe { Arc::from_raw(state as *const KThreadState) };
+    let ret = (state.func)(state.arg);
+    state.ret.store(ret, Ordering::Release);
+    KTHREADS.lock().retain(|kthread| !Arc::ptr_eq(kthread, &state));
+    state.done.complete_all();
+    ret
+}
+
+pub fn kthread_spawn(name: String, func: TaskFn, arg: usize) -> SysResult<KThread> {
+    let pid = TaskId::alloc();
+    let state = Arc::new(KThreadState {
+        pid,
+        func,
+        arg,
+        stop: AtomicBool::new(false),
+        ret: AtomicIsize::new(0),
+        done: Completion::new(),
+    });
+    let task = Task::new_kernel(name, kthread_main, Arc::into_raw(state.clone()) as usize, pid)?;
+    KTHREADS.lock().push(state.clone());
+    scheduler::add_task(task);
+    Ok(KThread { state })
+}
+
+//ask the thread to return and wait for it. A sleeping thread is woken up to see
+//kthread_should_stop, it has to check it after every wake up
+pub fn kthread_stop(kthread: KThread) -> isize {
+    kthread.state.stop.store(true, Ordering::Release);
+    scheduler::wake(kthread.state.pid);
+    kthread.join()
+}
+
+//whether kthread_stop was called for the current kernel thread
+pub fn kthread_should_stop() -> bool {
+    let pid = match scheduler::current() {
+        None => return false,
+        Some(task) => unsafe { (*task).pid() },
+    };
+    KTHREADS
+        .lock()
+        .iter()
+        .any(|kthread| kthread.pid == pid && kthread.stop.load(Ordering::Acquire))
+}
+
+//sleep for ms milliseconds, a kthread_stop ends it early
+pub fn sleep_ms(ms: u64) {
+    if kthread_should_stop() {
+        return;
+    }
+    WaitQueue::new().sleep_until((), Some(time_ms() + ms));
+}
+
+impl KThread {
+    pub fn pid(&self) -> TaskId {
+        self.state.pid
+    }
+    pub fn is_finished(&self) -> bool {
+        self.state.done.is_done()
+    }
+    //wait for the thread function to return, its return value
+    pub fn join(self) -> isize {
+        self.state.done.wait();
+        self.state.ret.load(Ordering::Acquire)
+    }
+}
@@ -1,38 +1,367 @@
-use crate::align_up;
-use crate::mm::{PAGE_SIZE, PageTable, PhyAddr, PTEFlags, VirtAddr};
-use crate::mm::heap::page_alloc;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use crate::{align_down, align_up};
+use crate::mm::{PAGE_SIZE, PageTable, PhyAddr, PTE, PTEFlags, USER_END, VirtAddr};
+use crate::mm::flush::{dsb_all, isb_all, tlb_all};
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::mm::heap::{page_alloc, page_free};
+use crate::mm::{shm, swap};
+use crate::task::elf::Elf;
+use crate::task::scheduler;
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum MapKind {
+    //anonymous, faulted in on demand and copied by fork
+    Private,
+    //anonymous, allocated up front and shared with forked children
+    Shared,
+    //attached System V segment
+    Shm(usize),
+}
+
+#[derive(Clone, Copy)]
+struct Mapping {
+    pages: usize,
+    flags: PTEFlags,
+    kind: MapKind,
+}
 
-#[repr(transparent)]
 #[derive(Clone)]
 pub struct UserSpace{
-    page: PageTable
+    page: PageTable,
+    //RLIMIT_STACK, how far the stack may grow down from USER_STACK_TOP
+    stack_limit: usize,
+    //mmap and shmat areas, keyed by start address
+    mappings: BTreeMap<usize, Mapping>,
+}
+
+//every user address space once, threads share theirs. SCHEDULER is only held to
+//collect them, f runs without it and may do swap I/O. false when one was skipped
+//because it is locked, by the caller itself in a page fault
+pub fn for_each_vm(mut f: impl FnMut(&mut UserSpace)) -> bool {
+    let mut spaces: Vec<Weak<Mutex<UserSpace>>> = Vec::new();
+    let mut complete = true;
+    scheduler::for_each_task(|task| {
+        if !task.is_user() || task.state.is_exited() {
+            return;
+        }
+        if spaces.iter().any(|vm| vm.as_ptr() == Arc::as_ptr(&task.page)) {
+            return;
+        }
+        match spaces.try_reserve(1) {
+            Ok(_) => spaces.push(Arc::downgrade(&task.page)),
+            Err(_) => complete = false,
+        }
+    });
+    //strong only while f runs, f does not switch away: a task that exited releases
+    //its address space at the next switch, as the last one holding it
+    for vm in spaces {
+        let vm = match vm.upgrade() {
+            None => continue,
+            Some(vm) => vm,
+        };
+        match vm.try_lock() {
+            Some(mut vm) => f(&mut vm),
+            None => complete = false,
+        };
+    }
+    complete
 }
 
 impl UserSpace{
-    pub const  USER_STACK_START: usize = 0x80000000;
+    pub const USER_STACK_TOP: usize = 0x80000000;
     pub const USER_START: usize = 0x00400000;
+    //pages mapped up front, the rest is faulted in on demand
     pub const USR_STACK_SIZE: usize = PAGE_SIZE * 4;
+    pub const STACK_LIMIT_DEFAULT: usize = 0x800000;
+    pub const STACK_LIMIT_MAX: usize = 0x4000000;
+    pub const MMAP_START: usize = 0x1_0000_0000;
+    pub const MMAP_END: usize = 0x100_0000_0000;
 
     pub fn empty()-> Self{
-        Self{page: PageTable::empty()}
+        Self{page: PageTable::empty(), stack_limit: 0, mappings: BTreeMap::new()}
     }
-    pub fn new()-> Self{
+    pub fn new()-> SysResult<Self>{
         let mut page = PageTable::empty();
-        page.init();
-        Self{page}
-    }
-    pub fn load_bin(&mut self, data: &[u8]) -> (usize, usize){
-        let bin_size = align_up!(data.len(), PAGE_SIZE);
-        let text_addr = page_alloc(bin_size / PAGE_SIZE);
-        text_addr.copy_from(data);
-        let text_start = VirtAddr::new(Self::USER_START);
-        self.page.map_area(text_start, text_addr.as_phy(), bin_size, PTEFlags::RWX | PTEFlags::U, true);
-        let stack_addr = page_alloc(Self::USR_STACK_SIZE / PAGE_SIZE);
-        let stack_start = VirtAddr::new(Self::USER_STACK_START);
-        self.page.map_area(stack_start, stack_addr.as_phy(), Self::USR_STACK_SIZE, PTEFlags::RW | PTEFlags::U, true);
-        (text_start.as_usize(), stack_start.as_usize() + Self::USR_STACK_SIZE)
+        page.init()?;
+        Ok(Self{page, stack_limit: Self::STACK_LIMIT_DEFAULT, mappings: BTreeMap::new()})
+    }
+    //on failure the caller releases whatever was mapped so far
+    pub fn load_elf(&mut self, data: &[u8]) -> SysResult<(usize, usize)>{
+        let elf = Elf::parse(data).ok_or(Errno::ENOEXEC)?;
+        for segment in elf.segments() {
+            let segment = segment.ok_or(Errno::ENOEXEC)?;
+            let start = align_down!(segment.vaddr, PAGE_SIZE);
+            let end = align_up!(segment.vaddr + segment.mem_size, PAGE_SIZE);
+            if start < Self::USER_START || end > Self::USER_STACK_TOP - Self::STACK_LIMIT_MAX {
+                return Err(Errno::ENOEXEC);
+            }
+            let pages = page_alloc((end - start) / PAGE_SIZE)?;
+            VirtAddr::new(pages.as_usize() + segment.vaddr - start).copy_from(segment.data);
+            if let Err(errno) = self.page.map_area(VirtAddr::new(start), pages.as_phy(), end - start, segment.flags, false) {
+                page_free(pages, (end - start) / PAGE_SIZE);
+                return Err(errno);
+            }
+        }
+        let stack_addr = page_alloc(Self::USR_STACK_SIZE / PAGE_SIZE)?;
+        let stack_start = VirtAddr::new(Self::USER_STACK_TOP - Self::USR_STACK_SIZE);
+        if let Err(errno) = self.page.map_area(stack_start, stack_addr.as_phy(), Self::USR_STACK_SIZE, PTEFlags::RW | PTEFlags::U, true) {
+            page_free(stack_addr, Self::USR_STACK_SIZE / PAGE_SIZE);
+            return Err(errno);
+        }
+        Ok((elf.entry(), Self::USER_STACK_TOP))
+    }
+    //resident and swapped out pages
+    pub fn pages(&mut self) -> usize {
+        let mut pages = 0;
+        self.page.for_each_page(|_, _| pages += 1);
+        pages
+    }
+    fn release_entry(entry: &mut PTE) {
+        if entry.is_shared() {
+            shm::put_frame(entry.as_phy_addr());
+        } else if entry.is_valid() {
+            page_free(entry.as_phy_addr().into_vaddr(), 1);
+        } else if entry.is_swap() {
+            swap::free_slot(entry);
+        }
+        entry.clear();
+    }
+    //give back every user frame, swap slot, segment attachment and page table frame
+    pub fn release(&mut self) {
+        self.page.destroy(|_, entry| Self::release_entry(entry));
+        for mapping in core::mem::take(&mut self.mappings).values() {
+            if let MapKind::Shm(id) = mapping.kind {
+                shm::detach(id);
+            }
+        }
+    }
+    //copy for a forked child: private pages are duplicated, shared frames get one more reference
+    pub fn fork(&mut self) -> SysResult<Self> {
+        let mut child = Self::new()?;
+        child.stack_limit = self.stack_limit;
+        child.mappings = self.mappings.clone();
+        for mapping in child.mappings.values() {
+            if let MapKind::Shm(id) = mapping.kind {
+                shm::attach_fork(id);
+            }
+        }
+        let mut result = Ok(());
+        self.page.for_each_page(|vaddr, entry| {
+            if result.is_ok() {
+                result = child.copy_entry(vaddr, entry);
+            }
+        });
+        dsb_all();
+        isb_all();
+        match result {
+            Ok(_) => Ok(child),
+            Err(errno) => {
+                child.release();
+                Err(errno)
+            }
+        }
+    }
+    fn copy_entry(&mut self, vaddr: VirtAddr, entry: &PTE) -> SysResult<()> {
+        if entry.is_shared() {
+            shm::get_frame(entry.as_phy_addr());
+            return self.map_shared(vaddr, entry.as_phy_addr(), entry.flags());
+        }
+        //allocating may swap the parent page out, look at the entry only afterwards
+        let page = swap::alloc_user_page()?;
+        let copied = match entry.is_valid() {
+            true => {
+                unsafe { core::ptr::copy_nonoverlapping(entry.as_phy_addr().into_vaddr().as_mut_ptr(), page.as_mut_ptr(), PAGE_SIZE) };
+                true
+            }
+            false => swap::read_slot(entry, page),
+        };
+        let flags = match entry.is_valid() {
+            true => entry.flags(),
+            false => PTEFlags::RW | PTEFlags::U,
+        };
+        match copied {
+            true => self.page.map_page(vaddr, page.as_phy(), flags, false),
+            false => Err(Errno::EIO),
+        }.map_err(|errno| {
+            page_free(page, 1);
+            errno
+        })
+    }
+    //takes over one reference of frame, dropped again on failure
+    fn map_shared(&mut self, vaddr: VirtAddr, frame: PhyAddr, flags: PTEFlags) -> SysResult<()> {
+        if let Err(errno) = self.page.map_page(vaddr, frame, flags, false) {
+            shm::put_frame(frame);
+            return Err(errno);
+        }
+        if let Some(entry) = self.page.entry(vaddr) {
+            entry.set_shared();
+        }
+        Ok(())
+    }
+    //lowest free area of pages between MMAP_START and MMAP_END
+    fn find_area(&self, pages: usize) -> SysResult<usize> {
+        let mut start = Self::MMAP_START;
+        for (&addr, mapping) in &self.mappings {
+            if addr - start >= pages * PAGE_SIZE {
+                break;
+            }
+            start = addr + mapping.pages * PAGE_SIZE;
+        }
+        match start + pages * PAGE_SIZE <= Self::MMAP_END {
+            true => Ok(start),
+            false => Err(Errno::ENOMEM),
+        }
+    }
+    //anonymous mapping, shared ones are allocated up front so that fork can share the frames
+    pub fn mmap(&mut self, len: usize, flags: PTEFlags, shared: bool) -> SysResult {
+        let pages = align_up!(len, PAGE_SIZE) / PAGE_SIZE;
+        if !shared {
+            let start = self.find_area(pages)?;
+            self.mappings.insert(start, Mapping { pages, flags, kind: MapKind::Private });
+            return Ok(start);
+        }
+        let frames = shm::alloc_frames(pages)?;
+        self.map_frames(&frames, flags, MapKind::Shared)
+    }
+    //frames of a shared mapping, each with one reference taken over by the mapping
+    pub fn map_frames(&mut self, frames: &[PhyAddr], flags: PTEFlags, kind: MapKind) -> SysResult {
+        let start = match self.find_area(frames.len()) {
+            Ok(start) => start,
+            Err(errno) => {
+                frames.iter().for_each(|frame| shm::put_frame(*frame));
+                return Err(errno);
+            }
+        };
+        self.mappings.insert(start, Mapping { pages: frames.len(), flags, kind });
+        for (i, frame) in frames.iter().enumerate() {
+            if let Err(errno) = self.map_shared(VirtAddr::new(start + i * PAGE_SIZE), *frame, flags) {
+                frames[i + 1..].iter().for_each(|frame| shm::put_frame(*frame));
+                self.munmap(start, frames.len() * PAGE_SIZE)?;
+                return Err(errno);
+            }
+        }
+        dsb_all();
+        isb_all();
+        Ok(start)
+    }
+    //whole mappings only, as created by mmap or shmat
+    pub fn munmap(&mut self, start: usize, len: usize) -> SysResult {
+        match self.mappings.get(&start) {
+            Some(mapping) if mapping.pages == align_up!(len, PAGE_SIZE) / PAGE_SIZE => {}
+            _ => return Err(Errno::EINVAL),
+        }
+        let mapping = self.mappings.remove(&start).unwrap();
+        for i in 0..mapping.pages {
+            if let Some(entry) = self.page.entry(VirtAddr::new(start + i * PAGE_SIZE)) {
+                Self::release_entry(entry);
+            }
+        }
+        dsb_all();
+        tlb_all();
+        isb_all();
+        if let MapKind::Shm(id) = mapping.kind {
+            shm::detach(id);
+        }
+        Ok(0)
+    }
+    //length of the segment attached at start
+    pub fn shm_len(&self, start: usize) -> Option<usize> {
+        self.mappings
+            .get(&start)
+            .filter(|mapping| matches!(mapping.kind, MapKind::Shm(_)))
+            .map(|mapping| mapping.pages * PAGE_SIZE)
     }
     pub const fn root_addr(&self) -> PhyAddr{
         self.page.root_addr()
     }
+    pub fn page_table(&mut self) -> &mut PageTable {
+        &mut self.page
+    }
+    //page fault on a user address: page aging, swapped out page or stack growth
+    pub fn handle_fault(&mut self, addr: usize, access_flag: bool) -> bool {
+        let page = VirtAddr::new(align_down!(addr, PAGE_SIZE));
+        if access_flag {
+            return match self.page.entry(page) {
+                Some(entry) if entry.is_valid() => {
+                    entry.set_accessed(true);
+                    true
+                }
+                _ => false,
+            };
+        }
+        swap::swap_in(&mut self.page, page) || self.grow_stack(addr) || self.fault_private(addr)
+    }
+    //user range the kernel is about to copy, faulted in up front so that a bad
+    //user pointer is an error instead of a data abort in the copy routine
+    pub fn access_ok(&mut self, addr: usize, len: usize, write: bool) -> bool {
+        match addr.checked_add(len) {
+            Some(end) if addr >= Self::USER_START && end <= USER_END => {}
+            _ => return false,
+        }
+        let mut need = PTEFlags::R | PTEFlags::U;
+        if write {
+            need |= PTEFlags::W;
+        }
+        let mut page = align_down!(addr, PAGE_SIZE);
+        while page < addr + len {
+            let present = matches!(self.page.entry(VirtAddr::new(page)), Some(entry) if entry.is_valid());
+            if !present && !self.handle_fault(page, false) {
+                return false;
+            }
+            match self.page.entry(VirtAddr::new(page)) {
+                Some(entry) if entry.is_valid() && entry.flags().contains(need) => {}
+                _ => return false,
+            }
+            page += PAGE_SIZE;
+        }
+        true
+    }
+    pub const fn stack_limit(&self) -> usize {
+        self.stack_limit
+    }
+    pub fn set_stack_limit(&mut self, limit: usize) -> bool {
+        if !(Self::USR_STACK_SIZE..=Self::STACK_LIMIT_MAX).contains(&limit) {
+            return false;
+        }
+        self.stack_limit = limit;
+        true
+    }
+    //translation fault below the stack, map one more page if it is within RLIMIT_STACK
+    pub fn grow_stack(&mut self, addr: usize) -> bool {
+        if addr >= Self::USER_STACK_TOP || addr < Self::USER_STACK_TOP - self.stack_limit {
+            return false;
+        }
+        self.map_zeroed(addr, PTEFlags::RW | PTEFlags::U)
+    }
+    //first touch of a page in a private anonymous mapping
+    fn fault_private(&mut self, addr: usize) -> bool {
+        match self.mappings.range(..=addr).next_back() {
+            Some((&start, mapping)) if mapping.kind == MapKind::Private && addr < start + mapping.pages * PAGE_SIZE => {
+                let flags = mapping.flags;
+                self.map_zeroed(addr, flags)
+            }
+            _ => false,
+        }
+    }
+    fn map_zeroed(&mut self, addr: usize, flags: PTEFlags) -> bool {
+        let page = VirtAddr::new(align_down!(addr, PAGE_SIZE));
+        if self.page.query(page, PageTable::L0).is_some() {
+            return false;
+        }
+        let frame = match swap::alloc_user_page() {
+            Ok(frame) => frame,
+            Err(_) => return false,
+        };
+        if self.page.map_page(page, frame.as_phy(), flags, false).is_err() {
+            page_free(frame, 1);
+            return false;
+        }
+        dsb_all();
+        isb_all();
+        true
+    }
 }
@@ -1,16 +1,31 @@
+use crate::common::sync::rcu;
 use crate::pr_notice;
 use crate::task::scheduler::add_task;
 use crate::task::task::Task;
+use crate::task::workqueue::SYSTEM_WQ;
 
 pub mod context;
 pub mod scheduler;
 pub mod task;
-mod mem;
+pub mod mem;
+mod elf;
+pub mod file;
+pub mod futex;
+pub mod kthread;
+pub mod pipe;
 pub mod queue;
-mod types;
+pub mod signal;
+pub mod softirq;
+pub mod sync;
+pub mod types;
+pub mod wait;
+pub mod workqueue;
 
 pub fn init(){
     pr_notice!("Init Scheduler\n");
+    softirq::init();
+    rcu::init();
+    SYSTEM_WQ.start(1).expect("system workqueue");
     let init = Task::init();
     pr_notice!("Start first user task {}\n", init.name);
     add_task(init);
@@ -0,0 +1,141 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::task::file::File;
+use crate::task::signal;
+use crate::task::wait::WaitQueue;
+
+const PIPE_SIZE: usize = 4096;
+
+struct Ring {
+    buf: Vec<u8>,
+    head: usize,
+    len: usize,
+    reader_open: bool,
+    writer_open: bool,
+}
+
+impl Ring {
+    fn pop(&mut self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.len);
+        for byte in out[..n].iter_mut() {
+            *byte = self.buf[self.head];
+            self.head = (self.head + 1) % PIPE_SIZE;
+        }
+        self.len -= n;
+        n
+    }
+    fn push(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(PIPE_SIZE - self.len);
+        for byte in &data[..n] {
+            self.buf[(self.head + self.len) % PIPE_SIZE] = *byte;
+            self.len += 1;
+        }
+        n
+    }
+}
+
+struct Pipe {
+    ring: Mutex<Ring>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+}
+
+pub struct PipeReader(Arc<Pipe>);
+pub struct PipeWriter(Arc<Pipe>);
+
+//the two ends of a new pipe, each end closes when its last descriptor goes
+pub fn pipe() -> SysResult<(Arc<dyn File>, Arc<dyn File>)> {
+    let mut buf = Vec::new();
+    buf.try_reserve_exact(PIPE_SIZE).map_err(|_| Errno::ENOMEM)?;
+    buf.resize(PIPE_SIZE, 0);
+    let pipe = Arc::new(Pipe {
+        ring: Mutex::new(Ring { buf, head: 0, len: 0, reader_open: true, writer_open: true }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    Ok((Arc::new(PipeReader(pipe.clone())), Arc::new(PipeWriter(pipe))))
+}
+
+impl File for PipeReader {
+    //blocks while the pipe is empty, 0 is end of file once the writer is closed.
+    //a signal ends the wait with EINTR
+    fn read(&self, buf: &mut [u8]) -> SysResult {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut ring = self.0.ring.lock();
+            if ring.len > 0 {
+                let n = ring.pop(buf);
+                drop(ring);
+                self.0.writable.wake_all();
+                return Ok(n);
+            }
+            if !ring.writer_open {
+                return Ok(0);
+            }
+            if signal::interrupted() {
+                return Err(Errno::EINTR);
+            }
+            self.0.readable.sleep(ring);
+        }
+    }
+    fn write(&self, _buf: &[u8]) -> SysResult {
+        Err(Errno::EBADF)
+    }
+}
+
+impl File for PipeWriter {
+    fn read(&self, _buf: &mut [u8]) -> SysResult {
+        Err(Errno::EBADF)
+    }
+    //blocks until everything is written, EPIPE and SIGPIPE once the reader is closed.
+    //a signal ends the wait with what was written so far, or EINTR
+    fn write(&self, buf: &[u8]) -> SysResult {
+        let mut written = 0;
+        loop {
+            let mut ring = self.0.ring.lock();
+            if !ring.reader_open {
+                return match written {
+                    0 => {
+                        signal::raise(signal::SIGPIPE);
+                        Err(Errno::EPIPE)
+                    }
+                    _ => Ok(written),
+                };
+            }
+            let n = ring.push(&buf[written..]);
+            written += n;
+            if n > 0 {
+                self.0.readable.wake_all();
+            }
+            if written == buf.len() {
+                return Ok(written);
+            }
+            if signal::interrupted() {
+                return match written {
+                    0 => Err(Errno::EINTR),
+                    _ => Ok(written),
+                };
+            }
+            self.0.writable.sleep(ring);
+        }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.ring.lock().reader_open = false;
+        self.0.writable.wake_all();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.ring.lock().writer_open = false;
+        self.0.readable.wake_all();
+    }
+}
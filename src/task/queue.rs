@@ -57,6 +57,34 @@ impl<T> TaskQueue<T> {
         self.tail = new_tail;
     }
 
+    //unlink the first item matching f
+    pub fn remove(&mut self, mut f: impl FnMut(&T) -> bool) -> Option<T> {
+        let mut prev: *mut Node<T> = ptr::null_mut();
+        let mut link: *mut Link<T> = &mut self.head;
+        unsafe {
+            while let Some(node) = &mut *link {
+                if f(&node.item) {
+                    let mut node = (*link).take().unwrap();
+                    *link = node.next.take();
+                    if (*link).is_none() {
+                        self.tail = prev;
+                    }
+                    self.len -= 1;
+                    return Some(node.item);
+                }
+                prev = &mut **node;
+                link = &mut node.next;
+            }
+        }
+        None
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            next: self.head.as_deref_mut(),
+        }
+    }
+
     pub fn head(&mut self) -> Option<&mut T> {
         self.head.as_mut().map(|node| &mut node.item)
     }
@@ -77,3 +105,17 @@ impl<T> TaskQueue<T> {
         self.head()
     }
 }
+
+pub struct IterMut<'a, T> {
+    next: Option<&'a mut Node<T>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next.take().map(|node| {
+            self.next = node.next.as_deref_mut();
+            &mut node.item
+        })
+    }
+}
@@ -1,11 +1,20 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use lazy_static::lazy_static;
 
 use crate::arch::reg::{DAIF, set_thread_pointer};
+use crate::arch::time_ms;
+use crate::arch::trap::in_interrupt;
 use crate::common::sync::Mutex;
+use crate::common::sync::rcu;
+#[cfg(feature = "lockdep")]
+use crate::common::sync::lockdep;
 use crate::mm::enable_table;
 use crate::task::context::{switch_context, TaskContext};
 use crate::task::queue::TaskQueue;
-use super::{task::Task, types::TaskState};
+use crate::common::errno::{Errno, SysResult};
+use super::{task::Task, types::{TaskId, TaskState}};
 
 lazy_static! {
     pub static ref SCHEDULER: Mutex<Scheduler> = {
@@ -49,9 +58,12 @@ impl Scheduler {
         self.state = State::Initialized;
     }
     unsafe fn switch(&mut self, current: *mut Task) {
+        #[cfg(feature = "lockdep")]
+        lockdep::check_switch();
         //start first task
         if !self.state.is_running() {
             self.state = State::Running;
+            RUNNING.store(true, Ordering::Release);
                 (*current).state = TaskState::Running;
                 set_thread_pointer(current.addr());
                 enable_table((*current).ctx.ttbr0_el1, false);
@@ -59,16 +71,21 @@ impl Scheduler {
         }
         //switch task
         else {
+            rcu::note_context_switch();
             match self.next() {
                 Some(next) => {
                         self.current.replace(&mut *next);
                     if next != current {
                             (*next).set_running();
-                            if !(*current).state.is_exited() {
+                            if (*current).state.is_running() {
                                 (*current).set_ready()
                             }
                             set_thread_pointer(next.addr());
                             enable_table((*next).ctx.ttbr0_el1, false);
+                            //off its page table now, the user memory of an exited task can go
+                            if (*current).state.is_exited() {
+                                (*current).release();
+                            }
                             switch_context(&mut (*current).ctx, &(*next).ctx)
 
                     }
@@ -94,6 +111,7 @@ impl Scheduler {
         match self.current() {
             None => {}
             Some(current) => unsafe {
+                (*current).clear_child_tid();
                 (*current).set_exited();
                 (*current).exit(exit_code)
             },
@@ -117,6 +135,32 @@ impl Scheduler {
         }
     }
     pub fn next(&mut self) -> Option<*mut Task> {
+        //exited threads and kernel threads are nobody's child, they go once they are off the cpu
+        let current = self.current.as_mut().map(|current| current.as_ptr());
+        while let Some(thread) = self.queue.remove(|task| {
+            (task.is_thread() || !task.is_user())
+                && task.state.is_exited()
+                && Some(task as *const Task as *mut Task) != current
+        }) {
+            drop(thread);
+        }
+        //woken from interrupt context since the last switch
+        let deferred = core::mem::take(&mut *DEFERRED_WAKE.lock());
+        for task in self.queue.iter_mut() {
+            if task.state.is_blocked() && deferred.contains(&task.pid()) {
+                task.set_ready();
+            }
+        }
+        //sleepers whose timeout expired, noticed at the next switch after the deadline
+        let now = time_ms();
+        for task in self.queue.iter_mut() {
+            if task.wake_at != 0 && task.wake_at <= now {
+                task.wake_at = 0;
+                if task.state.is_blocked() {
+                    task.set_ready();
+                }
+            }
+        }
         for _ in 0..self.queue.len {
             match self.queue.next() {
                 None => return self.idle(),
@@ -131,13 +175,11 @@ impl Scheduler {
     }
 }
 
+//the switch runs with SCHEDULER unlocked, the Scheduler itself never moves
 #[inline(always)]
 pub fn yield_current() {
-    let mut s = SCHEDULER.lock();
-    unsafe {
-        SCHEDULER.force_unlock();
-    }
-    s.get_mut().yield_current();
+    let scheduler: *mut Scheduler = &mut *SCHEDULER.lock();
+    unsafe { (*scheduler).yield_current() }
 }
 
 #[inline(always)]
@@ -154,11 +196,64 @@ pub fn current() -> Option<*mut Task> {
     }
 }
 
-#[inline(always)]
-pub fn exit_current(code: isize) -> ! {
+//Scheduler::state without SCHEDULER, for callers that may already hold it
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+//false until the first task was started
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Acquire)
+}
+
+pub fn for_each_task(mut f: impl FnMut(&mut Task)) {
+    for task in SCHEDULER.lock().queue.iter_mut() {
+        f(task)
+    }
+}
+
+//wake ups from interrupt context, the interrupted task may hold SCHEDULER
+static DEFERRED_WAKE: Mutex<Vec<TaskId>> = Mutex::new_no_irq(Vec::new());
+
+//make a blocked task runnable again, false when it was not blocked.
+//from interrupt context the task is made ready at the next switch and true is returned
+pub fn wake(pid: TaskId) -> bool {
+    if in_interrupt() {
+        DEFERRED_WAKE.lock().push(pid);
+        return true;
+    }
+    match SCHEDULER.lock().queue.iter_mut().find(|task| task.pid() == pid) {
+        Some(task) if task.state.is_blocked() => {
+            task.set_ready();
+            true
+        }
+        _ => false,
+    }
+}
+
+//remove an exited child of parent with its wait4 status, any child for pid None.
+//Ok(None) while matching children are still alive, ECHILD when there are none
+//a process is reaped once all of its threads have exited
+pub fn reap(parent: TaskId, pid: Option<TaskId>) -> SysResult<Option<(TaskId, i32)>> {
     let mut s = SCHEDULER.lock();
-    unsafe {
-        SCHEDULER.force_unlock();
+    let is_child = |task: &Task| {
+        task.ppid() == parent && !task.is_thread() && pid.map_or(true, |pid| task.pid() == pid)
+    };
+    if !s.queue.iter_mut().any(|task| is_child(task)) {
+        return Err(Errno::ECHILD);
     }
-    s.get_mut().exit_current(code);
+    let zombies: Vec<TaskId> = s.queue.iter_mut()
+        .filter(|task| is_child(task) && task.state.is_exited())
+        .map(|task| task.pid())
+        .collect();
+    let zombie = zombies.into_iter().find(|&tgid| {
+        !s.queue.iter_mut().any(|task| task.tgid == tgid && task.is_thread() && !task.state.is_exited())
+    });
+    let task = zombie.and_then(|tgid| s.queue.remove(|task| task.pid() == tgid));
+    drop(s);
+    Ok(task.map(|task| (task.pid(), task.wait_status())))
+}
+
+#[inline(always)]
+pub fn exit_current(code: isize) -> ! {
+    let scheduler: *mut Scheduler = &mut *SCHEDULER.lock();
+    unsafe { (*scheduler).exit_current(code) }
 }
@@ -0,0 +1,430 @@
+#![allow(dead_code)]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::arch::trap::context::Context;
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::devices::take_interrupt;
+use crate::mm::{UserBuffer, UserPtr};
+use crate::pr_err;
+use crate::task::scheduler;
+use crate::task::task::Task;
+use crate::task::types::TaskId;
+
+//https://man7.org/linux/man-pages/man7/signal.7.html
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGWINCH: usize = 28;
+pub const NSIG: usize = 64;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+pub const SA_RESTORER: usize = 0x0400_0000;
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+//wstatus bit of a task terminated with a core dump
+pub const WCOREFLAG: usize = 0x80;
+
+const fn bit(signo: usize) -> u64 {
+    1 << (signo - 1)
+}
+const UNBLOCKABLE: u64 = bit(SIGKILL) | bit(SIGSTOP);
+const STOP_SIGNALS: u64 = bit(SIGSTOP) | bit(SIGTSTP) | bit(SIGTTIN) | bit(SIGTTOU);
+
+//foreground process group of the console, Ctrl-C is sent to it
+static FOREGROUND: AtomicU32 = AtomicU32::new(1);
+
+//struct sigaction of the kernel abi: handler, flags, restorer, mask
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub restorer: usize,
+    pub mask: u64,
+}
+
+impl SigAction {
+    const WORDS: usize = size_of::<Self>() / size_of::<usize>();
+    const DEFAULT: Self = Self { handler: SIG_DFL, flags: 0, restorer: 0, mask: 0 };
+}
+
+enum DefaultAction {
+    Term,
+    Core,
+    Ignore,
+    Stop,
+    Cont,
+}
+
+fn default_action(signo: usize) -> DefaultAction {
+    match signo {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGCONT => DefaultAction::Cont,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV => DefaultAction::Core,
+        _ => DefaultAction::Term,
+    }
+}
+
+//pushed below the interrupted user sp, rt_sigreturn finds it at sp again
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalFrame {
+    context: Context,
+    blocked: u64,
+    _pad: u64,
+}
+
+impl SignalFrame {
+    const WORDS: usize = size_of::<Self>() / size_of::<usize>();
+}
+
+const _: () = assert!(size_of::<SignalFrame>() % 16 == 0);
+
+//per thread signal state, the actions are shared by CLONE_SIGHAND threads.
+//actions and mask survive fork, pending signals do not
+pub struct Signals {
+    pending: u64,
+    blocked: u64,
+    actions: Arc<Mutex<[SigAction; NSIG]>>,
+}
+
+impl Signals {
+    pub fn new() -> Self {
+        Self { pending: 0, blocked: 0, actions: Arc::new(Mutex::new([SigAction::DEFAULT; NSIG])) }
+    }
+    pub fn fork(&self) -> Self {
+        let actions = *self.actions.lock();
+        Self { pending: 0, blocked: self.blocked, actions: Arc::new(Mutex::new(actions)) }
+    }
+    pub fn share(&self) -> Self {
+        Self { pending: 0, blocked: self.blocked, actions: self.actions.clone() }
+    }
+    fn action(&self, signo: usize) -> SigAction {
+        self.actions.lock()[signo - 1]
+    }
+    fn ignored(&self, signo: usize) -> bool {
+        match self.action(signo).handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(default_action(signo), DefaultAction::Ignore),
+            _ => false,
+        }
+    }
+    //a pending signal that is not blocked
+    pub fn deliverable(&self) -> bool {
+        self.pending & !self.blocked != 0
+    }
+    //lowest deliverable signal, taken off the pending set
+    fn take(&mut self) -> Option<usize> {
+        let ready = self.pending & !self.blocked;
+        if ready == 0 {
+            return None;
+        }
+        let signo = ready.trailing_zeros() as usize + 1;
+        self.pending &= !bit(signo);
+        Some(signo)
+    }
+}
+
+fn valid(signo: usize) -> SysResult<()> {
+    match (1..=NSIG).contains(&signo) {
+        true => Ok(()),
+        false => Err(Errno::EINVAL),
+    }
+}
+
+//mark signo pending, delivery happens on the way back to EL0.
+//SIGCONT and SIGKILL resume a stopped task, a blocked one is woken to return EINTR
+fn post(task: &mut Task, signo: usize) {
+    if signo == SIGCONT || signo == SIGKILL {
+        task.signals.pending &= !STOP_SIGNALS;
+        if task.state.is_stopped() {
+            task.set_ready();
+        }
+    }
+    if STOP_SIGNALS & bit(signo) != 0 {
+        task.signals.pending &= !bit(SIGCONT);
+    }
+    let signals = &mut task.signals;
+    if signals.ignored(signo) && signals.blocked & bit(signo) == 0 {
+        return;
+    }
+    signals.pending |= bit(signo);
+    if signals.blocked & bit(signo) == 0 && task.state.is_blocked() {
+        task.set_ready();
+    }
+}
+
+//signo 0 only checks that a target exists
+pub fn send_where(signo: usize, mut target: impl FnMut(&Task) -> bool) -> SysResult {
+    if signo != 0 {
+        valid(signo)?;
+    }
+    let mut found = false;
+    scheduler::for_each_task(|task| {
+        if task.is_user() && !task.state.is_exited() && target(task) {
+            found = true;
+            if signo != 0 {
+                post(task, signo);
+            }
+        }
+    });
+    match found {
+        true => Ok(0),
+        false => Err(Errno::ESRCH),
+    }
+}
+
+pub fn send(pid: TaskId, signo: usize) -> SysResult {
+    send_where(signo, |task| task.pid() == pid)
+}
+
+pub fn send_group(pgid: TaskId, signo: usize) -> SysResult {
+    send_where(signo, |task| task.pgid == pgid)
+}
+
+//signal to the current task
+pub fn raise(signo: usize) {
+    if let Some(task) = scheduler::current() {
+        post(unsafe { &mut *task }, signo);
+    }
+}
+
+//kill(2): pid 0 is the group of the caller, -1 every task but init and the caller, -n group n
+pub fn kill(pid: isize, signo: usize) -> SysResult {
+    let task = scheduler::current().ok_or(Errno::ESRCH)?;
+    let (self_pid, self_pgid) = unsafe { ((*task).pid(), (*task).pgid) };
+    match pid {
+        pid if pid > 0 => send(TaskId::from(pid as u32), signo),
+        0 => send_group(self_pgid, signo),
+        -1 => send_where(signo, |task| task.pid() != self_pid && task.pid() != TaskId::from(1)),
+        pid => send_group(TaskId::from(pid.unsigned_abs() as u32), signo),
+    }
+}
+
+//a fault the task can not continue from: the handler runs if there is one,
+//a blocked or ignored signal falls back to the default action
+pub fn force(task: &mut Task, signo: usize) {
+    let signals = &mut task.signals;
+    if signals.blocked & bit(signo) != 0 || signals.action(signo).handler == SIG_IGN {
+        signals.blocked &= !bit(signo);
+        signals.actions.lock()[signo - 1].handler = SIG_DFL;
+    }
+    signals.pending |= bit(signo);
+}
+
+//SIGKILL is on its way, the task never returns to what it was doing
+pub fn killed(task: &Task) -> bool {
+    task.signals.pending & bit(SIGKILL) != 0
+}
+
+//a blocking syscall of the current task gives up with EINTR
+pub fn interrupted() -> bool {
+    match scheduler::current() {
+        None => false,
+        Some(task) => unsafe { (*task).signals.deliverable() },
+    }
+}
+
+pub fn foreground() -> TaskId {
+    TaskId::from(FOREGROUND.load(Ordering::Acquire))
+}
+
+pub fn set_foreground(pgid: TaskId) {
+    FOREGROUND.store(pgid.as_usize(), Ordering::Release)
+}
+
+//the uart irq only flags Ctrl-C, it is sent from task context:
+//on the way back to user space and from the idle task
+pub fn flush_interrupt() {
+    if take_interrupt() {
+        let _ = send_group(foreground(), SIGINT);
+    }
+}
+
+//deliver the pending signals of the current task before it returns to EL0 with context
+pub fn do_signal(context: &mut Context) {
+    flush_interrupt();
+    let task = match scheduler::current() {
+        Some(task) if unsafe { (*task).is_user() } => unsafe { &mut *task },
+        _ => return,
+    };
+    while let Some(signo) = task.signals.take() {
+        let action = task.signals.action(signo);
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(signo) {
+                DefaultAction::Ignore | DefaultAction::Cont => {}
+                DefaultAction::Stop => {
+                    task.set_stopped();
+                    scheduler::yield_current();
+                }
+                DefaultAction::Term => terminate(task, signo),
+                DefaultAction::Core => {
+                    pr_err!("{}: signal {}, core dumped\n", task, signo);
+                    pr_err!("{}\n", context);
+                    terminate(task, signo | WCOREFLAG)
+                }
+            },
+            _ => match setup_frame(task, signo, &action, context) {
+                true => return,
+                //no room for the frame on the user stack
+                false => terminate(task, SIGSEGV | WCOREFLAG),
+            },
+        }
+    }
+}
+
+//a fatal signal or exit_group takes the whole thread group down
+pub fn kill_other_threads(task: &Task) {
+    let (tgid, pid) = (task.tgid, task.pid());
+    let _ = send_where(SIGKILL, |other| other.tgid == tgid && other.pid() != pid);
+}
+
+fn terminate(task: &mut Task, status: usize) -> ! {
+    kill_other_threads(task);
+    task.term_signal = status;
+    scheduler::exit_current(0)
+}
+
+//run the handler on the user stack, x0 = signo and lr = restorer which calls rt_sigreturn
+fn setup_frame(task: &mut Task, signo: usize, action: &SigAction, context: &mut Context) -> bool {
+    let size = size_of::<SignalFrame>();
+    let sp = match context.usp.checked_sub(size) {
+        Some(sp) => sp & !0xF,
+        None => return false,
+    };
+    if !task.page.lock().access_ok(sp, size, true) {
+        return false;
+    }
+    let frame = SignalFrame { context: *context, blocked: task.signals.blocked, _pad: 0 };
+    let words = unsafe { core::slice::from_raw_parts(&frame as *const SignalFrame as *const usize, SignalFrame::WORDS) };
+    UserPtr::<usize>::new(sp, SignalFrame::WORDS).copy_from(words, SignalFrame::WORDS);
+
+    let signals = &mut task.signals;
+    signals.blocked |= action.mask & !UNBLOCKABLE;
+    if action.flags & SA_NODEFER == 0 {
+        signals.blocked |= bit(signo);
+    }
+    if action.flags & SA_RESETHAND != 0 {
+        signals.actions.lock()[signo - 1] = SigAction::DEFAULT;
+    }
+    //no siginfo or ucontext
+    context.reg[0] = signo;
+    context.reg[1] = 0;
+    context.reg[2] = 0;
+    context.lr = action.restorer;
+    context.usp = sp;
+    context.elr = action.handler;
+    true
+}
+
+//restore the context and mask saved by setup_frame, x0 of the interrupted code is returned
+pub fn sigreturn(context: &mut Context) -> SysResult {
+    let task = unsafe { &mut *scheduler::current().ok_or(Errno::ESRCH)? };
+    let sp = context.usp;
+    if sp & 0xF != 0 || !task.page.lock().access_ok(sp, size_of::<SignalFrame>(), false) {
+        force(task, SIGSEGV);
+        return Err(Errno::EFAULT);
+    }
+    let words = Vec::<usize>::copy_from_user(UserPtr::new(sp, SignalFrame::WORDS)).ok_or(Errno::ENOMEM)?;
+    let frame = unsafe { (words.as_ptr() as *const SignalFrame).read() };
+    *context = frame.context;
+    context.sanitize_spsr();
+    task.signals.blocked = frame.blocked & !UNBLOCKABLE;
+    Ok(context.reg[0])
+}
+
+//rt_sigaction, a handler needs SA_RESTORER since there is no vdso trampoline
+pub fn sigaction(signo: usize, act: usize, old_act: usize) -> SysResult {
+    valid(signo)?;
+    let task = unsafe { &mut *scheduler::current().ok_or(Errno::ESRCH)? };
+    let size = SigAction::WORDS * size_of::<usize>();
+    {
+        let mut vm = task.page.lock();
+        if (act != 0 && !vm.access_ok(act, size, false)) || (old_act != 0 && !vm.access_ok(old_act, size, true)) {
+            return Err(Errno::EFAULT);
+        }
+    }
+    let new = match act {
+        0 => None,
+        _ if signo == SIGKILL || signo == SIGSTOP => return Err(Errno::EINVAL),
+        act => {
+            let words = Vec::<usize>::copy_from_user(UserPtr::new(act, SigAction::WORDS)).ok_or(Errno::ENOMEM)?;
+            let action = SigAction { handler: words[0], flags: words[1], restorer: words[2], mask: words[3] as u64 };
+            if action.handler > SIG_IGN && (action.flags & SA_RESTORER == 0 || action.restorer == 0) {
+                return Err(Errno::EINVAL);
+            }
+            Some(action)
+        }
+    };
+    let signals = &mut task.signals;
+    if old_act != 0 {
+        let old = signals.action(signo);
+        Vec::from([old.handler, old.flags, old.restorer, old.mask as usize])
+            .copy_to_user(&mut UserPtr::new(old_act, SigAction::WORDS));
+    }
+    if let Some(action) = new {
+        signals.actions.lock()[signo - 1] = action;
+        //setting SIG_IGN discards a pending one
+        if signals.ignored(signo) {
+            signals.pending &= !bit(signo);
+        }
+    }
+    Ok(0)
+}
+
+pub fn sigprocmask(how: usize, set: usize, old_set: usize) -> SysResult {
+    let task = unsafe { &mut *scheduler::current().ok_or(Errno::ESRCH)? };
+    {
+        let mut vm = task.page.lock();
+        let size = size_of::<usize>();
+        if (set != 0 && !vm.access_ok(set, size, false)) || (old_set != 0 && !vm.access_ok(old_set, size, true)) {
+            return Err(Errno::EFAULT);
+        }
+    }
+    let signals = &mut task.signals;
+    let new = match set {
+        0 => None,
+        set => Some(Vec::<usize>::copy_from_user(UserPtr::new(set, 1)).ok_or(Errno::ENOMEM)?[0] as u64),
+    };
+    if old_set != 0 {
+        Vec::from([signals.blocked as usize]).copy_to_user(&mut UserPtr::new(old_set, 1));
+    }
+    if let Some(mask) = new {
+        signals.blocked = match how {
+            SIG_BLOCK => signals.blocked | mask,
+            SIG_UNBLOCK => signals.blocked & !mask,
+            SIG_SETMASK => mask,
+            _ => return Err(Errno::EINVAL),
+        } & !UNBLOCKABLE;
+    }
+    Ok(0)
+}
@@ -0,0 +1,117 @@
+#![allow(dead_code)]
+
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::arch::reg::DAIF;
+use crate::common::sync::Mutex;
+
+//bottom halves: a hard irq handler raises one, its handler runs at the end of
+//platform_irq with irqs enabled, or from the idle task when raised elsewhere.
+//handlers must not sleep, work that may goes to a WorkQueue
+#[derive(Copy, Clone, Debug)]
+#[repr(usize)]
+pub enum SoftIrq {
+    Timer = 0,
+    NetRx = 1,
+    Block = 2,
+    Tasklet = 3,
+    Rcu = 4,
+}
+
+const NR_SOFTIRQS: usize = 5;
+//pending ones raised while the handlers ran are picked up this many times at most,
+//the rest waits for the next irq
+const MAX_RESTART: usize = 10;
+
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+//fn() pointers, registered once at init and read from irq context
+static HANDLERS: [AtomicUsize; NR_SOFTIRQS] = [NO_HANDLER; NR_SOFTIRQS];
+static IN_SOFTIRQ: AtomicBool = AtomicBool::new(false);
+
+pub fn open_softirq(nr: SoftIrq, handler: fn()) {
+    HANDLERS[nr as usize].store(handler as usize, Ordering::Release);
+}
+
+pub fn raise_softirq(nr: SoftIrq) {
+    PENDING.fetch_or(1 << nr as usize, Ordering::AcqRel);
+}
+
+pub fn in_softirq() -> bool {
+    IN_SOFTIRQ.load(Ordering::Relaxed)
+}
+
+pub fn has_pending() -> bool {
+    PENDING.load(Ordering::Relaxed) != 0
+}
+
+//irqs stay enabled while the handlers run, a nested platform_irq only raises
+pub fn do_softirq() {
+    if IN_SOFTIRQ.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let irq_disabled = DAIF::Irq.is_disabled();
+    for _ in 0..MAX_RESTART {
+        let pending = PENDING.swap(0, Ordering::AcqRel);
+        if pending == 0 {
+            break;
+        }
+        DAIF::Irq.enable();
+        for (nr, handler) in HANDLERS.iter().enumerate() {
+            let handler = handler.load(Ordering::Acquire);
+            if pending & (1 << nr) != 0 && handler != 0 {
+                let handler: fn() = unsafe { core::mem::transmute(handler) };
+                handler();
+            }
+        }
+        DAIF::Irq.disable();
+    }
+    if !irq_disabled {
+        DAIF::Irq.enable();
+    }
+    IN_SOFTIRQ.store(false, Ordering::Release);
+}
+
+//a function run once in softirq context per schedule, a tasklet scheduled again while
+//pending still runs once
+pub struct Tasklet {
+    func: fn(usize),
+    data: usize,
+    scheduled: AtomicBool,
+}
+
+//taken in hard irq context, irqs stay off while it is held
+static TASKLETS: Mutex<VecDeque<&'static Tasklet>> = Mutex::new_no_irq(VecDeque::new());
+
+impl Tasklet {
+    pub const fn new(func: fn(usize), data: usize) -> Self {
+        Self {
+            func,
+            data,
+            scheduled: AtomicBool::new(false),
+        }
+    }
+    pub fn schedule(&'static self) {
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            TASKLETS.lock().push_back(self);
+            raise_softirq(SoftIrq::Tasklet);
+        }
+    }
+}
+
+fn tasklet_action() {
+    loop {
+        let tasklet = match TASKLETS.lock().pop_front() {
+            None => return,
+            Some(tasklet) => tasklet,
+        };
+        tasklet.scheduled.store(false, Ordering::Release);
+        (tasklet.func)(tasklet.data);
+    }
+}
+
+pub fn init() {
+    open_softirq(SoftIrq::Tasklet, tasklet_action);
+}
@@ -0,0 +1,232 @@
+#![allow(dead_code)]
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+use crate::arch::time_ms;
+use crate::common::errno::{Errno, SysResult};
+use crate::common::sync::Mutex;
+use crate::task::{scheduler, signal};
+use crate::task::wait::WaitQueue;
+
+//sleeping locks for task context: a contended lock blocks the task on a WaitQueue
+//instead of spinning, so the holder may wait for I/O. Before the scheduler runs they
+//spin, never take them from platform_irq.
+
+pub struct SleepMutex<T: ?Sized> {
+    locked: Mutex<bool>,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+pub struct SleepMutexGuard<'a, T: ?Sized> {
+    mutex: &'a SleepMutex<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for SleepMutex<T> {}
+
+unsafe impl<T: ?Sized + Send> Send for SleepMutex<T> {}
+
+impl<T> SleepMutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: Mutex::new(false),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SleepMutex<T> {
+    pub fn lock(&self) -> SleepMutexGuard<'_, T> {
+        loop {
+            let mut locked = self.locked.lock();
+            if !*locked {
+                *locked = true;
+                return SleepMutexGuard { mutex: self };
+            }
+            self.queue.sleep(locked);
+        }
+    }
+    pub fn try_lock(&self) -> Option<SleepMutexGuard<'_, T>> {
+        let mut locked = self.locked.lock();
+        if *locked {
+            return None;
+        }
+        *locked = true;
+        Some(SleepMutexGuard { mutex: self })
+    }
+    pub fn is_locked(&self) -> bool {
+        *self.locked.lock()
+    }
+    fn unlock(&self) {
+        *self.locked.lock() = false;
+        self.queue.wake(1);
+    }
+}
+
+impl<T: ?Sized> Deref for SleepMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SleepMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SleepMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock()
+    }
+}
+
+//counting semaphore
+pub struct Semaphore {
+    count: Mutex<usize>,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: Mutex::new(count),
+            queue: WaitQueue::new(),
+        }
+    }
+    pub fn down(&self) {
+        loop {
+            let mut count = self.count.lock();
+            if *count > 0 {
+                *count -= 1;
+                return;
+            }
+            self.queue.sleep(count);
+        }
+    }
+    //same, EINTR when a signal is pending instead of sleeping on
+    pub fn down_interruptible(&self) -> SysResult<()> {
+        loop {
+            let mut count = self.count.lock();
+            if *count > 0 {
+                *count -= 1;
+                return Ok(());
+            }
+            if signal::interrupted() {
+                return Err(Errno::EINTR);
+            }
+            self.queue.sleep(count);
+        }
+    }
+    pub fn try_down(&self) -> bool {
+        let mut count = self.count.lock();
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        true
+    }
+    pub fn up(&self) {
+        *self.count.lock() += 1;
+        self.queue.wake(1);
+    }
+}
+
+//used with a SleepMutex, the waiter is queued before the mutex is released
+pub struct Condvar {
+    queue: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            queue: WaitQueue::new(),
+        }
+    }
+    //unlock, sleep until notified and lock again. The caller rechecks its condition
+    pub fn wait<'a, T: ?Sized>(&self, guard: SleepMutexGuard<'a, T>) -> SleepMutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        self.queue.sleep_with(guard);
+        mutex.lock()
+    }
+    pub fn notify_one(&self) {
+        self.queue.wake(1);
+    }
+    pub fn notify_all(&self) {
+        self.queue.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//one shot event such as the end of a request. complete may be called before the
+//wait, the count remembers it, and from platform_irq
+pub struct Completion {
+    done: Mutex<usize>,
+    queue: WaitQueue,
+}
+
+impl Completion {
+    pub const fn new() -> Self {
+        Self {
+            done: Mutex::new_no_irq(0),
+            queue: WaitQueue::new(),
+        }
+    }
+    pub fn wait(&self) {
+        loop {
+            let mut done = self.done.lock();
+            if *done > 0 {
+                *done -= 1;
+                return;
+            }
+            self.queue.sleep(done);
+        }
+    }
+    //same, false once deadline (time_ms) passed without a completion
+    pub fn wait_until(&self, deadline: u64) -> bool {
+        loop {
+            let mut done = self.done.lock();
+            if *done > 0 {
+                *done -= 1;
+                return true;
+            }
+            if time_ms() >= deadline {
+                return false;
+            }
+            self.queue.sleep_until(done, Some(deadline));
+            //woken by the deadline, the entry is still queued
+            if let Some(task) = scheduler::current() {
+                self.queue.cancel(unsafe { (*task).pid() });
+            }
+        }
+    }
+    pub fn is_done(&self) -> bool {
+        *self.done.lock() > 0
+    }
+    pub fn complete(&self) {
+        *self.done.lock() += 1;
+        self.queue.wake(1);
+    }
+    //every waiter, now and later, until reinit
+    pub fn complete_all(&self) {
+        *self.done.lock() = usize::MAX / 2;
+        self.queue.wake_all();
+    }
+    pub fn reinit(&self) {
+        *self.done.lock() = 0;
+    }
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
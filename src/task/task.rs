@@ -1,25 +1,54 @@
+use alloc::boxed::Box;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use core::fmt;
 use core::fmt::{Display, Formatter};
 
-use crate::arch::reg::wfi;
-use crate::mm::{PAGE_SIZE, PhyAddr};
+use lazy_static::lazy_static;
+
+use crate::arch::reg::{user_thread_pointer, wfi};
+use crate::arch::trap::context::Context;
+use crate::common::errno::SysResult;
+use crate::common::sync::Mutex;
+use crate::mm::{PAGE_SIZE, PhyAddr, UserPtr};
 use crate::mm::flush::{dsb_all, isb_all};
 use crate::task::context::{TaskContext, TaskEntry};
+use crate::task::file::FdTable;
+use crate::task::futex;
 use crate::task::mem::UserSpace;
-use crate::task::scheduler;
+use crate::task::signal::{self, Signals};
+use crate::task::wait::CHILD_EXIT;
+use crate::task::{scheduler, softirq};
 use super::types::{KernelStack, TaskId, TaskState};
 
 pub const KERNEL_STACK_SIZE: usize= PAGE_SIZE * 4;
 pub type TaskFn = fn(usize) -> isize;
 
+//https://man7.org/linux/man-pages/man2/clone.2.html
+pub const CSIGNAL: usize = 0xff;
+pub const CLONE_VM: usize = 0x100;
+pub const CLONE_FS: usize = 0x200;
+pub const CLONE_FILES: usize = 0x400;
+pub const CLONE_SIGHAND: usize = 0x800;
+pub const CLONE_THREAD: usize = 0x10000;
+pub const CLONE_SYSVSEM: usize = 0x40000;
+pub const CLONE_SETTLS: usize = 0x80000;
+pub const CLONE_PARENT_SETTID: usize = 0x100000;
+pub const CLONE_CHILD_CLEARTID: usize = 0x200000;
+pub const CLONE_CHILD_SETTID: usize = 0x1000000;
+
+lazy_static! {
+    //kernel tasks and released ones, nothing is ever mapped or opened here
+    static ref NO_VM: Arc<Mutex<UserSpace>> = Arc::new(Mutex::new(UserSpace::empty()));
+    static ref NO_FILES: Arc<Mutex<FdTable>> = Arc::new(Mutex::new(FdTable::empty()));
+}
+
 
 #[link_section = ".rodata"]
 static BIN_INIT: &[u8] = include_bytes!(env!("INIT_BIN"));
 
 
 #[repr(C)]
-#[derive(Clone)]
 pub struct Task {
     pub name: String,
     pub state: TaskState,
@@ -28,7 +57,20 @@ pub struct Task {
     pub entry: TaskEntry,
     pub k_stack: KernelStack<KERNEL_STACK_SIZE>,
     pub pid: TaskId,
-    pub page: UserSpace,
+    pub ppid: TaskId,
+    //pid of the first thread, the one the parent waits for
+    pub tgid: TaskId,
+    //shared by the threads of a process
+    pub page: Arc<Mutex<UserSpace>>,
+    pub files: Arc<Mutex<FdTable>>,
+    pub pgid: TaskId,
+    pub signals: Signals,
+    //signal that terminated the task, WCOREFLAG or'ed in, 0 for exit()
+    pub term_signal: usize,
+    //CLONE_CHILD_CLEARTID or set_tid_address, zeroed when the thread exits
+    pub clear_child_tid: usize,
+    //time_ms at which a blocked task is woken up anyway, 0 for none
+    pub wake_at: u64,
 }
 impl Display for Task{
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -45,13 +87,17 @@ impl Display for Task{
 impl Task {
     fn idle_task(_: usize) -> isize{
         loop {
+            signal::flush_interrupt();
+            if softirq::has_pending() {
+                softirq::do_softirq();
+            }
             scheduler::yield_current();
             wfi()
         }
     }
-    pub fn new_kernel(name: String,entry: TaskFn, arg: usize, id: TaskId) -> Self {
-        let stack = KernelStack::new();
-        Task {
+    pub fn new_kernel(name: String,entry: TaskFn, arg: usize, id: TaskId) -> SysResult<Self> {
+        let stack = KernelStack::new()?;
+        Ok(Task {
             name,
             state: TaskState::Ready,
             ctx: TaskContext::new(stack.top(), PhyAddr::new(0)),
@@ -59,17 +105,34 @@ impl Task {
             entry: TaskEntry::new_kernel(entry as usize, arg),
             k_stack: stack,
             pid: id,
-            page: UserSpace::empty(),
-        }
+            ppid: TaskId::IDLE_TASK_ID,
+            tgid: id,
+            page: NO_VM.clone(),
+            files: NO_FILES.clone(),
+            pgid: id,
+            signals: Signals::new(),
+            term_signal: 0,
+            clear_child_tid: 0,
+            wake_at: 0,
+        })
     }
     pub fn idle() -> Self {
-        Self::new_kernel("idle".to_string(),Self::idle_task, 0, TaskId::IDLE_TASK_ID)
+        Self::new_kernel("idle".to_string(),Self::idle_task, 0, TaskId::IDLE_TASK_ID).expect("idle task")
     }
-    pub fn new_user(name: String, data: &[u8]) -> Self {
-        let mut vm = UserSpace::new();
-        let (entry, stack_top) = vm.load_bin(data);
+    //ENOEXEC for an invalid elf or a W^X violating segment, ENOMEM
+    pub fn new_user(name: String, data: &[u8]) -> SysResult<Self> {
+        let mut vm = UserSpace::new()?;
+        let (entry, stack_top, k_stack) = match vm.load_elf(data).and_then(|(entry, stack_top)| {
+            Ok((entry, stack_top, KernelStack::new()?))
+        }) {
+            Ok(loaded) => loaded,
+            Err(errno) => {
+                vm.release();
+                return Err(errno);
+            }
+        };
         let page_table_root = vm.root_addr();
-        let k_stack =  KernelStack::new();
+        let pid = TaskId::alloc();
         let t = Task{
             name,
             state: TaskState::Ready,
@@ -77,22 +140,97 @@ impl Task {
             exit_code: 0,
             entry: TaskEntry::new_user(entry, stack_top),
             k_stack,
-            pid: TaskId::alloc(),
-            page: vm,
+            pid,
+            ppid: TaskId::IDLE_TASK_ID,
+            tgid: pid,
+            page: Arc::new(Mutex::new(vm)),
+            files: Arc::new(Mutex::new(FdTable::console())),
+            pgid: pid,
+            signals: Signals::new(),
+            term_signal: 0,
+            clear_child_tid: 0,
+            wake_at: 0,
         };
         isb_all();
         dsb_all();
-        t
+        Ok(t)
+    }
+    //child of a user task returning 0 from the same syscall on stack, or on the
+    //same stack for 0. Memory, descriptors and signal actions are shared or copied
+    //according to flags, a CLONE_THREAD child joins the thread group of self
+    pub fn clone_task(&mut self, context: &Context, flags: usize, stack: usize, tls: usize) -> SysResult<Self> {
+        let page = match flags & CLONE_VM {
+            0 => Arc::new(Mutex::new(self.page.lock().fork()?)),
+            _ => self.page.clone(),
+        };
+        let k_stack = match KernelStack::new() {
+            Ok(k_stack) => k_stack,
+            Err(errno) => {
+                if flags & CLONE_VM == 0 {
+                    page.lock().release();
+                }
+                return Err(errno);
+            }
+        };
+        let files = match flags & CLONE_FILES {
+            0 => Arc::new(Mutex::new(self.files.lock().clone())),
+            _ => self.files.clone(),
+        };
+        let signals = match flags & CLONE_SIGHAND {
+            0 => self.signals.fork(),
+            _ => self.signals.share(),
+        };
+        let mut context = *context;
+        context.reg[0] = 0;
+        if stack != 0 {
+            context.usp = stack;
+        }
+        let mut ctx = TaskContext::new(k_stack.top(), page.lock().root_addr());
+        ctx.tpidr_el0 = match flags & CLONE_SETTLS {
+            0 => user_thread_pointer(),
+            _ => tls,
+        };
+        let pid = TaskId::alloc();
+        let (tgid, ppid) = match flags & CLONE_THREAD {
+            0 => (pid, self.tgid),
+            _ => (self.tgid, self.ppid),
+        };
+        Ok(Task {
+            name: self.name.clone(),
+            state: TaskState::Ready,
+            ctx,
+            exit_code: 0,
+            entry: TaskEntry::User(Box::new(context)),
+            k_stack,
+            pid,
+            ppid,
+            tgid,
+            page,
+            files,
+            pgid: self.pgid,
+            signals,
+            term_signal: 0,
+            clear_child_tid: 0,
+            wake_at: 0,
+        })
     }
     #[inline(always)]
     pub fn init() -> Self {
         Self::new_user("init".to_string() ,BIN_INIT)
+            .unwrap_or_else(|errno| panic!("init: {:?}", errno))
     }
 
     #[allow(dead_code)]
     pub fn pid(&self) -> TaskId {
         self.pid
     }
+    pub fn ppid(&self) -> TaskId {
+        self.ppid
+    }
+    //a thread other than the first of its group, nobody waits for it
+    pub fn is_thread(&self) -> bool {
+        self.pid != self.tgid
+    }
 
     #[inline(always)]
     pub fn set_ready(&mut self){
@@ -103,12 +241,51 @@ impl Task {
         self.state = TaskState::Running
     }
     #[inline(always)]
+    pub fn set_blocked(&mut self){
+        self.state = TaskState::Blocked
+    }
+    #[inline(always)]
+    pub fn set_stopped(&mut self){
+        self.state = TaskState::Stopped
+    }
+    #[inline(always)]
     pub fn set_exited(&mut self){
         self.state = TaskState::Exited
     }
     pub fn exit(&mut self, code: isize){
         self.exit_code = code
     }
+    //memory and descriptors of an exited task, the rest goes when the parent reaps it.
+    //shared ones are released by the last thread using them
+    pub fn release(&mut self) {
+        let vm = core::mem::replace(&mut self.page, NO_VM.clone());
+        if let Ok(vm) = Arc::try_unwrap(vm) {
+            vm.into_inner().release();
+        }
+        self.files = NO_FILES.clone();
+        CHILD_EXIT.wake_all();
+    }
+    //CLONE_CHILD_CLEARTID: a thread joining this one futex waits for the word to become 0
+    pub fn clear_child_tid(&mut self) {
+        let addr = core::mem::take(&mut self.clear_child_tid);
+        if addr == 0 || Arc::strong_count(&self.page) == 1 {
+            return;
+        }
+        if self.page.lock().access_ok(addr, 4, true) {
+            UserPtr::<u32>::new(addr, 1).copy_from(&[0], 1);
+            let _ = futex::wake(addr, 1, false);
+        }
+    }
+    //wait4 status: the exit code in bits 8..15 or the terminating signal in the low bits
+    pub fn wait_status(&self) -> i32 {
+        match self.term_signal {
+            0 => ((self.exit_code as i32) & 0xff) << 8,
+            status => status as i32,
+        }
+    }
+    pub fn is_user(&self) -> bool {
+        matches!(self.entry, TaskEntry::User(_))
+    }
 
     pub fn as_ptr(&mut self) -> *mut Self {
         &mut *self
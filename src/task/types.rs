@@ -1,22 +1,61 @@
 #![allow(dead_code)]
-use alloc::boxed::Box;
-use alloc::vec;
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU32, Ordering};
 
-#[repr(transparent)]
-#[derive(Clone)]
+use crate::common::errno::SysResult;
+use crate::common::sync::Mutex;
+use crate::mm::{KERNEL_STACK_AREA, map_kernel, PAGE_SIZE, PTEFlags, unmap_kernel, VirtAddr};
+use crate::mm::heap::{page_alloc, page_free};
 
-pub struct KernelStack<const N: usize>(Box<[u8]>);
+static STACK_SLOTS: Mutex<(usize, Vec<usize>)> = Mutex::new((0, Vec::new()));
+
+//every stack sits at the bottom of a 2 * N slot aligned to 2 * N,
+//the unmapped upper half is the guard page of the next slot.
+//so bit log2(N) of sp is set only after an overflow, see trap.S
+#[repr(C)]
+pub struct KernelStack<const N: usize> {
+    slot: usize,
+    frames: VirtAddr,
+}
 
 impl<const N: usize> KernelStack<N> {
-    pub fn new() -> Self {
-        Self(Box::from(vec![0u8; N]))
+    const SLOT_SIZE: usize = N * 2;
+    pub fn new() -> SysResult<Self> {
+        assert!(N.is_power_of_two() && N >= PAGE_SIZE);
+        let mut slots = STACK_SLOTS.lock();
+        let slot = match slots.1.pop() {
+            Some(slot) => slot,
+            None => {
+                slots.0 += 1;
+                slots.0 - 1
+            }
+        };
+        drop(slots);
+        let frames = match page_alloc(N / PAGE_SIZE) {
+            Ok(frames) => frames,
+            Err(errno) => {
+                STACK_SLOTS.lock().1.push(slot);
+                return Err(errno);
+            }
+        };
+        //from here on Drop gives back the frames and the slot
+        let stack = Self { slot, frames };
+        map_kernel(VirtAddr::new(stack.bottom()), frames.as_phy(), N, PTEFlags::RW)?;
+        Ok(stack)
     }
     pub fn top(&self) -> usize {
-        self.0.as_ptr_range().end.addr()
+        self.bottom() + N
     }
     pub fn bottom(&self) -> usize{
-        self.0.as_ptr_range().start.addr()
+        KERNEL_STACK_AREA + self.slot * Self::SLOT_SIZE
+    }
+}
+
+impl<const N: usize> Drop for KernelStack<N> {
+    fn drop(&mut self) {
+        unmap_kernel(VirtAddr::new(self.bottom()), N);
+        page_free(self.frames, N / PAGE_SIZE);
+        STACK_SLOTS.lock().1.push(self.slot);
     }
 }
 
@@ -45,6 +84,10 @@ pub enum TaskState {
     Ready = 1,
     Running = 2,
     Exited = 3,
+    //sleeping on a WaitQueue
+    Blocked = 4,
+    //SIGSTOP and friends, runnable again on SIGCONT or SIGKILL
+    Stopped = 5,
 }
 impl TaskState{
     #[inline]
@@ -62,6 +105,14 @@ impl TaskState{
         }
     }
     #[inline]
+    pub const fn is_blocked(&self) -> bool{
+        matches!(self, TaskState::Blocked)
+    }
+    #[inline]
+    pub const fn is_stopped(&self) -> bool{
+        matches!(self, TaskState::Stopped)
+    }
+    #[inline]
     pub const fn is_exited(&self) -> bool{
         match self {
             TaskState::Exited => true,
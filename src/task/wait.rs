@@ -0,0 +1,83 @@
+use alloc::vec::Vec;
+
+use crate::common::sync::{Mutex, MutexGuard};
+use crate::task::scheduler;
+use crate::task::types::TaskId;
+
+//woken whenever a task exits, wait4 sleeps here
+pub static CHILD_EXIT: WaitQueue = WaitQueue::new();
+
+//tasks are kept by pid, a reaped task simply is not found on wake up.
+//wake may be called from a softirq or platform_irq, so the waiters lock keeps irqs off
+pub struct WaitQueue {
+    waiters: Mutex<Vec<TaskId>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new_no_irq(Vec::new()),
+        }
+    }
+    //block the current task and release guard, the caller rechecks its condition
+    //after waking up. Blocked is set under guard, a wake up in between is not lost
+    pub fn sleep<T>(&self, guard: MutexGuard<'_, T>) {
+        self.sleep_with(guard)
+    }
+    //same, for a condition that is not protected by a Mutex
+    pub fn sleep_with<G>(&self, guard: G) {
+        self.sleep_until(guard, None)
+    }
+    //woken up at deadline (time_ms) at the latest. A waiter that may wake up for
+    //another reason than a wake up of the queue cancels its entry afterwards
+    pub fn sleep_until<G>(&self, guard: G, deadline: Option<u64>) {
+        let task = match scheduler::current() {
+            None => return,
+            Some(task) => task,
+        };
+        let pid = unsafe {
+            (*task).set_blocked();
+            (*task).wake_at = deadline.unwrap_or(0);
+            (*task).pid()
+        };
+        self.waiters.lock().push(pid);
+        drop(guard);
+        scheduler::yield_current();
+        unsafe { (*task).wake_at = 0 };
+    }
+    //take pid off the queue, false when a wake up already did
+    pub fn cancel(&self, pid: TaskId) -> bool {
+        let mut waiters = self.waiters.lock();
+        match waiters.iter().position(|waiter| *waiter == pid) {
+            Some(index) => {
+                waiters.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+    //the first n waiters in the order they went to sleep, returns how many were woken.
+    //entries of tasks that already woke up for another reason do not count
+    pub fn wake(&self, n: usize) -> usize {
+        let mut woken = 0;
+        while woken < n {
+            let pid = match self.waiters.lock() {
+                mut waiters if !waiters.is_empty() => waiters.remove(0),
+                _ => break,
+            };
+            if scheduler::wake(pid) {
+                woken += 1;
+            }
+        }
+        woken
+    }
+    pub fn wake_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for pid in waiters {
+            scheduler::wake(pid);
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
@@ -0,0 +1,81 @@
+#![allow(dead_code)]
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+
+use crate::common::errno::SysResult;
+use crate::common::sync::Mutex;
+use crate::task::kthread::kthread_spawn;
+use crate::task::wait::WaitQueue;
+
+//work that may sleep, run in task context by kernel worker threads. queue may be
+//called from a softirq or platform_irq, the work itself runs later
+pub type Work = Box<dyn FnOnce() + Send>;
+
+pub struct WorkQueue {
+    name: &'static str,
+    //queued and running works, flush waits for 0
+    works: Mutex<(VecDeque<Work>, usize)>,
+    more: WaitQueue,
+    idle: WaitQueue,
+}
+
+//shared by drivers, for short works that do not block it for long
+pub static SYSTEM_WQ: WorkQueue = WorkQueue::new("events");
+
+impl WorkQueue {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            works: Mutex::new_no_irq((VecDeque::new(), 0)),
+            more: WaitQueue::new(),
+            idle: WaitQueue::new(),
+        }
+    }
+    //spawn the worker threads, named like events/0
+    pub fn start(&'static self, workers: usize) -> SysResult<()> {
+        for n in 0..workers {
+            kthread_spawn(format!("{}/{}", self.name, n), Self::worker, self as *const Self as usize)?;
+        }
+        Ok(())
+    }
+    pub fn queue(&self, work: impl FnOnce() + Send + 'static) {
+        let mut works = self.works.lock();
+        works.0.push_back(Box::new(work));
+        works.1 += 1;
+        drop(works);
+        self.more.wake(1);
+    }
+    //wait until everything queued so far has run, from task context only
+    pub fn flush(&self) {
+        loop {
+            let works = self.works.lock();
+            if works.1 == 0 {
+                return;
+            }
+            self.idle.sleep(works);
+        }
+    }
+    fn worker(wq: usize) -> isize {
+        let wq = unsafe { &*(wq as *const Self) };
+        loop {
+            let mut works = wq.works.lock();
+            let work = match works.0.pop_front() {
+                Some(work) => work,
+                None => {
+                    wq.more.sleep(works);
+                    continue;
+                }
+            };
+            drop(works);
+            work();
+            let mut works = wq.works.lock();
+            works.1 -= 1;
+            if works.1 == 0 {
+                drop(works);
+                wq.idle.wake_all();
+            }
+        }
+    }
+}
@@ -3,9 +3,27 @@
 
 extern crate std;
 
-use std::{pr_notice, shutdown, reboot, sleep_ms, read_line};
+use std::{pr_notice, print, shutdown, reboot, sleep_ms, read_line};
+use std::{close, dup2, exit, fork, pipe, read, waitpid, write};
+use std::{getpgid, kill, setpgid, signal, tcsetpgrp, SIGINT, SIGTERM, SIG_DFL, SIG_IGN};
+use std::{wcoredump, wifsignaled, wtermsig};
+use std::net::{SocketAddrV4, TcpListener, UdpSocket};
+use std::sync::Mutex;
+use std::thread;
 use arrayvec::ArrayString;
 
+const STDIN: usize = 0;
+const STDOUT: usize = 1;
+const MAX_STAGES: usize = 8;
+const THREADS: usize = 4;
+const SUM_RANGE: usize = 1 << 20;
+//forwarded from the host ports 5555 and 5556 by make run
+const ECHO_TCP_PORT: u16 = 80;
+const ECHO_UDP_PORT: u16 = 7;
+
+//the threads builtin also adds the parts up here
+static TOTAL: Mutex<usize> = Mutex::new(0);
+
 #[no_mangle]
 pub fn main() -> isize {
     run_loop();
@@ -13,6 +31,8 @@ pub fn main() -> isize {
 }
 pub fn run_loop() {
     pr_notice!("User shell started !!!\n");
+    //Ctrl-C is for the foreground pipeline, not the shell
+    signal(SIGINT, SIG_IGN);
     let mut line =  ArrayString::new();
     loop {
         pr_notice!("#>>");
@@ -34,12 +54,204 @@ pub fn run_loop() {
                 pr_notice!("\nreboot!\n");
                 reboot()
             }
-            "help" | _=> {
-                pr_notice!("\ncommand: \n\texit shutdown reboot help.\n");
-            }
+            cmd => pipeline(cmd),
         }
         sleep_ms(20);
 
     }
 
 }
+
+//commands that also run as a pipeline stage, reading stdin and writing stdout
+fn builtin(cmd: &str) -> isize {
+    let (name, args) = cmd.split_once(' ').unwrap_or((cmd, ""));
+    match name {
+        "echo" => {
+            print!("{}\n", args);
+            0
+        }
+        "cat" => {
+            let mut buf = [0u8; 64];
+            loop {
+                match read(STDIN, &mut buf) {
+                    n if n <= 0 => return n,
+                    n => {
+                        write(STDOUT, &buf[..n as usize]);
+                    }
+                }
+            }
+        }
+        "yes" => {
+            loop {
+                if write(STDOUT, b"y\n") < 0 {
+                    return 1;
+                }
+            }
+        }
+        "kill" => {
+            let (signo, pid) = match args.split_once(' ') {
+                Some((signo, pid)) if signo.starts_with('-') => (signo[1..].parse().ok(), pid),
+                _ => (Some(SIGTERM), args),
+            };
+            match (signo, pid.trim().parse::<isize>()) {
+                (Some(signo), Ok(pid)) if kill(pid, signo) == 0 => 0,
+                _ => {
+                    pr_notice!("kill: [-signo] pid\n");
+                    1
+                }
+            }
+        }
+        "threads" => {
+            //1 + .. + SUM_RANGE split over THREADS threads
+            let mut handles: [Option<thread::JoinHandle>; THREADS] = Default::default();
+            for (i, handle) in handles.iter_mut().enumerate() {
+                *handle = thread::spawn(partial_sum, i);
+            }
+            *TOTAL.lock() = 0;
+            let mut sum = 0;
+            for handle in handles.into_iter().flatten() {
+                sum += handle.join() as usize;
+            }
+            print!("sum {} locked total {}\n", sum, *TOTAL.lock());
+            0
+        }
+        "wc" => {
+            let mut buf = [0u8; 64];
+            let (mut lines, mut bytes) = (0, 0);
+            loop {
+                match read(STDIN, &mut buf) {
+                    n if n <= 0 => break,
+                    n => {
+                        bytes += n as usize;
+                        lines += buf[..n as usize].iter().filter(|c| **c == b'\n').count();
+                    }
+                }
+            }
+            print!("{} {}\n", lines, bytes);
+            0
+        }
+        "echod" => match args.trim() {
+            "udp" => echo_udp(),
+            _ => echo_tcp(),
+        },
+        "help" | _ => {
+            pr_notice!("\ncommand: \n\texit shutdown reboot help echo cat wc yes kill threads echod [udp], a | b pipelines.\n\tCtrl-C interrupts the running command.\n");
+            0
+        }
+    }
+}
+
+//one connection at a time, until Ctrl-C
+fn echo_tcp() -> isize {
+    let listener = match TcpListener::bind(SocketAddrV4::new([0; 4], ECHO_TCP_PORT)) {
+        Ok(listener) => listener,
+        Err(err) => return err,
+    };
+    let mut buf = [0u8; 512];
+    loop {
+        let (stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(err) => return err,
+        };
+        print!("echod: {} connected\n", peer);
+        while let Ok(n) = stream.read(&mut buf) {
+            if n == 0 || stream.write_all(&buf[..n]).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+fn echo_udp() -> isize {
+    let socket = match UdpSocket::bind(SocketAddrV4::new([0; 4], ECHO_UDP_PORT)) {
+        Ok(socket) => socket,
+        Err(err) => return err,
+    };
+    let mut buf = [0u8; 512];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((n, peer)) => {
+                socket.send_to(&buf[..n], peer).ok();
+            }
+            Err(err) => return err,
+        }
+    }
+}
+
+fn partial_sum(part: usize) -> isize {
+    let len = SUM_RANGE / THREADS;
+    let sum = (part * len + 1..=(part + 1) * len).sum::<usize>();
+    *TOTAL.lock() += sum;
+    sum as isize
+}
+
+//a | b | ...: one child per stage, each stdout connected to the next stdin.
+//the stages form a process group that is in the foreground until they exit
+fn pipeline(line: &str) {
+    pr_notice!("\n");
+    let mut children = 0;
+    let mut pgid = 0;
+    let mut input = None;
+    let mut stages = line.split('|').map(|stage| stage.trim()).take(MAX_STAGES).peekable();
+    while let Some(stage) = stages.next() {
+        let output = match stages.peek() {
+            None => None,
+            Some(_) => match pipe() {
+                None => {
+                    pr_notice!("pipe failed\n");
+                    break
+                }
+                Some(fds) => Some(fds),
+            },
+        };
+        match fork() {
+            0 => {
+                setpgid(0, pgid);
+                signal(SIGINT, SIG_DFL);
+                if let Some(fd) = input {
+                    dup2(fd, STDIN);
+                    close(fd);
+                }
+                if let Some((read_end, write_end)) = output {
+                    dup2(write_end, STDOUT);
+                    close(read_end);
+                    close(write_end);
+                }
+                exit(builtin(stage))
+            }
+            pid if pid < 0 => pr_notice!("fork failed\n"),
+            pid => {
+                if pgid == 0 {
+                    pgid = pid as usize;
+                }
+                setpgid(pid as usize, pgid);
+                children += 1
+            }
+        }
+        //the children hold their own copies, so readers see EOF once the writer stage exits
+        if let Some(fd) = input.take() {
+            close(fd);
+        }
+        if let Some((read_end, write_end)) = output {
+            close(write_end);
+            input = Some(read_end);
+        }
+    }
+    if let Some(fd) = input {
+        close(fd);
+    }
+    if pgid != 0 {
+        tcsetpgrp(STDIN, pgid);
+    }
+    for _ in 0..children {
+        if let Some((pid, status)) = waitpid(-1, 0) {
+            if wifsignaled(status) {
+                pr_notice!("[{}] signal {}{}\n", pid, wtermsig(status), match wcoredump(status) {
+                    true => " (core dumped)",
+                    false => "",
+                });
+            }
+        }
+    }
+    tcsetpgrp(STDIN, getpgid(0) as usize);
+}
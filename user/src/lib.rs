@@ -4,21 +4,29 @@
 #![feature(strict_provenance)]
 #![feature(stdsimd)]
 
+use core::arch::global_asm;
 use core::panic::PanicInfo;
 use arrayvec::ArrayString;
 
-use syscall::{sys_exit, sys_read, sys_shutdown, sys_write, sys_reboot};
+use syscall::{sys_exit_group, sys_getpid, sys_getppid, sys_sched_yield};
+use syscall::{sys_read, sys_shutdown, sys_write, sys_reboot, sys_getrlimit, sys_setrlimit, sys_swapon, sys_swapoff};
+use syscall::{sys_clone, sys_mmap, sys_munmap, sys_shmget, sys_shmctl, sys_shmat, sys_shmdt};
+use syscall::{sys_dup, sys_dup3, sys_close, sys_pipe2, sys_wait4};
+use syscall::{sys_ioctl, sys_kill, sys_rt_sigaction, sys_rt_sigprocmask, sys_setpgid, sys_getpgid};
 
 pub const CLOCK_FREQ:u64 =  0x3b9aca0;
 pub const MS_PEER_CYCLE: u64 = CLOCK_FREQ / 1000;
+pub mod net;
 pub mod syscall;
+pub mod sync;
+pub mod thread;
 #[macro_use]
 pub mod stdio;
 
 #[no_mangle]
 #[link_section = ".text._start"]
 pub extern "C" fn _start() -> ! {
-    sys_exit(main())
+    sys_exit_group(main())
 }
 
 #[linkage = "weak"]
@@ -44,6 +52,247 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
     sys_read(fd, buf)
 }
 
+pub const RLIMIT_STACK: usize = 3;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Rlimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+pub fn getrlimit(resource: usize) -> Option<Rlimit> {
+    let mut limit = [0usize; 2];
+    match sys_getrlimit(resource, &mut limit) {
+        0 => Some(Rlimit { cur: limit[0], max: limit[1] }),
+        _ => None
+    }
+}
+
+pub fn setrlimit(resource: usize, limit: Rlimit) -> isize {
+    sys_setrlimit(resource, &[limit.cur, limit.max])
+}
+
+//partition: MBR partition index 1..=4 of type 0x82 on the block device
+pub fn swapon(partition: usize) -> isize {
+    sys_swapon(partition)
+}
+pub fn swapoff(partition: usize) -> isize {
+    sys_swapoff(partition)
+}
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+const SA_RESTORER: usize = 0x0400_0000;
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+//handlers return here, rt_sigreturn restores the interrupted context
+global_asm!(
+    ".global __restore_rt",
+    "__restore_rt:",
+    "mov x8, #139",
+    "svc #0",
+);
+extern "C" {
+    fn __restore_rt();
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub restorer: usize,
+    pub mask: u64,
+}
+
+impl SigAction {
+    //handler is SIG_DFL, SIG_IGN or an extern "C" fn(usize)
+    pub fn new(handler: usize, flags: usize, mask: u64) -> Self {
+        Self { handler, flags, restorer: 0, mask }
+    }
+}
+
+pub const fn sigmask(signo: usize) -> u64 {
+    1 << (signo - 1)
+}
+
+//the previous action, None for an invalid signal
+pub fn sigaction(signo: usize, action: &SigAction) -> Option<SigAction> {
+    let mut act = *action;
+    if act.handler > SIG_IGN {
+        act.flags |= SA_RESTORER;
+        act.restorer = __restore_rt as usize;
+    }
+    let mut old = SigAction::new(SIG_DFL, 0, 0);
+    match sys_rt_sigaction(signo, (&act as *const SigAction).addr(), (&mut old as *mut SigAction).addr()) {
+        0 => Some(old),
+        _ => None,
+    }
+}
+pub fn signal(signo: usize, handler: usize) -> Option<usize> {
+    sigaction(signo, &SigAction::new(handler, 0, 0)).map(|old| old.handler)
+}
+//the previous mask
+pub fn sigprocmask(how: usize, mask: u64) -> Option<u64> {
+    let mut old = 0u64;
+    match sys_rt_sigprocmask(how, (&mask as *const u64).addr(), (&mut old as *mut u64).addr()) {
+        0 => Some(old),
+        _ => None,
+    }
+}
+//pid 0 is the caller's group, -1 every other task, -pgid a group
+pub fn kill(pid: isize, signo: usize) -> isize {
+    sys_kill(pid, signo)
+}
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+    sys_setpgid(pid, pgid)
+}
+pub fn getpgid(pid: usize) -> isize {
+    sys_getpgid(pid)
+}
+
+const TIOCGPGRP: usize = 0x540F;
+const TIOCSPGRP: usize = 0x5410;
+
+//foreground process group of the console, Ctrl-C sends SIGINT to it
+pub fn tcgetpgrp(fd: usize) -> isize {
+    let mut pgid = 0i32;
+    match sys_ioctl(fd, TIOCGPGRP, (&mut pgid as *mut i32).addr()) {
+        0 => pgid as isize,
+        err => err,
+    }
+}
+pub fn tcsetpgrp(fd: usize, pgid: usize) -> isize {
+    let pgid = pgid as i32;
+    sys_ioctl(fd, TIOCSPGRP, (&pgid as *const i32).addr())
+}
+
+//0 in the child, the child pid in the parent
+pub fn fork() -> isize {
+    sys_clone(SIGCHLD)
+}
+
+pub const WNOHANG: usize = 1;
+
+//pid -1 for any child, Some((pid, wait status)) once one has exited
+pub fn waitpid(pid: isize, options: usize) -> Option<(isize, i32)> {
+    let mut status = 0i32;
+    match sys_wait4(pid, &mut status, options) {
+        child if child > 0 => Some((child, status)),
+        _ => None,
+    }
+}
+pub fn wifexited(status: i32) -> bool {
+    status & 0x7f == 0
+}
+pub fn wexitstatus(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+pub fn wifsignaled(status: i32) -> bool {
+    status & 0x7f != 0
+}
+pub fn wtermsig(status: i32) -> usize {
+    (status & 0x7f) as usize
+}
+pub fn wcoredump(status: i32) -> bool {
+    status & 0x80 != 0
+}
+
+//read end first
+pub fn pipe() -> Option<(usize, usize)> {
+    let mut fds = [0i32; 2];
+    match sys_pipe2(&mut fds, 0) {
+        0 => Some((fds[0] as usize, fds[1] as usize)),
+        _ => None,
+    }
+}
+pub fn close(fd: usize) -> isize {
+    sys_close(fd)
+}
+pub fn dup(fd: usize) -> isize {
+    sys_dup(fd)
+}
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    match old_fd == new_fd {
+        true => new_fd as isize,
+        false => sys_dup3(old_fd, new_fd, 0),
+    }
+}
+
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+//anonymous mappings only, MAP_SHARED ones stay shared with forked children
+pub fn mmap(len: usize, prot: usize, flags: usize) -> Option<&'static mut [u8]> {
+    match sys_mmap(0, len, prot, flags | MAP_ANONYMOUS) {
+        addr if addr < 0 => None,
+        addr => Some(unsafe { core::slice::from_raw_parts_mut(addr as usize as *mut u8, len) }),
+    }
+}
+pub fn munmap(buf: &mut [u8]) -> isize {
+    sys_munmap(buf.as_mut_ptr().addr(), buf.len())
+}
+
+pub const IPC_PRIVATE: usize = 0;
+pub const IPC_CREAT: usize = 0o1000;
+pub const IPC_EXCL: usize = 0o2000;
+pub const IPC_RMID: usize = 0;
+pub const SHM_RDONLY: usize = 0o10000;
+
+pub fn shmget(key: usize, size: usize, flags: usize) -> isize {
+    sys_shmget(key, size, flags)
+}
+pub fn shmat(id: usize, size: usize, flags: usize) -> Option<&'static mut [u8]> {
+    match sys_shmat(id, flags) {
+        addr if addr < 0 => None,
+        addr => Some(unsafe { core::slice::from_raw_parts_mut(addr as usize as *mut u8, size) }),
+    }
+}
+pub fn shmdt(buf: &mut [u8]) -> isize {
+    sys_shmdt(buf.as_mut_ptr().addr())
+}
+pub fn shmctl(id: usize, cmd: usize) -> isize {
+    sys_shmctl(id, cmd)
+}
+
+//all threads of the process
+pub fn exit(code: isize) -> ! {
+    sys_exit_group(code)
+}
+pub fn getpid() -> usize {
+    sys_getpid() as usize
+}
+pub fn getppid() -> usize {
+    sys_getppid() as usize
+}
+pub fn sched_yield() {
+    sys_sched_yield();
+}
 pub fn shutdown() ->!{
     sys_shutdown();
     loop {}
@@ -0,0 +1,266 @@
+use core::fmt::{Display, Formatter};
+
+use crate::close;
+use crate::syscall::{sys_accept, sys_bind, sys_connect, sys_getsockopt, sys_listen, sys_read, sys_recvfrom};
+use crate::syscall::{sys_sendto, sys_setsockopt, sys_socket, sys_socket_shutdown, sys_write};
+
+//sockets that close when they are dropped. Err is the negative errno of the call
+
+pub const AF_UNIX: usize = 1;
+pub const AF_INET: usize = 2;
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+const SOL_SOCKET: usize = 1;
+const SO_REUSEADDR: usize = 2;
+const SO_ERROR: usize = 4;
+const SO_RCVTIMEO: usize = 20;
+const SO_SNDTIMEO: usize = 21;
+const SOCKADDR_IN_LEN: usize = 16;
+const UNIX_PATH_MAX: usize = 108;
+const BACKLOG: usize = 128;
+const EINVAL: isize = 22;
+
+pub type Result<T> = core::result::Result<T, isize>;
+
+fn check(ret: isize) -> Result<usize> {
+    match ret {
+        ret if ret < 0 => Err(ret),
+        ret => Ok(ret as usize),
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SocketAddrV4 {
+    pub ip: [u8; 4],
+    pub port: u16,
+}
+
+impl SocketAddrV4 {
+    pub const fn new(ip: [u8; 4], port: u16) -> Self {
+        Self { ip, port }
+    }
+    //struct sockaddr_in
+    fn to_raw(self) -> [u8; SOCKADDR_IN_LEN] {
+        let mut raw = [0u8; SOCKADDR_IN_LEN];
+        raw[..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+        raw[2..4].copy_from_slice(&self.port.to_be_bytes());
+        raw[4..8].copy_from_slice(&self.ip);
+        raw
+    }
+    fn from_raw(raw: &[u8; SOCKADDR_IN_LEN]) -> Self {
+        Self {
+            ip: [raw[4], raw[5], raw[6], raw[7]],
+            port: u16::from_be_bytes([raw[2], raw[3]]),
+        }
+    }
+}
+
+impl Display for SocketAddrV4 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d] = self.ip;
+        write!(f, "{}.{}.{}.{}:{}", a, b, c, d, self.port)
+    }
+}
+
+//struct sockaddr_un of path and its length
+fn unix_addr(path: &str) -> Result<([u8; 2 + UNIX_PATH_MAX], usize)> {
+    if path.is_empty() || path.len() >= UNIX_PATH_MAX {
+        return Err(-EINVAL);
+    }
+    let mut raw = [0u8; 2 + UNIX_PATH_MAX];
+    raw[..2].copy_from_slice(&(AF_UNIX as u16).to_ne_bytes());
+    raw[2..2 + path.len()].copy_from_slice(path.as_bytes());
+    Ok((raw, 2 + path.len() + 1))
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Shutdown {
+    Read = 0,
+    Write = 1,
+    Both = 2,
+}
+
+//a socket descriptor, what every kind of socket does
+struct Socket(usize);
+
+impl Socket {
+    fn new(domain: usize, ty: usize) -> Result<Self> {
+        check(sys_socket(domain, ty, 0)).map(Socket)
+    }
+    fn set_int(&self, level: usize, name: usize, value: i32) -> Result<()> {
+        check(sys_setsockopt(self.0, level, name, &value.to_ne_bytes())).map(|_| ())
+    }
+    fn get_int(&self, level: usize, name: usize) -> Result<i32> {
+        let mut value = [0u8; 4];
+        let mut len = 0;
+        check(sys_getsockopt(self.0, level, name, &mut value, &mut len))?;
+        Ok(i32::from_ne_bytes(value))
+    }
+    //None waits forever
+    fn set_timeout(&self, name: usize, ms: Option<u64>) -> Result<()> {
+        let ms = ms.unwrap_or(0);
+        let mut timeval = [0u8; 16];
+        timeval[..8].copy_from_slice(&((ms / 1000) as i64).to_ne_bytes());
+        timeval[8..].copy_from_slice(&((ms % 1000 * 1000) as i64).to_ne_bytes());
+        check(sys_setsockopt(self.0, SOL_SOCKET, name, &timeval)).map(|_| ())
+    }
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        check(sys_read(self.0, buf))
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        check(sys_write(self.0, buf))
+    }
+    fn write_all(&self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let n = self.write(buf)?;
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+    fn shutdown(&self, how: Shutdown) -> Result<()> {
+        check(sys_socket_shutdown(self.0, how as usize)).map(|_| ())
+    }
+    fn accept(&self, addr: &mut [u8]) -> Result<Socket> {
+        let mut len = 0;
+        check(sys_accept(self.0, addr, &mut len)).map(Socket)
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        close(self.0);
+    }
+}
+
+//the methods of a connected stream
+macro_rules! stream_methods {
+    () => {
+        pub fn fd(&self) -> usize {
+            self.0 .0
+        }
+        //0 at the end of the stream
+        pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+            self.0.read(buf)
+        }
+        pub fn write(&self, buf: &[u8]) -> Result<usize> {
+            self.0.write(buf)
+        }
+        pub fn write_all(&self, buf: &[u8]) -> Result<()> {
+            self.0.write_all(buf)
+        }
+        pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+            self.0.shutdown(how)
+        }
+        //reads and writes fail with -EAGAIN once the timeout passed
+        pub fn set_read_timeout(&self, ms: Option<u64>) -> Result<()> {
+            self.0.set_timeout(SO_RCVTIMEO, ms)
+        }
+        pub fn set_write_timeout(&self, ms: Option<u64>) -> Result<()> {
+            self.0.set_timeout(SO_SNDTIMEO, ms)
+        }
+    };
+}
+
+pub struct TcpListener(Socket);
+
+impl TcpListener {
+    //port 0 takes an ephemeral one, the address can be bound again right away
+    pub fn bind(addr: SocketAddrV4) -> Result<Self> {
+        let socket = Socket::new(AF_INET, SOCK_STREAM)?;
+        socket.set_int(SOL_SOCKET, SO_REUSEADDR, 1)?;
+        check(sys_bind(socket.0, &addr.to_raw()))?;
+        check(sys_listen(socket.0, BACKLOG))?;
+        Ok(Self(socket))
+    }
+    pub fn accept(&self) -> Result<(TcpStream, SocketAddrV4)> {
+        let mut raw = [0u8; SOCKADDR_IN_LEN];
+        let socket = self.0.accept(&mut raw)?;
+        Ok((TcpStream(socket), SocketAddrV4::from_raw(&raw)))
+    }
+    pub fn fd(&self) -> usize {
+        self.0 .0
+    }
+}
+
+pub struct TcpStream(Socket);
+
+impl TcpStream {
+    pub fn connect(addr: SocketAddrV4) -> Result<Self> {
+        let socket = Socket::new(AF_INET, SOCK_STREAM)?;
+        check(sys_connect(socket.0, &addr.to_raw()))?;
+        Ok(Self(socket))
+    }
+    //the error that ended the connection, taken by asking
+    pub fn take_error(&self) -> Result<isize> {
+        self.0.get_int(SOL_SOCKET, SO_ERROR).map(|errno| -(errno as isize))
+    }
+    stream_methods!();
+}
+
+pub struct UdpSocket(Socket);
+
+impl UdpSocket {
+    pub fn bind(addr: SocketAddrV4) -> Result<Self> {
+        let socket = Socket::new(AF_INET, SOCK_DGRAM)?;
+        check(sys_bind(socket.0, &addr.to_raw()))?;
+        Ok(Self(socket))
+    }
+    //send and recv go to and come from addr only
+    pub fn connect(&self, addr: SocketAddrV4) -> Result<()> {
+        check(sys_connect(self.0 .0, &addr.to_raw())).map(|_| ())
+    }
+    pub fn send_to(&self, buf: &[u8], addr: SocketAddrV4) -> Result<usize> {
+        check(sys_sendto(self.0 .0, buf, 0, &addr.to_raw()))
+    }
+    //a datagram longer than buf is cut
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4)> {
+        let mut raw = [0u8; SOCKADDR_IN_LEN];
+        let mut len = 0;
+        let n = check(sys_recvfrom(self.0 .0, buf, 0, &mut raw, &mut len))?;
+        Ok((n, SocketAddrV4::from_raw(&raw)))
+    }
+    pub fn send(&self, buf: &[u8]) -> Result<usize> {
+        self.0.write(buf)
+    }
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        self.0.read(buf)
+    }
+    pub fn set_read_timeout(&self, ms: Option<u64>) -> Result<()> {
+        self.0.set_timeout(SO_RCVTIMEO, ms)
+    }
+    pub fn fd(&self) -> usize {
+        self.0 .0
+    }
+}
+
+//unix socket names are not files, a name is free again once its socket is closed
+pub struct UnixListener(Socket);
+
+impl UnixListener {
+    pub fn bind(path: &str) -> Result<Self> {
+        let (raw, len) = unix_addr(path)?;
+        let socket = Socket::new(AF_UNIX, SOCK_STREAM)?;
+        check(sys_bind(socket.0, &raw[..len]))?;
+        check(sys_listen(socket.0, BACKLOG))?;
+        Ok(Self(socket))
+    }
+    pub fn accept(&self) -> Result<UnixStream> {
+        let mut raw = [0u8; 2 + UNIX_PATH_MAX];
+        self.0.accept(&mut raw).map(UnixStream)
+    }
+    pub fn fd(&self) -> usize {
+        self.0 .0
+    }
+}
+
+pub struct UnixStream(Socket);
+
+impl UnixStream {
+    pub fn connect(path: &str) -> Result<Self> {
+        let (raw, len) = unix_addr(path)?;
+        let socket = Socket::new(AF_UNIX, SOCK_STREAM)?;
+        check(sys_connect(socket.0, &raw[..len]))?;
+        Ok(Self(socket))
+    }
+    stream_methods!();
+}
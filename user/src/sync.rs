@@ -0,0 +1,127 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::syscall::sys_futex;
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+const FUTEX_PRIVATE_FLAG: usize = 128;
+const ETIMEDOUT: isize = 110;
+
+//sleep while *word == val, false when timeout_ms passed. Wake ups may be spurious
+pub fn futex_wait(word: &AtomicU32, val: u32, timeout_ms: Option<u64>) -> bool {
+    let timespec = timeout_ms.map(|ms| [(ms / 1000) as usize, (ms % 1000 * 1_000_000) as usize]);
+    let timeout = timespec.as_ref().map_or(0, |timespec| timespec.as_ptr().addr());
+    sys_futex(word.as_ptr(), FUTEX_WAIT | FUTEX_PRIVATE_FLAG, val, timeout) != -ETIMEDOUT
+}
+
+//returns how many threads were woken
+pub fn futex_wake(word: &AtomicU32, n: u32) -> usize {
+    sys_futex(word.as_ptr(), FUTEX_WAKE | FUTEX_PRIVATE_FLAG, n, 0).max(0) as usize
+}
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+//locked and somebody may sleep in the kernel, unlock has to wake one up
+const CONTENDED: u32 = 2;
+
+//threads of one process only, the futex is private
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            //no system call at all while uncontended
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                futex_wait(&self.state, CONTENDED, None);
+            }
+        }
+        MutexGuard { mutex: self }
+    }
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock()
+    }
+}
+
+//a notify bumps seq, a waiter that read the old value before unlocking does not miss it
+pub struct Condvar {
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+        }
+    }
+    //unlock, sleep until notified and lock again. The caller rechecks its condition
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_timeout(guard, None).0
+    }
+    //same, the flag is false when timeout_ms passed without a notify
+    pub fn wait_timeout<'a, T>(&self, guard: MutexGuard<'a, T>, timeout_ms: Option<u64>) -> (MutexGuard<'a, T>, bool) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+        drop(guard);
+        let notified = futex_wait(&self.seq, seq, timeout_ms);
+        (mutex.lock(), notified)
+    }
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.seq, 1);
+    }
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.seq, i32::MAX as u32);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
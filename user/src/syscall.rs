@@ -4,6 +4,46 @@ const SYSCALL_SHUTDOWN: usize = 142;
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_EXIT_GROUP: usize = 94;
+const SYSCALL_FUTEX: usize = 98;
+const SYSCALL_SCHED_YIELD: usize = 124;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETPPID: usize = 173;
+const SYSCALL_GETTID: usize = 178;
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
+const SYSCALL_SWAPON: usize = 224;
+const SYSCALL_SWAPOFF: usize = 225;
+const SYSCALL_SHMGET: usize = 194;
+const SYSCALL_SHMCTL: usize = 195;
+const SYSCALL_SHMAT: usize = 196;
+const SYSCALL_SHMDT: usize = 197;
+const SYSCALL_MUNMAP: usize = 215;
+const SYSCALL_CLONE: usize = 220;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP3: usize = 24;
+const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_PIPE2: usize = 59;
+const SYSCALL_WAIT4: usize = 260;
+const SYSCALL_IOCTL: usize = 29;
+const SYSCALL_KILL: usize = 129;
+const SYSCALL_RT_SIGACTION: usize = 134;
+const SYSCALL_RT_SIGPROCMASK: usize = 135;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_SOCKET: usize = 198;
+const SYSCALL_BIND: usize = 200;
+const SYSCALL_LISTEN: usize = 201;
+const SYSCALL_ACCEPT: usize = 202;
+const SYSCALL_CONNECT: usize = 203;
+const SYSCALL_SENDTO: usize = 206;
+const SYSCALL_RECVFROM: usize = 207;
+const SYSCALL_SETSOCKOPT: usize = 208;
+const SYSCALL_GETSOCKOPT: usize = 209;
+const SYSCALL_SOCKET_SHUTDOWN: usize = 210;
+//sizeof(sigset_t)
+const SIGSET_SIZE: usize = 8;
 
 #[no_mangle]
 fn syscall(id: usize, args: [usize; 6]) -> isize {
@@ -66,11 +106,43 @@ pub fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
     )
 }
 
+//the calling thread only
 pub fn sys_exit(exit_code: isize) -> ! {
     syscall(SYSCALL_EXIT, syscall_args![exit_code as usize]);
     panic!();
 }
 
+pub fn sys_exit_group(exit_code: isize) -> ! {
+    syscall(SYSCALL_EXIT_GROUP, syscall_args![exit_code as usize]);
+    panic!();
+}
+
+//timeout points to a relative struct timespec { tv_sec, tv_nsec } or is 0
+#[inline(always)]
+pub fn sys_futex(uaddr: *const u32, op: usize, val: u32, timeout: usize) -> isize {
+    syscall(SYSCALL_FUTEX, syscall_args![uaddr.addr(), op, val as usize, timeout])
+}
+
+#[inline(always)]
+pub fn sys_sched_yield() -> isize {
+    syscall(SYSCALL_SCHED_YIELD, syscall_args![])
+}
+
+#[inline(always)]
+pub fn sys_getpid() -> isize {
+    syscall(SYSCALL_GETPID, syscall_args![])
+}
+
+#[inline(always)]
+pub fn sys_getppid() -> isize {
+    syscall(SYSCALL_GETPPID, syscall_args![])
+}
+
+#[inline(always)]
+pub fn sys_gettid() -> isize {
+    syscall(SYSCALL_GETTID, syscall_args![])
+}
+
 #[inline(always)]
 pub fn sys_shutdown() -> isize {
     syscall(
@@ -86,3 +158,191 @@ pub fn sys_reboot() -> isize {
         syscall_args![1],
     )
 }
+
+#[inline(always)]
+pub fn sys_getrlimit(resource: usize, limit: &mut [usize; 2]) -> isize {
+    syscall(
+        SYSCALL_GETRLIMIT,
+        syscall_args![resource, limit.as_mut_ptr().addr()],
+    )
+}
+
+#[inline(always)]
+pub fn sys_setrlimit(resource: usize, limit: &[usize; 2]) -> isize {
+    syscall(
+        SYSCALL_SETRLIMIT,
+        syscall_args![resource, limit.as_ptr().addr()],
+    )
+}
+
+#[inline(always)]
+pub fn sys_swapon(partition: usize) -> isize {
+    syscall(SYSCALL_SWAPON, syscall_args![partition])
+}
+
+#[inline(always)]
+pub fn sys_swapoff(partition: usize) -> isize {
+    syscall(SYSCALL_SWAPOFF, syscall_args![partition])
+}
+
+#[inline(always)]
+pub fn sys_clone(flags: usize) -> isize {
+    syscall(SYSCALL_CLONE, syscall_args![flags])
+}
+
+#[inline(always)]
+pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize) -> isize {
+    syscall(SYSCALL_MMAP, syscall_args![addr, len, prot, flags, usize::MAX, 0])
+}
+
+#[inline(always)]
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, syscall_args![addr, len])
+}
+
+#[inline(always)]
+pub fn sys_shmget(key: usize, size: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SHMGET, syscall_args![key, size, flags])
+}
+
+#[inline(always)]
+pub fn sys_shmctl(id: usize, cmd: usize) -> isize {
+    syscall(SYSCALL_SHMCTL, syscall_args![id, cmd])
+}
+
+#[inline(always)]
+pub fn sys_shmat(id: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SHMAT, syscall_args![id, 0, flags])
+}
+
+#[inline(always)]
+pub fn sys_shmdt(addr: usize) -> isize {
+    syscall(SYSCALL_SHMDT, syscall_args![addr])
+}
+
+#[inline(always)]
+pub fn sys_dup(fd: usize) -> isize {
+    syscall(SYSCALL_DUP, syscall_args![fd])
+}
+
+#[inline(always)]
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: usize) -> isize {
+    syscall(SYSCALL_DUP3, syscall_args![old_fd, new_fd, flags])
+}
+
+#[inline(always)]
+pub fn sys_close(fd: usize) -> isize {
+    syscall(SYSCALL_CLOSE, syscall_args![fd])
+}
+
+#[inline(always)]
+pub fn sys_pipe2(fds: &mut [i32; 2], flags: usize) -> isize {
+    syscall(SYSCALL_PIPE2, syscall_args![fds.as_mut_ptr().addr(), flags])
+}
+
+#[inline(always)]
+pub fn sys_wait4(pid: isize, status: &mut i32, options: usize) -> isize {
+    syscall(SYSCALL_WAIT4, syscall_args![pid as usize, (status as *mut i32).addr(), options])
+}
+
+#[inline(always)]
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_IOCTL, syscall_args![fd, cmd, arg])
+}
+
+#[inline(always)]
+pub fn sys_kill(pid: isize, signo: usize) -> isize {
+    syscall(SYSCALL_KILL, syscall_args![pid as usize, signo])
+}
+
+//act and old_act point to struct sigaction { handler, flags, restorer, mask }
+#[inline(always)]
+pub fn sys_rt_sigaction(signo: usize, act: usize, old_act: usize) -> isize {
+    syscall(SYSCALL_RT_SIGACTION, syscall_args![signo, act, old_act, SIGSET_SIZE])
+}
+
+#[inline(always)]
+pub fn sys_rt_sigprocmask(how: usize, set: usize, old_set: usize) -> isize {
+    syscall(SYSCALL_RT_SIGPROCMASK, syscall_args![how, set, old_set, SIGSET_SIZE])
+}
+
+#[inline(always)]
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    syscall(SYSCALL_SETPGID, syscall_args![pid, pgid])
+}
+
+#[inline(always)]
+pub fn sys_getpgid(pid: usize) -> isize {
+    syscall(SYSCALL_GETPGID, syscall_args![pid])
+}
+
+#[inline(always)]
+pub fn sys_socket(domain: usize, ty: usize, protocol: usize) -> isize {
+    syscall(SYSCALL_SOCKET, syscall_args![domain, ty, protocol])
+}
+
+//addr is a struct sockaddr_in or sockaddr_un
+#[inline(always)]
+pub fn sys_bind(fd: usize, addr: &[u8]) -> isize {
+    syscall(SYSCALL_BIND, syscall_args![fd, addr.as_ptr().addr(), addr.len()])
+}
+
+#[inline(always)]
+pub fn sys_listen(fd: usize, backlog: usize) -> isize {
+    syscall(SYSCALL_LISTEN, syscall_args![fd, backlog])
+}
+
+//len holds the size of addr and receives the length of the peer address
+#[inline(always)]
+pub fn sys_accept(fd: usize, addr: &mut [u8], len: &mut u32) -> isize {
+    *len = addr.len() as u32;
+    syscall(SYSCALL_ACCEPT, syscall_args![fd, addr.as_mut_ptr().addr(), (len as *mut u32).addr()])
+}
+
+#[inline(always)]
+pub fn sys_connect(fd: usize, addr: &[u8]) -> isize {
+    syscall(SYSCALL_CONNECT, syscall_args![fd, addr.as_ptr().addr(), addr.len()])
+}
+
+//an empty addr sends where the socket is connected
+#[inline(always)]
+pub fn sys_sendto(fd: usize, buffer: &[u8], flags: usize, addr: &[u8]) -> isize {
+    let addr_ptr = match addr.is_empty() {
+        true => 0,
+        false => addr.as_ptr().addr(),
+    };
+    syscall(
+        SYSCALL_SENDTO,
+        syscall_args![fd, buffer.as_ptr().addr(), buffer.len(), flags, addr_ptr, addr.len()],
+    )
+}
+
+//len holds the size of addr and receives the length of the sender address
+#[inline(always)]
+pub fn sys_recvfrom(fd: usize, buffer: &mut [u8], flags: usize, addr: &mut [u8], len: &mut u32) -> isize {
+    *len = addr.len() as u32;
+    syscall(
+        SYSCALL_RECVFROM,
+        syscall_args![fd, buffer.as_mut_ptr().addr(), buffer.len(), flags, addr.as_mut_ptr().addr(), (len as *mut u32).addr()],
+    )
+}
+
+#[inline(always)]
+pub fn sys_setsockopt(fd: usize, level: usize, name: usize, value: &[u8]) -> isize {
+    syscall(SYSCALL_SETSOCKOPT, syscall_args![fd, level, name, value.as_ptr().addr(), value.len()])
+}
+
+#[inline(always)]
+pub fn sys_getsockopt(fd: usize, level: usize, name: usize, value: &mut [u8], len: &mut u32) -> isize {
+    *len = value.len() as u32;
+    syscall(
+        SYSCALL_GETSOCKOPT,
+        syscall_args![fd, level, name, value.as_mut_ptr().addr(), (len as *mut u32).addr()],
+    )
+}
+
+//of a socket, sys_shutdown powers the machine off
+#[inline(always)]
+pub fn sys_socket_shutdown(fd: usize, how: usize) -> isize {
+    syscall(SYSCALL_SOCKET_SHUTDOWN, syscall_args![fd, how])
+}
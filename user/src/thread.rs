@@ -0,0 +1,121 @@
+use core::arch::global_asm;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::sync::futex_wait;
+use crate::syscall::sys_gettid;
+use crate::{mmap, munmap, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+
+const CLONE_VM: usize = 0x100;
+const CLONE_FS: usize = 0x200;
+const CLONE_FILES: usize = 0x400;
+const CLONE_SIGHAND: usize = 0x800;
+const CLONE_THREAD: usize = 0x10000;
+const CLONE_SYSVSEM: usize = 0x40000;
+const CLONE_SETTLS: usize = 0x80000;
+const CLONE_PARENT_SETTID: usize = 0x100000;
+const CLONE_CHILD_CLEARTID: usize = 0x200000;
+const THREAD_FLAGS: usize = CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD | CLONE_SYSVSEM
+    | CLONE_SETTLS | CLONE_PARENT_SETTID | CLONE_CHILD_CLEARTID;
+
+//stack of a thread, its Block sits at the bottom and is the TLS pointer
+pub const THREAD_SIZE: usize = 0x10000;
+
+//clone(flags, stack, parent_tid, tls, child_tid) and call entry(arg) on the new stack,
+//the child never returns from here but exits the thread with the result of entry
+global_asm!(
+    ".global __clone",
+    "__clone:",
+    "stp x5, x6, [x1, #-16]!",
+    "mov x8, #220",
+    "svc #0",
+    "cbz x0, 1f",
+    "ret",
+    "1:",
+    "ldp x1, x0, [sp], #16",
+    "blr x1",
+    "mov x8, #93",
+    "svc #0",
+);
+extern "C" {
+    fn __clone(
+        flags: usize,
+        stack: usize,
+        parent_tid: *mut u32,
+        tls: usize,
+        child_tid: *mut u32,
+        entry: extern "C" fn(usize) -> isize,
+        arg: usize,
+    ) -> isize;
+}
+
+#[repr(C)]
+struct Block {
+    //set by the kernel before clone returns, zeroed when the thread exits
+    tid: u32,
+    entry: fn(usize) -> isize,
+    arg: usize,
+    ret: isize,
+}
+
+extern "C" fn thread_start(block: usize) -> isize {
+    let block = block as *mut Block;
+    unsafe {
+        (*block).ret = ((*block).entry)((*block).arg);
+        (*block).ret
+    }
+}
+
+pub struct JoinHandle {
+    block: *mut Block,
+    mapping: &'static mut [u8],
+}
+
+//run entry(arg) in a new thread of this process
+pub fn spawn(entry: fn(usize) -> isize, arg: usize) -> Option<JoinHandle> {
+    let mapping = mmap(THREAD_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE)?;
+    let block = mapping.as_mut_ptr() as *mut Block;
+    let stack = mapping.as_ptr().addr() + THREAD_SIZE;
+    let tid = unsafe {
+        block.write(Block { tid: 0, entry, arg, ret: 0 });
+        let tid = addr_of_mut!((*block).tid);
+        __clone(THREAD_FLAGS, stack, tid, block.addr(), tid, thread_start, block.addr())
+    };
+    if tid < 0 {
+        munmap(mapping);
+        return None;
+    }
+    Some(JoinHandle { block, mapping })
+}
+
+impl JoinHandle {
+    fn tid_word(&self) -> &AtomicU32 {
+        unsafe { AtomicU32::from_ptr(addr_of!((*self.block).tid).cast_mut()) }
+    }
+    pub fn tid(&self) -> usize {
+        self.tid_word().load(Ordering::Acquire) as usize
+    }
+    //the kernel clears the tid at thread exit and wakes us, the stack goes with it
+    pub fn join(self) -> isize {
+        loop {
+            match self.tid_word().load(Ordering::Acquire) {
+                0 => break,
+                tid => futex_wait(self.tid_word(), tid, None),
+            };
+        }
+        let ret = unsafe { (*self.block).ret };
+        munmap(self.mapping);
+        ret
+    }
+}
+
+pub fn gettid() -> usize {
+    sys_gettid() as usize
+}
+
+//TPIDR_EL0, 0 in the main thread
+pub fn thread_pointer() -> usize {
+    let tp: usize;
+    unsafe { core::arch::asm!("mrs {}, tpidr_el0", out(reg) tp) };
+    tp
+}